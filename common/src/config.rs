@@ -31,6 +31,7 @@ pub struct Config {
     pub road_low_col: Color,
     pub road_mid_col: Color,
     pub road_hig_col: Color,
+    pub road_bike_col: Color,
     pub road_line_col: Color,
     pub road_pylon_col: Color,
 
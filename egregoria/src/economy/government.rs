@@ -16,8 +16,55 @@ impl Default for Government {
     }
 }
 
+/// Price of moving one cubic meter of earth, in cents
+const EARTHWORKS_PRICE: f32 = 1.0;
+
 impl Government {
-    pub fn action_cost(_action: &WorldCommand, _goria: &Egregoria) -> Money {
-        Money::new_cents(100)
+    pub fn action_cost(action: &WorldCommand, goria: &Egregoria) -> Money {
+        match *action {
+            WorldCommand::MapTerraform(kind, center, radius, amount) => {
+                let volume = goria
+                    .map()
+                    .terrain
+                    .terraform_volume(kind, center, radius, amount);
+                Money::new_cents(100 + (volume * EARTHWORKS_PRICE) as i64)
+            }
+            WorldCommand::MapAddRiver(ref path, width) => {
+                let volume = goria.map().terrain.river_volume(path, width);
+                Money::new_cents(100 + (volume * EARTHWORKS_PRICE) as i64)
+            }
+            WorldCommand::SetParkingPrice(..) => Money::default(),
+            _ => Money::new_cents(100),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Government;
+    use crate::economy::Money;
+    use crate::engine_interaction::WorldCommand;
+    use crate::map::{TerraformKind, Terrain};
+    use crate::Egregoria;
+    use geom::vec2;
+
+    #[test]
+    fn earthworks_cost_grows_with_the_volume() {
+        let goria = Egregoria::new(false);
+        goria.map_mut().terrain = Terrain::new(2, 2);
+
+        let center = vec2(512.0, 512.0);
+        let cost = |radius: f32| {
+            let cmd = WorldCommand::MapTerraform(TerraformKind::Raise, center, radius, 2.0);
+            Government::action_cost(&cmd, &goria)
+        };
+
+        let volume = goria
+            .map()
+            .terrain
+            .terraform_volume(TerraformKind::Raise, center, 100.0, 2.0);
+        assert_eq!(cost(100.0), Money::new_cents(100 + volume as i64));
+        assert!(cost(200.0) > cost(100.0));
+        assert_eq!(cost(0.0), Money::new_cents(100));
     }
 }
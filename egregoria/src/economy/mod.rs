@@ -1,15 +1,15 @@
 use crate::SoulID;
-use common::FastMap;
 use hecs::World;
 use resources::Resources;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::ops::SubAssign;
+use std::ops::{AddAssign, SubAssign};
 
 mod government;
 mod market;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 /// Money in cents, can be negative when in debt.
 pub struct Money(i64);
 
@@ -28,6 +28,20 @@ impl Display for Money {
     }
 }
 
+impl std::ops::Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
 impl std::ops::Sub for Money {
     type Output = Money;
 
@@ -63,7 +77,7 @@ pub use market::*;
 pub struct Sold(pub Vec<Trade>);
 
 #[derive(Default, Serialize, Deserialize)]
-pub struct Bought(pub FastMap<CommodityKind, Vec<Trade>>);
+pub struct Bought(pub BTreeMap<CommodityKind, Vec<Trade>>);
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Workers(pub Vec<SoulID>);
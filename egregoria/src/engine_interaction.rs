@@ -1,6 +1,7 @@
 use crate::map::{
-    BuildingGen, BuildingID, BuildingKind, IntersectionID, LaneID, LanePattern, LightPolicy, LotID,
-    Map, MapProject, RoadID, StraightRoadGen, TurnPolicy,
+    BuildingGen, BuildingID, BuildingKind, Heightmap, HeightmapScale, IntersectionID, LaneID,
+    LanePattern, LaneTurns, LightPolicy, LotID, Map, MapProject, RoadID, StraightRoadGen,
+    TerraformKind, Terrain, TurnPolicy,
 };
 use crate::Egregoria;
 use hecs::Entity;
@@ -39,20 +40,36 @@ pub enum WorldCommand {
     AddTrain(f32, u32, LaneID),
     MapMakeConnection(MapProject, MapProject, Option<Vec2>, LanePattern),
     MapUpdateIntersectionPolicy(IntersectionID, TurnPolicy, LightPolicy),
+    MapMakeGreenWave(Vec<IntersectionID>),
+    MapUpdateLaneTurns(IntersectionID, LaneID, LaneTurns),
+    MapTerraform(TerraformKind, Vec2, f32, f32),
+    MapAddRiver(PolyLine, f32),
+    MapPlantTree(Vec2),
+    MapPaintForest(Vec2, f32, f32),
+    MapClearTrees(Vec2, f32),
     MapBuildSpecialBuilding(OBB, BuildingKind, BuildingGen, Vec<StraightRoadGen>),
     MapLoadParis,
     MapLoadTestField(Vec2, u32, f32),
+    MapLoadHeightmap(Heightmap, u32, u32, HeightmapScale),
+    MapLoadOsm(OsmData, OsmBBox, bool),
+    MapGenerateCity(Vec2, CityParams),
+    MapRepairInvariants,
+    SetParkingPrice(ParkingPlace, Money),
     ResetSave,
     SetGameTime(GameTime),
     UpdateTransform(Entity, Transform),
 }
 
-use crate::economy::Government;
-use crate::map::procgen::{load_parismap, load_testfield};
-use crate::map_dynamic::BuildingInfos;
+use crate::economy::{Government, Money};
+use crate::map::procgen::{
+    gen_city, load_osm, load_parismap, load_testfield, CityParams, IndustryDescription, OsmBBox,
+    OsmData,
+};
+use crate::map_dynamic::{BuildingInfos, ParkingManagement, ParkingPlace};
+use crate::souls::goods_company::GoodsCompanyRegistry;
 use crate::utils::time::GameTime;
 use crate::vehicles::trains::{spawn_train, RailWagonKind};
-use geom::{Transform, Vec2, OBB};
+use geom::{PolyLine, Transform, Vec2, OBB};
 use WorldCommand::*;
 
 impl WorldCommands {
@@ -76,6 +93,31 @@ impl WorldCommands {
         self.commands.push(MapLoadTestField(pos, size, spacing))
     }
 
+    /// Starts a new empty world of `w` x `h` chunks on the given heightmap
+    pub fn map_load_heightmap(&mut self, hm: Heightmap, w: u32, h: u32, scale: HeightmapScale) {
+        self.commands.push(MapLoadHeightmap(hm, w, h, scale))
+    }
+
+    /// Imports the roads of parsed OpenStreetMap data, and its buildings if `buildings` is set
+    pub fn map_load_osm(&mut self, data: OsmData, bbox: OsmBBox, buildings: bool) {
+        self.commands.push(MapLoadOsm(data, bbox, buildings))
+    }
+
+    /// Generates a procedural city around `center`
+    pub fn map_generate_city(&mut self, center: Vec2, params: CityParams) {
+        self.commands.push(MapGenerateCity(center, params))
+    }
+
+    /// Fixes the broken map invariants that can be fixed
+    pub fn map_repair_invariants(&mut self) {
+        self.commands.push(MapRepairInvariants)
+    }
+
+    /// Sets the price of parking once at `place`, zero makes it free
+    pub fn set_parking_price(&mut self, place: ParkingPlace, price: Money) {
+        self.commands.push(SetParkingPrice(place, price))
+    }
+
     pub fn update_transform(&mut self, e: Entity, trans: Transform) {
         self.commands.push(UpdateTransform(e, trans))
     }
@@ -138,6 +180,35 @@ impl WorldCommands {
     ) {
         self.commands.push(MapUpdateIntersectionPolicy(id, tp, lp))
     }
+
+    pub fn map_update_lane_turns(&mut self, id: IntersectionID, lane: LaneID, turns: LaneTurns) {
+        self.commands.push(MapUpdateLaneTurns(id, lane, turns))
+    }
+
+    pub fn map_make_green_wave(&mut self, corridor: Vec<IntersectionID>) {
+        self.commands.push(MapMakeGreenWave(corridor))
+    }
+
+    pub fn map_add_river(&mut self, path: PolyLine, width: f32) {
+        self.commands.push(MapAddRiver(path, width))
+    }
+
+    pub fn map_plant_tree(&mut self, pos: Vec2) {
+        self.commands.push(MapPlantTree(pos))
+    }
+
+    pub fn map_paint_forest(&mut self, center: Vec2, radius: f32, density: f32) {
+        self.commands.push(MapPaintForest(center, radius, density))
+    }
+
+    pub fn map_clear_trees(&mut self, center: Vec2, radius: f32) {
+        self.commands.push(MapClearTrees(center, radius))
+    }
+
+    pub fn map_terraform(&mut self, kind: TerraformKind, center: Vec2, radius: f32, amount: f32) {
+        self.commands
+            .push(MapTerraform(kind, center, radius, amount))
+    }
 }
 
 impl WorldCommand {
@@ -161,10 +232,39 @@ impl WorldCommand {
             }
             MapUpdateIntersectionPolicy(id, tp, lp) => {
                 goria.map_mut().update_intersection(id, move |i| {
+                    // The green wave offset only makes sense for the lights it was made for
+                    if i.light_policy != lp {
+                        i.light_sync = None;
+                    }
                     i.light_policy = lp;
                     i.turn_policy = tp;
                 })
             }
+            MapMakeGreenWave(ref corridor) => {
+                goria.map_mut().make_green_wave(corridor);
+            }
+            MapUpdateLaneTurns(id, lane, turns) => {
+                goria.map_mut().update_intersection(id, move |i| {
+                    if turns == LaneTurns::default() {
+                        i.lane_turns.remove(&lane);
+                    } else {
+                        i.lane_turns.insert(lane, turns);
+                    }
+                })
+            }
+            MapTerraform(kind, center, radius, amount) => {
+                goria.map_mut().terraform(kind, center, radius, amount);
+            }
+            MapAddRiver(ref path, width) => {
+                goria.map_mut().add_river(path, width);
+            }
+            MapPlantTree(pos) => {
+                goria.map_mut().plant_trees(vec![pos]);
+            }
+            MapPaintForest(center, radius, density) => {
+                goria.map_mut().paint_forest(center, radius, density);
+            }
+            MapClearTrees(center, radius) => goria.map_mut().clear_trees(center, radius),
             MapBuildSpecialBuilding(obb, kind, gen, ref attachments) => {
                 if let Some(id) =
                     goria
@@ -182,6 +282,38 @@ impl WorldCommand {
             MapLoadTestField(pos, size, spacing) => {
                 load_testfield(&mut *goria.map_mut(), pos, size, spacing)
             }
+            MapLoadHeightmap(ref hm, w, h, scale) => {
+                *goria = Egregoria::new(false);
+                goria.map_mut().terrain = Terrain::from_heightmap(hm, w, h, scale);
+            }
+            MapLoadOsm(ref data, bbox, buildings) => {
+                let built = load_osm(&mut *goria.map_mut(), data, bbox, buildings);
+                let mut infos = goria.write::<BuildingInfos>();
+                for id in built {
+                    infos.insert(id);
+                }
+            }
+            MapGenerateCity(center, params) => {
+                let industries: Vec<IndustryDescription> = goria
+                    .read::<GoodsCompanyRegistry>()
+                    .descriptions
+                    .values()
+                    .map(|d| (d.bkind, d.bgen, d.size))
+                    .collect();
+                let built = gen_city(&mut *goria.map_mut(), center, params, &industries);
+                let mut infos = goria.write::<BuildingInfos>();
+                for id in built {
+                    infos.insert(id);
+                }
+            }
+            MapRepairInvariants => {
+                let mut map = goria.map_mut();
+                let violations = map.invariant_violations();
+                map.repair_invariants(&violations);
+            }
+            SetParkingPrice(place, price) => {
+                goria.write::<ParkingManagement>().set_price(place, price)
+            }
             ResetSave => {
                 *goria = Egregoria::new(true);
             }
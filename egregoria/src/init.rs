@@ -1,13 +1,16 @@
 use crate::economy::{market_update, Government, Market};
 use crate::map::Map;
 use crate::map_dynamic::{
-    itinerary_update, routing_changed_system, routing_update_system, BuildingInfos,
-    ParkingManagement,
+    congestion_update, itinerary_update, landmarks_update, parking_occupancy_update,
+    routing_changed_system, routing_update_system, traffic_signals_update, traffic_stats_update,
+    BuildingInfos, CongestionTracker, LandmarksRebuild, ModalSplit, ParkingManagement,
+    ParkingOccupancy, TrafficSignals, TrafficStats, TripLog,
 };
 use crate::pedestrians::pedestrian_decision_system;
 use crate::physics::systems::coworld_synchronize;
 use crate::souls::goods_company::{company_system, GoodsCompanyRegistry};
 use crate::souls::human::update_decision_system;
+use crate::vehicles::gridlock::{gridlock_update, GridlockDetector};
 use crate::vehicles::systems::{vehicle_decision_system, vehicle_state_update_system};
 use crate::vehicles::trains::{
     locomotive_random_movement_system, locomotive_system, train_reservations_update,
@@ -24,6 +27,7 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 pub fn init() {
+    register_system("landmarks_update", landmarks_update);
     register_system("update_decision_system", update_decision_system);
     register_system("company_system", company_system);
     register_system("pedestrian_decision_system", pedestrian_decision_system);
@@ -33,11 +37,16 @@ pub fn init() {
         "locomotive_random_movement_system",
         locomotive_random_movement_system,
     );
+    register_system("traffic_signals_update", traffic_signals_update);
     register_system("vehicle_decision_system", vehicle_decision_system);
     register_system("vehicle_state_update_system", vehicle_state_update_system);
+    register_system("gridlock_update", gridlock_update);
     register_system("routing_changed_system", routing_changed_system);
     register_system("routing_update_system", routing_update_system);
     register_system("itinerary_update", itinerary_update);
+    register_system("congestion_update", congestion_update);
+    register_system("parking_occupancy_update", parking_occupancy_update);
+    register_system("traffic_stats_update", traffic_stats_update);
     register_system("market_update", market_update);
     register_system("train_reservations_update", train_reservations_update);
 
@@ -49,7 +58,15 @@ pub fn init() {
     register_resource("government", Government::default);
     register_resource("market", Market::default);
     register_resource("pmanagement", ParkingManagement::default);
+    register_resource("parking_occupancy", ParkingOccupancy::default);
+    register_resource("modal_split", ModalSplit::default);
+    register_resource("traffic_stats", TrafficStats::default);
+    register_resource("trip_log", TripLog::default);
     register_resource("binfos", BuildingInfos::default);
+    register_resource("traffic_signals", TrafficSignals::default);
+    register_resource("congestion_tracker", CongestionTracker::default);
+    register_resource("gridlock_detector", GridlockDetector::default);
+    register_resource("landmarks_rebuild", LandmarksRebuild::default);
     register_resource("game_time", || {
         GameTime::new(0.0, SECONDS_PER_DAY as f64 + 10.0 * SECONDS_PER_HOUR as f64)
     });
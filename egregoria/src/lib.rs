@@ -50,7 +50,6 @@ pub mod vehicles;
 use crate::init::{GSYSTEMS, INIT_FUNCS, SAVELOAD_FUNCS};
 use crate::utils::scheduler::RunnableSystem;
 use crate::vehicles::trains::RailWagon;
-use common::FastMap;
 use serde::de::Error;
 pub use utils::par_command_buffer::ParCommandBuffer;
 
@@ -291,7 +290,7 @@ impl Serialize for Egregoria {
     {
         log::info!("serializing egregoria");
         let t = Instant::now();
-        let mut m: FastMap<String, Vec<u8>> = FastMap::default();
+        let mut m: BTreeMap<String, Vec<u8>> = BTreeMap::new();
 
         unsafe {
             for l in &SAVELOAD_FUNCS {
@@ -318,7 +317,7 @@ impl Serialize for Egregoria {
 struct EgregoriaSer<'a> {
     world: SerWorld<'a>,
     version: String,
-    res: FastMap<String, Vec<u8>>,
+    res: BTreeMap<String, Vec<u8>>,
     tick: u32,
 }
 
@@ -326,7 +325,7 @@ struct EgregoriaSer<'a> {
 struct EgregoriaDeser {
     world: DeserWorld,
     version: String,
-    res: FastMap<String, Vec<u8>>,
+    res: BTreeMap<String, Vec<u8>>,
     tick: u32,
 }
 
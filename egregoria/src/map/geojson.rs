@@ -0,0 +1,237 @@
+use crate::map::{
+    BuildingID, BuildingKind, IntersectionID, LaneKind, LightPolicy, LotID, LotKind, Map, RoadID,
+    TurnPolicy,
+};
+use common::saveload::{Encoder, JSON};
+use geom::{Vec2, OBB};
+use serde::Serialize;
+use std::io::Write;
+
+/// GeoJSON export of the map, meant to be analyzed in GIS tools.
+/// Coordinates are in meters in the game's plane (x east, y north, z up),
+/// not in WGS84, so the layer must be opened in a local projected CRS.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub struct GeoJsonMap {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "Feature")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f32; 3]),
+    LineString(Vec<[f32; 3]>),
+    Polygon(Vec<Vec<[f32; 2]>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "layer", rename_all = "snake_case")]
+pub enum Properties {
+    Road {
+        id: RoadID,
+        src: IntersectionID,
+        dst: IntersectionID,
+        lanes_forward: usize,
+        lanes_backward: usize,
+        rail: bool,
+        sidewalks: usize,
+        /// In m/s
+        speed_limit: f32,
+        one_way: bool,
+        width: f32,
+    },
+    Intersection {
+        id: IntersectionID,
+        turn_policy: TurnPolicy,
+        light_policy: LightPolicy,
+    },
+    Lot {
+        id: LotID,
+        parent: RoadID,
+        kind: LotKind,
+    },
+    Building {
+        id: BuildingID,
+        kind: BuildingKind,
+        height: f32,
+    },
+}
+
+impl GeoJsonMap {
+    pub fn new(map: &Map) -> Self {
+        let mut features = vec![];
+
+        for (id, road) in map.roads() {
+            let (mut fwd, mut bwd, mut rail, mut sidewalks) = (0, 0, false, 0);
+            let mut speed_limit: f32 = 0.0;
+            for (lane_id, kind) in road.lanes_iter() {
+                let lane = unwrap_cont!(map.lanes().get(lane_id));
+                match kind {
+                    LaneKind::Walking => sidewalks += 1,
+                    // Bike and bus lanes aren't counted as lanes of the road
+                    LaneKind::Driving | LaneKind::Rail => {
+                        rail |= kind.is_rail();
+                        speed_limit = speed_limit.max(lane.speed_limit);
+                        if lane.src == road.src {
+                            fwd += 1;
+                        } else {
+                            bwd += 1;
+                        }
+                    }
+                    LaneKind::Biking | LaneKind::Bus | LaneKind::Parking => {}
+                }
+            }
+
+            features.push(Feature {
+                geometry: Geometry::LineString(
+                    road.points.iter().map(|p| [p.x, p.y, p.z]).collect(),
+                ),
+                properties: Properties::Road {
+                    id,
+                    src: road.src,
+                    dst: road.dst,
+                    lanes_forward: fwd,
+                    lanes_backward: bwd,
+                    rail,
+                    sidewalks,
+                    speed_limit,
+                    // Roads without any vehicle lanes, like footpaths, aren't one way
+                    one_way: (fwd == 0) != (bwd == 0),
+                    width: road.width,
+                },
+            });
+        }
+
+        for (id, inter) in map.intersections() {
+            features.push(Feature {
+                geometry: Geometry::Point([inter.pos.x, inter.pos.y, inter.pos.z]),
+                properties: Properties::Intersection {
+                    id,
+                    turn_policy: inter.turn_policy,
+                    light_policy: inter.light_policy,
+                },
+            });
+        }
+
+        for (id, lot) in map.lots() {
+            features.push(Feature {
+                geometry: obb_polygon(&lot.shape),
+                properties: Properties::Lot {
+                    id,
+                    parent: lot.parent,
+                    kind: lot.kind,
+                },
+            });
+        }
+
+        for (id, building) in map.buildings() {
+            features.push(Feature {
+                geometry: obb_polygon(&building.obb),
+                properties: Properties::Building {
+                    id,
+                    kind: building.kind,
+                    height: building.height,
+                },
+            });
+        }
+
+        Self { features }
+    }
+
+    pub fn write(&self, w: impl Write) -> std::io::Result<()> {
+        JSON::encode_writer(self, w)
+    }
+}
+
+/// Closed counter-clockwise ring, as the GeoJSON spec recommends for exterior rings
+fn obb_polygon(obb: &OBB) -> Geometry {
+    let mut ring: Vec<Vec2> = obb.corners.to_vec();
+    let [a, b] = obb.axis();
+    if a.cross(b) < 0.0 {
+        ring.reverse();
+    }
+    ring.push(ring[0]);
+    Geometry::Polygon(vec![ring.into_iter().map(|p| [p.x, p.y]).collect()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GeoJsonMap, Properties};
+    use crate::map::procgen::load_testfield;
+    use crate::map::{LanePatternBuilder, Map, RoadID, RoadSegmentKind};
+    use geom::{vec3, Vec2};
+
+    #[test]
+    fn testfield_export() {
+        let mut m = Map::empty();
+        load_testfield(&mut m, Vec2::ZERO, 3, 100.0);
+        let grid: Vec<RoadID> = m.roads().keys().collect();
+
+        let a = m.add_intersection(vec3(500.0, 0.0, 0.3));
+        let b = m.add_intersection(vec3(700.0, 0.0, 0.3));
+        let c = m.add_intersection(vec3(700.0, 200.0, 0.3));
+        let one_way = m
+            .connect(
+                a,
+                b,
+                &LanePatternBuilder::new().one_way(true).n_lanes(2).build(),
+                RoadSegmentKind::Straight,
+            )
+            .unwrap();
+        let footpath = m
+            .connect(
+                b,
+                c,
+                &LanePatternBuilder::new().n_lanes(0).build(),
+                RoadSegmentKind::Straight,
+            )
+            .unwrap();
+
+        let geo = GeoJsonMap::new(&m);
+        assert_eq!(
+            geo.features.len(),
+            m.roads().len() + m.intersections().len() + m.lots().len() + m.buildings().len()
+        );
+
+        let mut n_roads = 0;
+        for f in &geo.features {
+            if let Properties::Road {
+                id,
+                lanes_forward,
+                lanes_backward,
+                rail,
+                sidewalks,
+                one_way: is_one_way,
+                ..
+            } = f.properties
+            {
+                n_roads += 1;
+                let expected = if grid.contains(&id) {
+                    (1, 1, false)
+                } else if id == one_way {
+                    (2, 0, true)
+                } else {
+                    assert_eq!(id, footpath);
+                    (0, 0, false)
+                };
+                assert_eq!((lanes_forward, lanes_backward, is_one_way), expected);
+                assert!(!rail);
+                assert_eq!(sidewalks, 2);
+            }
+        }
+        assert_eq!(n_roads, 14);
+
+        let mut out = vec![];
+        geo.write(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"FeatureCollection\""));
+        assert!(json.contains("\"layer\": \"road\""));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::map::{Chunk, Terrain, CHUNK_RESOLUTION};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Formatter};
+use std::num::Wrapping;
+
+/// 16-bit grayscale heightmap, row-major.
+/// The first row is the north of the map (highest y), like an image seen from above.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u16>,
+}
+
+/// How the gray levels of a heightmap map to heights in meters
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeightmapScale {
+    /// Height of the black pixels
+    pub base: f32,
+    /// Height difference between the white and black pixels
+    pub vertical: f32,
+}
+
+impl Default for HeightmapScale {
+    fn default() -> Self {
+        Self {
+            base: -500.0,
+            vertical: 1000.0,
+        }
+    }
+}
+
+impl HeightmapScale {
+    /// Path of the text file next to the heightmap where its scale is saved,
+    /// as neither png nor raw files can hold it
+    pub fn sidecar_path(heightmap_path: &str) -> String {
+        format!("{}.scale", heightmap_path)
+    }
+
+    /// Contents of the sidecar file, one `key value` per line
+    pub fn to_sidecar(&self) -> String {
+        format!("base {}\nvertical {}\n", self.base, self.vertical)
+    }
+
+    pub fn from_sidecar(s: &str) -> Option<Self> {
+        let mut base = None;
+        let mut vertical = None;
+        for line in s.lines() {
+            let mut it = line.split_whitespace();
+            let (key, value) = (it.next(), it.next().and_then(|v| v.parse::<f32>().ok()));
+            match key {
+                Some("base") => base = value,
+                Some("vertical") => vertical = value,
+                _ => {}
+            }
+        }
+        Some(Self {
+            base: base?,
+            vertical: vertical?,
+        })
+    }
+}
+
+impl Heightmap {
+    /// Reads headerless little-endian 16-bit samples (.r16/.raw)
+    pub fn from_raw16(bytes: &[u8], width: u32, height: u32) -> Option<Self> {
+        if bytes.len() != width as usize * height as usize * 2 {
+            return None;
+        }
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Some(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn to_raw16(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Raw files have no header, so guess the dimensions from the file length
+    /// assuming the image has the same aspect ratio as the map.
+    pub fn raw16_dims(n_bytes: usize, w_chunks: u32, h_chunks: u32) -> Option<(u32, u32)> {
+        if n_bytes % 2 != 0 || w_chunks == 0 || h_chunks == 0 {
+            return None;
+        }
+        let n = (n_bytes / 2) as f64;
+        let w = (n * w_chunks as f64 / h_chunks as f64).sqrt().round() as u32;
+        if w == 0 || (n_bytes / 2) % w as usize != 0 {
+            return None;
+        }
+        Some((w, (n_bytes / 2 / w as usize) as u32))
+    }
+
+    fn get(&self, x: u32, y: u32) -> f32 {
+        let x = x.min(self.width.saturating_sub(1));
+        let y = y.min(self.height.saturating_sub(1));
+        self.data
+            .get((y * self.width + x) as usize)
+            .copied()
+            .unwrap_or(0) as f32
+            / u16::MAX as f32
+    }
+
+    /// Bilinear sample in 0..1, u and v are in 0..1 from the south-west corner
+    fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * self.width.saturating_sub(1) as f32;
+        let y = (1.0 - v.clamp(0.0, 1.0)) * self.height.saturating_sub(1) as f32;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as u32, y0 as u32);
+
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bot = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bot * fy
+    }
+}
+
+impl Debug for Heightmap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Heightmap({}x{})", self.width, self.height)
+    }
+}
+
+impl Terrain {
+    /// Creates a terrain of `w` x `h` chunks with the heightmap stretched over it
+    pub fn from_heightmap(hm: &Heightmap, w: u32, h: u32, scale: HeightmapScale) -> Self {
+        let mut t = Terrain {
+            width: w,
+            height: h,
+            ..Terrain::default()
+        };
+
+        let cells_w = (w as usize * CHUNK_RESOLUTION).saturating_sub(1).max(1) as f32;
+        let cells_h = (h as usize * CHUNK_RESOLUTION).saturating_sub(1).max(1) as f32;
+
+        for cy in 0..h {
+            for cx in 0..w {
+                let mut chunk = Chunk::default();
+                for (y, l) in chunk.heights.iter_mut().enumerate() {
+                    for (x, height) in l.iter_mut().enumerate() {
+                        let u = (cx as usize * CHUNK_RESOLUTION + x) as f32 / cells_w;
+                        let v = (cy as usize * CHUNK_RESOLUTION + y) as f32 / cells_h;
+                        *height = scale.base + hm.sample(u, v) * scale.vertical;
+                    }
+                }
+                chunk.trees = Terrain::generate_trees((cx, cy), &chunk.heights);
+                t.chunks.insert((cx, cy), chunk);
+            }
+        }
+        t.dirt_id += Wrapping(1);
+        t
+    }
+
+    /// One pixel per cell. The scale is chosen to use the full 16-bit range.
+    pub fn to_heightmap(&self) -> (Heightmap, HeightmapScale) {
+        let heights = || {
+            self.chunks
+                .values()
+                .flat_map(|c| c.heights.iter().flatten())
+        };
+        let min = heights().copied().fold(f32::INFINITY, f32::min);
+        let max = heights().copied().fold(f32::NEG_INFINITY, f32::max);
+        let scale = if min.is_finite() && max > min {
+            HeightmapScale {
+                base: min,
+                vertical: max - min,
+            }
+        } else {
+            HeightmapScale {
+                base: if min.is_finite() { min } else { 0.0 },
+                vertical: 1.0,
+            }
+        };
+
+        let width = self.width * CHUNK_RESOLUTION as u32;
+        let height = self.height * CHUNK_RESOLUTION as u32;
+        let mut data = Vec::with_capacity(width as usize * height as usize);
+
+        for py in 0..height {
+            // first row is the north
+            let gy = height - 1 - py;
+            for gx in 0..width {
+                let r = CHUNK_RESOLUTION as u32;
+                let h = self
+                    .chunks
+                    .get(&(gx / r, gy / r))
+                    .and_then(|c| c.heights.get((gy % r) as usize))
+                    .and_then(|l| l.get((gx % r) as usize))
+                    .copied()
+                    .unwrap_or(scale.base);
+                let v = (h - scale.base) / scale.vertical;
+                data.push((v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16);
+            }
+        }
+
+        (
+            Heightmap {
+                width,
+                height,
+                data,
+            },
+            scale,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heightmap, HeightmapScale};
+    use crate::map::Terrain;
+
+    fn max_height_error(a: &Terrain, b: &Terrain) -> f32 {
+        assert_eq!(a.chunks.len(), b.chunks.len());
+        a.chunks
+            .iter()
+            .flat_map(|(id, ca)| {
+                let cb = &b.chunks[id];
+                ca.heights
+                    .iter()
+                    .flatten()
+                    .zip(cb.heights.iter().flatten())
+                    .map(|(ha, hb)| (ha - hb).abs())
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn heightmap_round_trip() {
+        let t = Terrain::new(2, 2);
+        let (hm, scale) = t.to_heightmap();
+        assert!(scale.vertical > 0.0);
+
+        let back = Terrain::from_heightmap(&hm, 2, 2, scale);
+        let quantum = scale.vertical / u16::MAX as f32;
+        assert!(max_height_error(&t, &back) <= quantum);
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn raw16_round_trip() {
+        let t = Terrain::new(2, 1);
+        let (hm, scale) = t.to_heightmap();
+        let bytes = hm.to_raw16();
+
+        assert_eq!(
+            Heightmap::raw16_dims(bytes.len(), 2, 1),
+            Some((hm.width, hm.height))
+        );
+        assert!(Heightmap::from_raw16(&bytes[1..], hm.width, hm.height).is_none());
+
+        let read = Heightmap::from_raw16(&bytes, hm.width, hm.height).unwrap();
+        assert_eq!(read.data, hm.data);
+
+        let back = Terrain::from_heightmap(&read, 2, 1, scale);
+        assert!(max_height_error(&t, &back) <= scale.vertical / u16::MAX as f32);
+    }
+
+    #[test]
+    fn scale_sidecar() {
+        assert_eq!(
+            HeightmapScale::sidecar_path("maps/alps.png"),
+            "maps/alps.png.scale"
+        );
+
+        let scale = HeightmapScale {
+            base: -123.5,
+            vertical: 842.25,
+        };
+        assert_eq!(
+            HeightmapScale::from_sidecar(&scale.to_sidecar()),
+            Some(scale)
+        );
+        assert_eq!(
+            HeightmapScale::from_sidecar("vertical 10\n\nbase 2\n"),
+            Some(HeightmapScale {
+                base: 2.0,
+                vertical: 10.0
+            })
+        );
+        assert_eq!(HeightmapScale::from_sidecar("base 2\n"), None);
+        assert_eq!(HeightmapScale::from_sidecar("base x\nvertical 1\n"), None);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::map::{
+    BuildingID, IntersectionID, LaneID, LaneKind, LotID, Map, ProjectKind, RoadID, TurnID,
+};
+use geom::pseudo_angle;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::num::Wrapping;
+
+/// A broken map invariant, as found by [`Map::invariant_violations`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InvariantViolation {
+    IntersectionWithoutRoads(IntersectionID),
+    UnsortedIntersectionRoads(IntersectionID),
+    IntersectionDeadRoad(IntersectionID, RoadID),
+    NonFiniteIntersection(IntersectionID),
+    InvalidTurn(TurnID),
+    /// The object is not in the spatial map
+    MissingSpatialEntry(ProjectKind),
+    /// The spatial map contains an object that does not exist
+    DeadSpatialEntry(ProjectKind),
+    EmptyLane(LaneID),
+    DanglingLane(LaneID),
+    ParkingLaneWithoutSpots(LaneID),
+    RoadDeadIntersection(RoadID),
+    RoadNotInIntersection(RoadID, IntersectionID),
+    RoadWithoutLanes(RoadID),
+    RoadEndpointsMismatch(RoadID),
+    DegenerateRoad(RoadID),
+    RoadDeadLane(RoadID, LaneID),
+    ParkingWithoutDriving(RoadID),
+    DegenerateLot(LotID),
+    LotDeadParent(LotID),
+    BuildingDeadAttachment(BuildingID, RoadID),
+    ParkingReuseNotEmpty,
+}
+
+/// Object changed by a repair, so that an object fixed for several violations counts once
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum RepairedObject {
+    Object(ProjectKind),
+    Lane(LaneID),
+    ParkingReuse,
+}
+
+impl InvariantViolation {
+    /// Whether [`Map::repair_invariants`] knows how to fix it
+    pub fn is_fixable(&self) -> bool {
+        use InvariantViolation::*;
+        !matches!(
+            self,
+            NonFiniteIntersection(_)
+                | EmptyLane(_)
+                | DegenerateRoad(_)
+                | RoadDeadLane(..)
+                | ParkingWithoutDriving(_)
+        )
+    }
+}
+
+impl Map {
+    /// Checks all the invariants of the map and returns the broken ones
+    pub fn invariant_violations(&self) -> Vec<InvariantViolation> {
+        use InvariantViolation::*;
+        let mut v = vec![];
+
+        for inter in self.intersections.values() {
+            if inter.roads.is_empty() {
+                v.push(IntersectionWithoutRoads(inter.id));
+            }
+
+            let mut last_angle = -f32::INFINITY;
+            for &road in &inter.roads {
+                let road = unwrap_or!(self.roads.get(road), {
+                    v.push(IntersectionDeadRoad(inter.id, road));
+                    continue;
+                });
+                let ang = pseudo_angle(road.dir_from(inter.id));
+                if ang <= last_angle {
+                    v.push(UnsortedIntersectionRoads(inter.id));
+                }
+                last_angle = ang;
+            }
+
+            for turn in inter.turns() {
+                if turn.id.parent != inter.id
+                    || !self.lanes.contains_key(turn.id.src)
+                    || !self.lanes.contains_key(turn.id.dst)
+                    || turn.points.n_points() < 2
+                {
+                    v.push(InvalidTurn(turn.id));
+                }
+            }
+
+            if !inter.pos.is_finite() {
+                v.push(NonFiniteIntersection(inter.id));
+            }
+            if !self.spatial_map.contains(inter.id) {
+                v.push(MissingSpatialEntry(ProjectKind::Inter(inter.id)));
+            }
+        }
+
+        for lane in self.lanes.values() {
+            if lane.points.is_empty() {
+                v.push(EmptyLane(lane.id));
+            }
+            if !self.intersections.contains_key(lane.src)
+                || !self.intersections.contains_key(lane.dst)
+                || !self.roads.contains_key(lane.parent)
+            {
+                v.push(DanglingLane(lane.id));
+            }
+            if matches!(lane.kind, LaneKind::Parking) && self.parking.spots(lane.id).is_none() {
+                v.push(ParkingLaneWithoutSpots(lane.id));
+            }
+        }
+
+        for road in self.roads.values() {
+            let src = self.intersections.get(road.src);
+            let dst = self.intersections.get(road.dst);
+            let (src, dst) = match (src, dst) {
+                (Some(src), Some(dst)) => (src, dst),
+                _ => {
+                    v.push(RoadDeadIntersection(road.id));
+                    continue;
+                }
+            };
+
+            for inter in [src, dst] {
+                if !inter.roads.contains(&road.id) {
+                    v.push(RoadNotInIntersection(road.id, inter.id));
+                }
+            }
+
+            if road.lanes_iter().next().is_none() {
+                v.push(RoadWithoutLanes(road.id));
+            }
+            if road.points.is_empty()
+                || !road.points.first().is_close(src.pos, 0.001)
+                || !road.points.last().is_close(dst.pos, 0.001)
+            {
+                v.push(RoadEndpointsMismatch(road.id));
+            } else if road.interfaced_points().n_points() < 2 || road.length() <= 0.0 {
+                v.push(DegenerateRoad(road.id));
+            }
+            if !self.spatial_map.contains(road.id) {
+                v.push(MissingSpatialEntry(ProjectKind::Road(road.id)));
+            }
+
+            for (id, _) in road.lanes_iter() {
+                if self.lanes.get(id).map_or(true, |l| l.parent != road.id) {
+                    v.push(RoadDeadLane(road.id, id));
+                }
+            }
+
+            // Road with parking lane has driving lane, in both directions
+            for lanes in [
+                road.incoming_lanes_to(road.src),
+                road.outgoing_lanes_from(road.src),
+            ] {
+                let has_parking = lanes
+                    .iter()
+                    .any(|(_, kind)| matches!(kind, LaneKind::Parking));
+                let has_driving = lanes
+                    .iter()
+                    .any(|(_, kind)| matches!(kind, LaneKind::Driving));
+                if has_parking && !has_driving {
+                    v.push(ParkingWithoutDriving(road.id));
+                }
+            }
+        }
+
+        for lot in self.lots.values() {
+            if !lot.shape.axis().iter().all(|x| x.magnitude() > 0.0) {
+                v.push(DegenerateLot(lot.id));
+            }
+            if !self.roads.contains_key(lot.parent) {
+                v.push(LotDeadParent(lot.id));
+            }
+            if !self.spatial_map.contains(lot.id) {
+                v.push(MissingSpatialEntry(ProjectKind::Lot(lot.id)));
+            }
+        }
+
+        for building in self.buildings.values() {
+            for &road in &building.attachments {
+                if !self.roads.contains_key(road) {
+                    v.push(BuildingDeadAttachment(building.id, road));
+                }
+            }
+            if !self.spatial_map.contains(building.id) {
+                v.push(MissingSpatialEntry(ProjectKind::Building(building.id)));
+            }
+        }
+
+        for obj in self.spatial_map.objects() {
+            if !obj.check_valid(self) {
+                v.push(DeadSpatialEntry(*obj));
+            }
+        }
+
+        if !self.parking.reuse_spot.is_empty() {
+            v.push(ParkingReuseNotEmpty);
+        }
+
+        v
+    }
+
+    /// Fixes the violations that can be fixed and returns the number of distinct objects
+    /// that were changed, the other violations are left untouched.
+    pub fn repair_invariants(&mut self, violations: &[InvariantViolation]) -> usize {
+        use InvariantViolation::*;
+        info!("repair_invariants {:?}", violations);
+        self.dirt_id += Wrapping(1);
+
+        let mut repaired = BTreeSet::new();
+        for violation in violations {
+            let object = match *violation {
+                IntersectionWithoutRoads(id) => {
+                    self.remove_intersection_inner(id);
+                    RepairedObject::Object(ProjectKind::Inter(id))
+                }
+                UnsortedIntersectionRoads(id) => {
+                    let roads = &self.roads;
+                    let inter = unwrap_cont!(self.intersections.get_mut(id));
+                    inter.roads.retain(|&r| roads.contains_key(r));
+                    inter.roads.sort_by_key(|&r| {
+                        #[allow(clippy::indexing_slicing)]
+                        OrderedFloat(pseudo_angle(roads[r].dir_from(id)))
+                    });
+                    self.invalidate(id);
+                    RepairedObject::Object(ProjectKind::Inter(id))
+                }
+                IntersectionDeadRoad(id, road) => {
+                    unwrap_cont!(self.intersections.get_mut(id)).remove_road(road);
+                    self.invalidate(id);
+                    RepairedObject::Object(ProjectKind::Inter(id))
+                }
+                InvalidTurn(id) => {
+                    self.invalidate(id.parent);
+                    RepairedObject::Object(ProjectKind::Inter(id.parent))
+                }
+                MissingSpatialEntry(kind) => {
+                    match kind {
+                        ProjectKind::Inter(id) => {
+                            let inter = unwrap_cont!(self.intersections.get(id));
+                            self.spatial_map.insert(id, inter.bcircle(&self.roads));
+                        }
+                        ProjectKind::Road(id) => {
+                            let road = unwrap_cont!(self.roads.get(id));
+                            self.spatial_map.insert(id, road.boldline());
+                        }
+                        ProjectKind::Lot(id) => {
+                            let lot = unwrap_cont!(self.lots.get(id));
+                            self.spatial_map.insert(id, lot.shape);
+                        }
+                        ProjectKind::Building(id) => {
+                            let building = unwrap_cont!(self.buildings.get(id));
+                            self.spatial_map.insert(id, building.obb);
+                        }
+                        ProjectKind::Ground => continue,
+                    }
+                    RepairedObject::Object(kind)
+                }
+                DeadSpatialEntry(kind) => {
+                    self.spatial_map.remove(kind);
+                    RepairedObject::Object(kind)
+                }
+                DanglingLane(id) => {
+                    self.lanes.remove(id);
+                    self.parking.remove_spots(id);
+                    RepairedObject::Lane(id)
+                }
+                ParkingLaneWithoutSpots(id) => {
+                    let lane = unwrap_cont!(self.lanes.get(id));
+                    self.parking.generate_spots(lane);
+                    RepairedObject::Lane(id)
+                }
+                RoadDeadIntersection(id) | RoadWithoutLanes(id) => {
+                    if self.remove_road_inner(id).is_none() {
+                        continue;
+                    }
+                    RepairedObject::Object(ProjectKind::Road(id))
+                }
+                RoadNotInIntersection(road, id) => {
+                    let r = unwrap_cont!(self.roads.get(road));
+                    unwrap_cont!(self.intersections.get_mut(id)).add_road(&self.roads, r);
+                    self.invalidate(id);
+                    RepairedObject::Object(ProjectKind::Inter(id))
+                }
+                RoadEndpointsMismatch(id) => {
+                    let road = unwrap_cont!(self.roads.get(id));
+                    let (src, dst) = (road.src, road.dst);
+                    let src_inter = unwrap_cont!(self.intersections.get(src));
+                    let dst_inter = unwrap_cont!(self.intersections.get(dst));
+                    #[allow(clippy::indexing_slicing)] // borrowed before
+                    self.roads[id].update_points(src_inter, dst_inter);
+                    self.invalidate(src);
+                    self.invalidate(dst);
+                    RepairedObject::Object(ProjectKind::Road(id))
+                }
+                DegenerateLot(id) | LotDeadParent(id) => {
+                    if self.lots.remove(id).is_none() {
+                        continue;
+                    }
+                    self.spatial_map.remove(id);
+                    RepairedObject::Object(ProjectKind::Lot(id))
+                }
+                BuildingDeadAttachment(id, road) => {
+                    unwrap_cont!(self.buildings.get_mut(id))
+                        .attachments
+                        .retain(|&r| r != road);
+                    RepairedObject::Object(ProjectKind::Building(id))
+                }
+                ParkingReuseNotEmpty => {
+                    self.parking.clean_reuse();
+                    RepairedObject::ParkingReuse
+                }
+                NonFiniteIntersection(_)
+                | EmptyLane(_)
+                | DegenerateRoad(_)
+                | RoadDeadLane(..)
+                | ParkingWithoutDriving(_) => continue,
+            };
+            repaired.insert(object);
+        }
+
+        repaired.len()
+    }
+
+    pub fn check_invariants(&self) {
+        if std::env::var("MAP_INVARIANT_CHECK").is_err() {
+            return;
+        }
+        let violations = self.invariant_violations();
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvariantViolation::*;
+    use crate::map::procgen::load_testfield;
+    use crate::map::{Map, ProjectKind};
+    use geom::Vec2;
+
+    #[test]
+    fn repair_fixable_violations() {
+        let mut m = Map::empty();
+        load_testfield(&mut m, Vec2::ZERO, 3, 100.0);
+
+        let road = m.roads.keys().next().unwrap();
+        let lot = m.lots.keys().next().unwrap();
+        m.spatial_map.remove(road);
+        m.lots.get_mut(lot).unwrap().parent = Default::default();
+
+        let violations = m.invariant_violations();
+        assert!(violations.contains(&MissingSpatialEntry(ProjectKind::Road(road))));
+        assert!(violations.contains(&LotDeadParent(lot)));
+        assert!(violations.iter().all(|v| v.is_fixable()));
+
+        assert_eq!(m.repair_invariants(&violations), violations.len());
+        assert_eq!(m.invariant_violations(), vec![]);
+
+        // An object repaired for several violations is counted once
+        let (id, turn) = m
+            .intersections
+            .iter()
+            .find_map(|(id, i)| Some((id, i.turns().next()?.id)))
+            .unwrap();
+        let same_inter = [UnsortedIntersectionRoads(id), InvalidTurn(turn)];
+        assert_eq!(m.repair_invariants(&same_inter), 1);
+        assert_eq!(m.invariant_violations(), vec![]);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::map::{IntersectionID, LaneKind, Map};
+use ordered_float::OrderedFloat;
+use slotmap::SecondaryMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Number of landmarks per graph
+const N_LANDMARKS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LandmarkGraph {
+    /// Directed graph of vehicle and rail lanes, costs are in seconds at the speed limit
+    Vehicle,
+    /// Undirected graph of walking lanes, costs are in meters
+    Pedestrian,
+}
+
+type Adjacency = SecondaryMap<IntersectionID, Vec<(IntersectionID, f32)>>;
+
+/// Precomputed shortest distances from and to a few landmark intersections (ALT).
+/// By the triangle inequality they give a lower bound of the distance between any two
+/// intersections, which is a much tighter A* heuristic than the euclidean distance.
+#[derive(Default)]
+pub struct Landmarks {
+    dirt_id: u32,
+    /// For each intersection: d(landmark, inter), d(inter, landmark) for each landmark
+    dists: SecondaryMap<IntersectionID, Vec<f32>>,
+}
+
+/// Snapshot of a pathfinding graph taken from the map, so the landmarks can be
+/// computed away from it
+pub struct LandmarksInput {
+    dirt_id: u32,
+    forward: Adjacency,
+    backward: Adjacency,
+    landmarks: Vec<IntersectionID>,
+}
+
+impl LandmarksInput {
+    pub fn new(map: &Map, graph: LandmarkGraph) -> Self {
+        let mut forward = Adjacency::new();
+        let mut backward = Adjacency::new();
+        for id in map.intersections.keys() {
+            forward.insert(id, vec![]);
+            backward.insert(id, vec![]);
+        }
+
+        let mut add_edge = |from: IntersectionID, to: IntersectionID, w: f32| {
+            if let Some(v) = forward.get_mut(from) {
+                v.push((to, w));
+            }
+            if let Some(v) = backward.get_mut(to) {
+                v.push((from, w));
+            }
+        };
+
+        for lane in map.lanes.values() {
+            match graph {
+                LandmarkGraph::Vehicle => {
+                    if lane.kind.vehicles() || lane.kind.is_rail() {
+                        add_edge(lane.src, lane.dst, lane.points.length() / lane.speed_limit);
+                    }
+                }
+                LandmarkGraph::Pedestrian => {
+                    if matches!(lane.kind, LaneKind::Walking) {
+                        add_edge(lane.src, lane.dst, lane.points.length());
+                        add_edge(lane.dst, lane.src, lane.points.length());
+                    }
+                }
+            }
+        }
+
+        Self {
+            dirt_id: map.dirt_id.0,
+            forward,
+            backward,
+            landmarks: Self::pick_landmarks(map),
+        }
+    }
+
+    /// Farthest point selection, spreads the landmarks on the map borders
+    fn pick_landmarks(map: &Map) -> Vec<IntersectionID> {
+        let inters = &map.intersections;
+        let first = match inters.values().next() {
+            Some(x) => x.pos,
+            None => return vec![],
+        };
+
+        let mut landmarks: Vec<IntersectionID> = vec![];
+        let mut min_dist: SecondaryMap<IntersectionID, f32> = inters
+            .values()
+            .map(|i| (i.id, i.pos.distance(first)))
+            .collect();
+
+        for _ in 0..N_LANDMARKS.min(inters.len()) {
+            let (far, _) = unwrap_or!(
+                min_dist.iter().max_by_key(|&(_, &d)| OrderedFloat(d)),
+                break
+            );
+            if landmarks.contains(&far) {
+                break;
+            }
+            landmarks.push(far);
+            let far_pos = unwrap_or!(inters.get(far), break).pos;
+            for (id, d) in min_dist.iter_mut() {
+                if let Some(i) = inters.get(id) {
+                    *d = d.min(i.pos.distance(far_pos));
+                }
+            }
+        }
+        landmarks
+    }
+
+    /// Computes the distances to and from the landmarks, the expensive part
+    pub fn build(self) -> Landmarks {
+        let mut dists: SecondaryMap<IntersectionID, Vec<f32>> = self
+            .forward
+            .keys()
+            .map(|id| (id, Vec::with_capacity(self.landmarks.len() * 2)))
+            .collect();
+
+        for &l in &self.landmarks {
+            let from_l = dijkstra(&self.forward, l);
+            let to_l = dijkstra(&self.backward, l);
+            for (id, v) in dists.iter_mut() {
+                v.push(from_l.get(id).copied().unwrap_or(f32::INFINITY));
+                v.push(to_l.get(id).copied().unwrap_or(f32::INFINITY));
+            }
+        }
+
+        Landmarks {
+            dirt_id: self.dirt_id,
+            dists,
+        }
+    }
+}
+
+impl Landmarks {
+    pub fn build(map: &Map, graph: LandmarkGraph) -> Self {
+        LandmarksInput::new(map, graph).build()
+    }
+
+    /// Whether the landmarks were computed for the current state of the map
+    pub fn is_valid(&self, map: &Map) -> bool {
+        self.dirt_id == map.dirt_id.0 && !self.dists.is_empty()
+    }
+
+    /// Lower bound of the shortest distance from `from` to `to`
+    pub fn lower_bound(&self, from: IntersectionID, to: IntersectionID) -> Option<f32> {
+        let a = self.dists.get(from)?;
+        let b = self.dists.get(to)?;
+        let mut best: f32 = 0.0;
+        for (da, db) in a.chunks_exact(2).zip(b.chunks_exact(2)) {
+            if let ([l_from, from_l], [l_to, to_l]) = (da, db) {
+                // f32::max ignores the NaNs coming from unreachable landmarks
+                best = best.max(l_to - l_from).max(from_l - to_l);
+            }
+        }
+        Some(best)
+    }
+}
+
+/// Landmarks for all the pathfinding graphs, rebuilt when the map changes
+#[derive(Default)]
+pub struct RoutingLandmarks {
+    pub vehicle: Landmarks,
+    pub pedestrian: Landmarks,
+}
+
+impl RoutingLandmarks {
+    pub fn build(map: &Map) -> Self {
+        RoutingLandmarksInput::new(map).build()
+    }
+}
+
+/// Snapshots of all the pathfinding graphs, see `LandmarksInput`
+pub struct RoutingLandmarksInput {
+    vehicle: LandmarksInput,
+    pedestrian: LandmarksInput,
+}
+
+impl RoutingLandmarksInput {
+    pub fn new(map: &Map) -> Self {
+        Self {
+            vehicle: LandmarksInput::new(map, LandmarkGraph::Vehicle),
+            pedestrian: LandmarksInput::new(map, LandmarkGraph::Pedestrian),
+        }
+    }
+
+    pub fn build(self) -> RoutingLandmarks {
+        RoutingLandmarks {
+            vehicle: self.vehicle.build(),
+            pedestrian: self.pedestrian.build(),
+        }
+    }
+}
+
+fn dijkstra(adj: &Adjacency, start: IntersectionID) -> SecondaryMap<IntersectionID, f32> {
+    let mut dist = SecondaryMap::new();
+    let mut heap = BinaryHeap::new();
+    dist.insert(start, 0.0);
+    heap.push((Reverse(OrderedFloat(0.0)), start));
+
+    while let Some((Reverse(OrderedFloat(d)), node)) = heap.pop() {
+        if dist.get(node).map_or(false, |&best| d > best) {
+            continue;
+        }
+        for &(next, w) in adj.get(node).into_iter().flatten() {
+            let nd = d + w;
+            if dist.get(next).map_or(true, |&best| nd < best) {
+                dist.insert(next, nd);
+                heap.push((Reverse(OrderedFloat(nd)), next));
+            }
+        }
+    }
+    dist
+}
@@ -1,4 +1,7 @@
-use crate::map::{Intersection, LaneID, Lanes, Roads, TrafficControl, TrafficLightSchedule};
+use crate::map::{
+    Intersection, LaneID, Lanes, RoadID, Roads, TrafficBehavior, TrafficControl,
+    TrafficLightSchedule,
+};
 use imgui_inspect::{imgui::Ui, InspectArgsDefault, InspectRenderDefault};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -8,9 +11,34 @@ pub enum LightPolicy {
     NoLights,
     StopSigns,
     Lights,
+    /// Lights whose phases are extended or skipped depending on the vehicles waiting.
+    /// Green times are in seconds.
+    Actuated {
+        min_green: u16,
+        max_green: u16,
+    },
     Auto,
 }
 
+/// Forces the phase of the lights letting vehicles in from `road` to turn green at `green_start`
+/// (modulo the cycle length) instead of using a random offset.
+/// Used to coordinate lights along a corridor (green wave).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightSync {
+    pub road: RoadID,
+    /// in seconds
+    pub green_start: u32,
+    /// Length of the whole light cycle in seconds, the same along the corridor so the
+    /// wave holds from one cycle to the next. Must be a multiple of the number of phases.
+    pub period: u32,
+}
+
+/// Length of each phase of a light cycle, in seconds
+pub const LIGHT_PHASE_LENGTH: u32 = 14;
+
+pub const DEFAULT_MIN_GREEN: u16 = 6;
+pub const DEFAULT_MAX_GREEN: u16 = 30;
+
 impl Default for LightPolicy {
     fn default() -> Self {
         LightPolicy::Auto
@@ -18,8 +46,15 @@ impl Default for LightPolicy {
 }
 
 impl LightPolicy {
-    pub fn apply(self, inter: &Intersection, lanes: &mut Lanes, roads: &Roads) {
-        let in_road_lanes: Vec<Vec<LaneID>> = inter
+    pub fn actuated_default() -> Self {
+        LightPolicy::Actuated {
+            min_green: DEFAULT_MIN_GREEN,
+            max_green: DEFAULT_MAX_GREEN,
+        }
+    }
+
+    fn in_road_lanes(inter: &Intersection, roads: &Roads) -> Vec<Vec<LaneID>> {
+        inter
             .roads
             .iter()
             .map(|&x| {
@@ -35,7 +70,23 @@ impl LightPolicy {
                     .collect::<Vec<_>>()
             })
             .filter(|v| !v.is_empty())
-            .collect();
+            .collect()
+    }
+
+    /// Groups the incoming lanes needing a light into the phases of a light cycle.
+    /// Opposite roads share the same phase.
+    pub fn light_phases(inter: &Intersection, roads: &Roads) -> Vec<Vec<LaneID>> {
+        let in_road_lanes = Self::in_road_lanes(inter, roads);
+        let n_cycles = (in_road_lanes.len() + 1) / 2;
+        let mut phases = vec![vec![]; n_cycles];
+        for (i, incoming_lanes) in in_road_lanes.into_iter().enumerate() {
+            unwrap_cont!(phases.get_mut(i % n_cycles)).extend(incoming_lanes);
+        }
+        phases
+    }
+
+    pub fn apply(self, inter: &Intersection, lanes: &mut Lanes, roads: &Roads) {
+        let in_road_lanes = Self::in_road_lanes(inter, roads);
 
         for incoming_lanes in &in_road_lanes {
             for &lane in incoming_lanes {
@@ -51,6 +102,17 @@ impl LightPolicy {
             LightPolicy::Lights => {
                 Self::lights(in_road_lanes, inter, lanes);
             }
+            LightPolicy::Actuated { .. } => {
+                // Everything starts red, the traffic signals system will give the green
+                for incoming_lanes in in_road_lanes {
+                    for lane in incoming_lanes {
+                        unwrap_cont!(lanes.get_mut(lane)).control = TrafficControl::Actuated {
+                            vehicles: TrafficBehavior::RED,
+                            walk: TrafficBehavior::RED,
+                        };
+                    }
+                }
+            }
             LightPolicy::Auto => {
                 if in_road_lanes.len() <= 2 {
                     return;
@@ -75,15 +137,33 @@ impl LightPolicy {
 
     fn lights(in_road_lanes: Vec<Vec<LaneID>>, inter: &Intersection, lanes: &mut Lanes) {
         let n_cycles = (in_road_lanes.len() + 1) / 2;
-        let cycle_size = 14;
+        let cycle_size = match inter.light_sync {
+            Some(sync) => sync.period as usize / n_cycles.max(1),
+            None => LIGHT_PHASE_LENGTH as usize,
+        };
         let orange_length = 4;
 
         let total_length = cycle_size * n_cycles;
 
         let offset = inter.id.as_ffi();
-        let inter_offset: usize =
+        let mut inter_offset: usize =
             (common::rand::rand(offset as f32) * total_length as f32) as usize;
 
+        if let Some(sync) = inter.light_sync {
+            let synced_phase = in_road_lanes.iter().position(|incoming_lanes| {
+                incoming_lanes
+                    .first()
+                    .and_then(|&l| lanes.get(l))
+                    .map_or(false, |l| l.parent == sync.road)
+            });
+
+            // green starts when (seconds + offset) % total_length == 0
+            if let Some(i) = synced_phase {
+                let start = sync.green_start as usize + cycle_size * (i % n_cycles);
+                inter_offset = (total_length - start % total_length) % total_length;
+            }
+        }
+
         for (i, incoming_lanes) in in_road_lanes.into_iter().enumerate() {
             let light = TrafficControl::Light(TrafficLightSchedule::from_basic(
                 cycle_size - orange_length,
@@ -118,7 +198,8 @@ impl InspectRenderDefault<LightPolicy> for LightPolicy {
             LightPolicy::NoLights => 0,
             LightPolicy::StopSigns => 1,
             LightPolicy::Lights => 2,
-            LightPolicy::Auto => 3,
+            LightPolicy::Actuated { .. } => 3,
+            LightPolicy::Auto => 4,
         };
 
         #[allow(clippy::indexing_slicing)]
@@ -129,6 +210,7 @@ impl InspectRenderDefault<LightPolicy> for LightPolicy {
                 LightPolicy::NoLights,
                 LightPolicy::StopSigns,
                 LightPolicy::Lights,
+                LightPolicy::actuated_default(),
                 LightPolicy::Auto,
             ],
             |x| {
@@ -136,6 +218,7 @@ impl InspectRenderDefault<LightPolicy> for LightPolicy {
                     LightPolicy::NoLights => "No lights",
                     LightPolicy::StopSigns => "Stop signs",
                     LightPolicy::Lights => "Lights",
+                    LightPolicy::Actuated { .. } => "Actuated lights",
                     LightPolicy::Auto => "Auto",
                 })
             },
@@ -146,11 +229,31 @@ impl InspectRenderDefault<LightPolicy> for LightPolicy {
                 0 => **p = LightPolicy::NoLights,
                 1 => **p = LightPolicy::StopSigns,
                 2 => **p = LightPolicy::Lights,
-                3 => **p = LightPolicy::Auto,
+                3 => **p = LightPolicy::actuated_default(),
+                4 => **p = LightPolicy::Auto,
                 _ => unreachable!(),
             }
         }
 
+        let mut changed = changed;
+        if let LightPolicy::Actuated {
+            min_green,
+            max_green,
+        } = &mut **p
+        {
+            let mut min = *min_green as i32;
+            let mut max = *max_green as i32;
+            if ui.input_int("min green (s)", &mut min).build() {
+                *min_green = min.max(1) as u16;
+                *max_green = (*max_green).max(*min_green);
+                changed = true;
+            }
+            if ui.input_int("max green (s)", &mut max).build() {
+                *max_green = (max.max(1) as u16).max(*min_green);
+                changed = true;
+            }
+        }
+
         changed
     }
 }
@@ -1,12 +1,14 @@
 use crate::map::serializing::SerializedMap;
 use crate::map::{
     Building, BuildingGen, BuildingID, BuildingKind, Intersection, IntersectionID, Lane, LaneID,
-    LaneKind, LanePattern, Lot, LotID, LotKind, ParkingSpotID, ParkingSpots, ProjectFilter,
-    ProjectKind, Road, RoadID, RoadSegmentKind, SpatialMap, StraightRoadGen, Terrain, TrainStation,
-    TrainStationID,
+    LaneKind, LanePattern, LightPolicy, LightSync, Lot, LotID, LotKind, ParkingSpotID,
+    ParkingSpots, ProjectFilter, ProjectKind, Road, RoadID, RoadSegmentKind, RoutingLandmarks,
+    SpatialMap, StraightRoadGen, TerraformKind, Terrain, TrafficControl, TrainStation,
+    TrainStationID, TravelTimes, TurnID, BRIDGE_CLEARANCE, CELL_SIZE, LIGHT_PHASE_LENGTH,
+    WATER_LEVEL,
 };
 use geom::OBB;
-use geom::{pseudo_angle, Circle, Intersect, Shape, Spline3, Vec2, Vec3};
+use geom::{Circle, Intersect, PolyLine, Shape, Spline3, Vec2, Vec3, AABB};
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 use slotmap::DenseSlotMap;
@@ -19,6 +21,9 @@ pub type Intersections = DenseSlotMap<IntersectionID, Intersection>;
 pub type Buildings = DenseSlotMap<BuildingID, Building>;
 pub type Lots = DenseSlotMap<LotID, Lot>;
 
+/// Minimum distance between a planted tree and roads, lots or buildings
+const TREE_CLEARANCE: f32 = 5.0;
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MapProject {
     pub pos: Vec3,
@@ -34,6 +39,8 @@ pub struct Map {
     pub(crate) spatial_map: SpatialMap,
     pub terrain: Terrain,
     pub parking: ParkingSpots,
+    pub(crate) travel_times: TravelTimes,
+    pub(crate) landmarks: RoutingLandmarks,
     pub dirt_id: Wrapping<u32>,
 }
 
@@ -56,6 +63,8 @@ impl Map {
             buildings: Buildings::default(),
             lots: Lots::default(),
             terrain: Terrain::default(),
+            travel_times: TravelTimes::default(),
+            landmarks: RoutingLandmarks::default(),
             dirt_id: Wrapping(1),
             spatial_map: SpatialMap::default(),
         }
@@ -74,6 +83,114 @@ impl Map {
         self.check_invariants()
     }
 
+    /// Coordinates the lights along a chain of connected intersections so that vehicles
+    /// driving at the speed limit get consecutive greens (green wave).
+    /// Intersections along the corridor are switched to fixed lights sharing the cycle length
+    /// of the one with the most phases. The first one anchors the wave: the phase of its lanes
+    /// coming from the second intersection turns green at 0, and each next intersection turns
+    /// green for the corridor when a vehicle leaving the first one at 0 arrives.
+    /// The whole corridor is checked first and nothing is changed if it isn't a chain of
+    /// connected intersections with lanes going along it, if one of them has actuated lights,
+    /// or if their numbers of phases don't divide the common cycle length.
+    /// Returns whether the corridor was synchronized.
+    pub fn make_green_wave(&mut self, corridor: &[IntersectionID]) -> bool {
+        info!("make_green_wave {:?}", corridor);
+
+        let first = match corridor.first() {
+            Some(&first) if corridor.len() >= 2 => first,
+            _ => {
+                log::warn!("green wave corridor needs at least two intersections");
+                return false;
+            }
+        };
+
+        let mut syncs = Vec::with_capacity(corridor.len());
+        let mut arrival = 0.0;
+        for w in corridor.windows(2) {
+            let (from, to) = match *w {
+                [from, to] => (from, to),
+                _ => continue,
+            };
+
+            let roads = &self.roads;
+            let lanes = &self.lanes;
+            let road = unwrap_or!(
+                self.intersections.get(from).and_then(|i| {
+                    i.roads
+                        .iter()
+                        .flat_map(|&r| roads.get(r))
+                        .find(|r| r.other_end(from) == Some(to))
+                }),
+                {
+                    log::warn!("green wave corridor is not connected: {:?} {:?}", from, to);
+                    return false;
+                }
+            );
+
+            let speed = road
+                .outgoing_lanes_from(from)
+                .iter()
+                .filter(|(_, kind)| kind.needs_light())
+                .flat_map(|&(id, _)| lanes.get(id))
+                .map(|l| l.speed_limit)
+                .fold(f32::INFINITY, f32::min);
+            if !speed.is_finite() || speed <= 0.0 {
+                log::warn!("no lane going from {:?} to {:?} in green wave", from, to);
+                return false;
+            }
+
+            if from == first {
+                syncs.push((from, road.id, 0));
+            }
+            arrival += road.length() / speed;
+            syncs.push((to, road.id, arrival.round() as u32));
+        }
+
+        if let Some(&id) = corridor.iter().find(|&&id| {
+            self.intersections.get(id).map_or(false, |i| {
+                matches!(i.light_policy, LightPolicy::Actuated { .. })
+            })
+        }) {
+            log::warn!(
+                "{:?} has actuated lights, remove them before making a green wave",
+                id
+            );
+            return false;
+        }
+
+        let n_phases: Vec<u32> = corridor
+            .iter()
+            .flat_map(|&id| self.intersections.get(id))
+            .map(|i| LightPolicy::light_phases(i, &self.roads).len() as u32)
+            .filter(|&n| n > 0)
+            .collect();
+        let period = LIGHT_PHASE_LENGTH * n_phases.iter().copied().max().unwrap_or(1);
+        if n_phases.iter().any(|&n| period % n != 0) {
+            log::warn!(
+                "green wave corridor mixes intersections with {:?} phases, they can't share a cycle",
+                n_phases
+            );
+            return false;
+        }
+
+        self.dirt_id += Wrapping(1);
+        for (id, road, green_start) in syncs {
+            let inter = unwrap_cont!(self.intersections.get_mut(id));
+            inter.light_policy = LightPolicy::Lights;
+            inter.light_sync = Some(LightSync {
+                road,
+                green_start,
+                period,
+            });
+            inter.update_traffic_control(&mut self.lanes, &self.roads);
+        }
+
+        #[cfg(debug_assertions)]
+        self.check_invariants();
+
+        true
+    }
+
     pub fn remove_intersection(&mut self, src: IntersectionID) {
         info!("remove_intersection {:?}", src);
         self.dirt_id += Wrapping(1);
@@ -84,7 +201,7 @@ impl Map {
         self.check_invariants()
     }
 
-    fn remove_intersection_inner(&mut self, src: IntersectionID) {
+    pub(crate) fn remove_intersection_inner(&mut self, src: IntersectionID) {
         let inter = unwrap_ret!(self.intersections.remove(src));
 
         for road in inter.roads {
@@ -101,6 +218,7 @@ impl Map {
 
         let b = self.buildings.remove(b)?;
         self.spatial_map.remove(b.id);
+        self.parking.remove_building_spots(b.id);
 
         self.dirt_id += Wrapping(1);
 
@@ -153,6 +271,10 @@ impl Map {
         gen: BuildingGen,
         attachments_gen: &[StraightRoadGen],
     ) -> Option<BuildingID> {
+        if self.terrain.obb_in_water(obb) {
+            info!("cannot build {:?} in the water", kind);
+            return None;
+        }
         if self.building_overlaps(*obb) {
             return None;
         }
@@ -193,6 +315,11 @@ impl Map {
             gen,
             attachments,
         );
+        if let (Some(id), BuildingGen::Parking { levels }) = (v, gen) {
+            #[allow(clippy::indexing_slicing)] // just inserted
+            self.parking
+                .generate_building_spots(&self.buildings[id], levels);
+        }
         #[cfg(debug_assertions)]
         self.check_invariants();
         v
@@ -233,7 +360,7 @@ impl Map {
         v
     }
 
-    fn remove_road_inner(&mut self, road_id: RoadID) -> Option<Road> {
+    pub(crate) fn remove_road_inner(&mut self, road_id: RoadID) -> Option<Road> {
         let road = self.remove_raw_road(road_id)?;
 
         for (id, _) in road.lanes_iter() {
@@ -269,6 +396,95 @@ impl Map {
         }
     }
 
+    /// Edits the terrain with a brush and puts back the intersections, lots and buildings
+    /// standing on the edited cells onto the ground, along with the roads crossing them.
+    /// Returns the earthworks volume in cubic meters.
+    pub fn terraform(
+        &mut self,
+        kind: TerraformKind,
+        center: Vec2,
+        radius: f32,
+        amount: f32,
+    ) -> f32 {
+        info!("terraform {:?} {:?} {} {}", kind, center, radius, amount);
+
+        let volume = self.terrain.terraform(kind, center, radius, amount);
+        if volume == 0.0 {
+            return volume;
+        }
+        self.dirt_id += Wrapping(1);
+
+        self.snap_to_terrain(Circle::new(center, radius).bbox());
+
+        #[cfg(debug_assertions)]
+        self.check_invariants();
+
+        volume
+    }
+
+    /// Digs a river along the path, roads crossing it become bridges and
+    /// the lots ending up in the water are removed.
+    /// Returns the earthworks volume in cubic meters.
+    pub fn add_river(&mut self, path: &PolyLine, width: f32) -> f32 {
+        info!("add_river {:?} {}", path, width);
+
+        let volume = self.terrain.carve_river(path, width);
+        if volume == 0.0 {
+            return volume;
+        }
+        self.dirt_id += Wrapping(1);
+
+        self.snap_to_terrain(path.bbox().expand(width * 0.5));
+
+        #[cfg(debug_assertions)]
+        self.check_invariants();
+
+        volume
+    }
+
+    /// Plants trees at the positions where nothing is built.
+    /// Returns the number of trees planted.
+    pub fn plant_trees(&mut self, positions: Vec<Vec2>) -> usize {
+        info!("plant_trees {}", positions.len());
+
+        let spatial = &self.spatial_map;
+        let free: Vec<Vec2> = positions
+            .into_iter()
+            .filter(|&p| {
+                spatial
+                    .query_around(p, TREE_CLEARANCE, ProjectFilter::ALL)
+                    .next()
+                    .is_none()
+            })
+            .collect();
+
+        self.terrain.add_trees(free)
+    }
+
+    /// Fills the circle with trees, `density` is in trees per hectare
+    pub fn paint_forest(&mut self, center: Vec2, radius: f32, density: f32) -> usize {
+        let positions = self.terrain.forest_positions(center, radius, density);
+        self.plant_trees(positions)
+    }
+
+    pub fn clear_trees(&mut self, center: Vec2, radius: f32) {
+        info!("clear_trees {:?} {}", center, radius);
+
+        self.terrain
+            .remove_near_filter(Circle::new(center, radius).bbox(), |p| {
+                p.is_close(center, radius)
+            });
+    }
+
+    /// Recomputes the landmarks used to speed up pathfinding, they are only used by
+    /// the pathfinders while the map doesn't change.
+    /// This is a full synchronous rebuild, the game rebuilds them on another thread instead
+    /// (see `landmarks_update`) and routing falls back to the euclidean heuristic meanwhile.
+    pub fn update_landmarks(&mut self) {
+        info!("update_landmarks");
+        self.landmarks = RoutingLandmarks::build(self);
+    }
+
     pub fn clear(&mut self) {
         info!("clear");
         let before = std::mem::replace(self, Self::empty());
@@ -281,11 +497,95 @@ impl Map {
 
     // Private mutating
 
-    pub(crate) fn add_intersection(&mut self, pos: Vec3) -> IntersectionID {
+    /// Moves the objects in the area back onto the terrain after its heights changed.
+    /// The roads crossing the area are regenerated along with their lanes and lots, even when
+    /// their intersections are outside of it.
+    fn snap_to_terrain(&mut self, area: AABB) {
+        // Terrain::height interpolates with the neighboring cells
+        let area = area.expand(2.0 * CELL_SIZE);
+        let objs: Vec<ProjectKind> = self
+            .spatial_map
+            .query(
+                area,
+                ProjectFilter::INTER
+                    | ProjectFilter::ROAD
+                    | ProjectFilter::BUILDING
+                    | ProjectFilter::LOT,
+            )
+            .collect();
+
+        let mut moved = vec![];
+        let mut roads = vec![];
+        for obj in objs {
+            match obj {
+                ProjectKind::Inter(id) => {
+                    let inter = unwrap_cont!(self.intersections.get_mut(id));
+                    inter.pos.z = unwrap_cont!(self.terrain.road_height(inter.pos.xy()));
+                    moved.push(id);
+                }
+                ProjectKind::Lot(id) => {
+                    let lot = unwrap_cont!(self.lots.get_mut(id));
+                    if self.terrain.obb_in_water(&lot.shape) {
+                        self.lots.remove(id);
+                        self.spatial_map.remove(id);
+                        continue;
+                    }
+                    lot.height = unwrap_cont!(self.terrain.height(lot.shape.center()));
+                }
+                ProjectKind::Building(id) => {
+                    let b = unwrap_cont!(self.buildings.get_mut(id));
+                    let h = unwrap_cont!(self.terrain.height(b.obb.center()));
+                    let dz = Vec3::z(h - b.height);
+                    for (poly, _) in &mut b.mesh.faces {
+                        for v in poly {
+                            *v += dz;
+                        }
+                    }
+                    b.door_pos += dz;
+                    b.height = h;
+                }
+                ProjectKind::Road(id) => roads.push(id),
+                _ => {}
+            }
+        }
+
+        for &id in &moved {
+            roads.extend(
+                unwrap_cont!(self.intersections.get(id))
+                    .roads
+                    .iter()
+                    .copied(),
+            );
+        }
+        roads.sort_unstable();
+        roads.dedup();
+
+        let mut to_invalidate = moved;
+        for r_id in roads {
+            let road = unwrap_cont!(self.roads.get_mut(r_id));
+            let src = unwrap_cont!(self.intersections.get(road.src));
+            let dst = unwrap_cont!(self.intersections.get(road.dst));
+            road.update_points(src, dst);
+            self.spatial_map.update(r_id, road.boldline());
+            to_invalidate.push(road.src);
+            to_invalidate.push(road.dst);
+        }
+
+        to_invalidate.sort_unstable();
+        to_invalidate.dedup();
+        for id in to_invalidate {
+            self.invalidate(id);
+        }
+    }
+
+    pub(crate) fn add_intersection(&mut self, mut pos: Vec3) -> IntersectionID {
+        if self.terrain.is_water(pos.xy()) {
+            pos.z = pos.z.max(WATER_LEVEL + BRIDGE_CLEARANCE);
+        }
         Intersection::make(&mut self.intersections, &mut self.spatial_map, pos)
     }
 
-    fn invalidate(&mut self, id: IntersectionID) {
+    pub(crate) fn invalidate(&mut self, id: IntersectionID) {
         info!("invalidate {:?}", id);
 
         self.dirt_id += Wrapping(1);
@@ -548,6 +848,9 @@ impl Map {
     pub fn lanes(&self) -> &Lanes {
         &self.lanes
     }
+    pub fn travel_times(&self) -> &TravelTimes {
+        &self.travel_times
+    }
     pub fn intersections(&self) -> &Intersections {
         &self.intersections
     }
@@ -615,8 +918,31 @@ impl Map {
             .map(|(id, _)| id)
     }
 
+    /// Walk/don't-walk control of a crosswalk, derived from the lights of the road it crosses
+    pub fn crosswalk_control(&self, turn: TurnID) -> TrafficControl {
+        self.crosswalk_light_lane(turn)
+            .and_then(|id| self.lanes.get(id))
+            .map_or(TrafficControl::Always, |lane| lane.control.walk_control())
+    }
+
+    /// Incoming lane of the road crossed by the crosswalk whose light controls it, if any
+    pub fn crosswalk_light_lane(&self, turn: TurnID) -> Option<LaneID> {
+        let road = self
+            .lanes
+            .get(turn.src)
+            .and_then(|l| self.roads.get(l.parent))?;
+        road.incoming_lanes_to(turn.parent)
+            .iter()
+            .filter(|(_, kind)| kind.needs_light())
+            .map(|&(id, _)| id)
+            .find(|&id| self.lanes.get(id).map_or(false, |l| l.control.is_light()))
+    }
+
     pub fn parking_to_drive(&self, spot: ParkingSpotID) -> Option<LaneID> {
         let spot = self.parking.get(spot)?;
+        if let Some(building) = spot.building {
+            return self.parking_entrance(building);
+        }
         let park_lane = self.lanes.get(spot.parent)?;
         let road = self.roads.get(park_lane.parent)?;
         road.outgoing_lanes_from(park_lane.src)
@@ -625,129 +951,160 @@ impl Map {
             .map(|&(id, _)| id)
     }
 
+    /// Driving lane in front of the entrance of a parking building
+    pub fn parking_entrance(&self, building: BuildingID) -> Option<LaneID> {
+        let door = self.buildings.get(building)?.door_pos;
+        self.nearest_lane(door, LaneKind::Driving, Some(20.0))
+    }
+
     pub fn parking_to_drive_pos(&self, spot: ParkingSpotID) -> Option<Vec3> {
+        let lane = self.parking_to_drive(spot)?;
         let spot = self.parking.get(spot)?;
-        let park_lane = self.lanes.get(spot.parent)?;
-        let road = self.roads.get(park_lane.parent)?;
-        let lane = road
-            .outgoing_lanes_from(park_lane.src)
-            .iter()
-            .rfind(|&&(_, kind)| kind == LaneKind::Driving)
-            .map(|&(id, _)| id)?;
+        let target = match spot.building {
+            Some(building) => self.buildings.get(building)?.door_pos,
+            None => spot.trans.position,
+        };
 
-        let (pos, _, dir) = self
-            .lanes()
-            .get(lane)?
-            .points
-            .project_segment_dir(spot.trans.position);
+        let (pos, _, dir) = self.lanes().get(lane)?.points.project_segment_dir(target);
         Some(pos - dir * 4.0)
     }
+}
 
-    pub fn check_invariants(&self) {
-        if std::env::var("MAP_INVARIANT_CHECK").is_err() {
-            return;
-        }
-        for inter in self.intersections.values() {
-            log::debug!("{:?}", inter.id);
-            assert!(!inter.roads.is_empty());
-
-            let mut last_angle = -f32::INFINITY;
-            for &road in &inter.roads {
-                let road = self.roads.get(road).expect("road does not exist");
-                let ang = pseudo_angle(road.dir_from(inter.id));
-                assert!(ang > last_angle);
-                last_angle = ang;
-            }
-
-            for turn in inter.turns() {
-                log::debug!("{:?}", turn.id);
-                assert_eq!(turn.id.parent, inter.id);
-                assert!(self.lanes.contains_key(turn.id.src));
-                assert!(self.lanes.contains_key(turn.id.dst));
-                assert!(turn.points.n_points() >= 2);
-            }
-
-            assert!(inter.pos.is_finite());
-            assert!(self.spatial_map.contains(inter.id));
+#[cfg(test)]
+mod tests {
+    use crate::map::{
+        BuildingGen, BuildingKind, LanePatternBuilder, Map, RoadSegmentKind, Terrain,
+        TrafficBehavior, BRIDGE_CLEARANCE, CELL_SIZE, WATER_LEVEL,
+    };
+    use geom::{vec2, vec3, PolyLine, Vec2, OBB};
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn green_wave_holds_over_several_cycles() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let pat = LanePatternBuilder::new().parking(false).build();
+
+        let corridor: Vec<_> = (0..4)
+            .map(|i| map.add_intersection(vec3(1000.0 + 300.0 * i as f32, 1000.0, 0.0)))
+            .collect();
+        let roads: Vec<_> = corridor
+            .windows(2)
+            .map(|w| {
+                map.connect(w[0], w[1], &pat, RoadSegmentKind::Straight)
+                    .unwrap()
+            })
+            .collect();
+
+        // Side streets so the intersections have 1, 3, 2 and 1 phases
+        let n = map.add_intersection(vec3(1300.0, 1250.0, 0.0));
+        let s = map.add_intersection(vec3(1300.0, 750.0, 0.0));
+        let nw = map.add_intersection(vec3(1100.0, 1250.0, 0.0));
+        let e = map.add_intersection(vec3(1600.0, 1250.0, 0.0));
+        for (a, b) in [
+            (corridor[1], n),
+            (corridor[1], s),
+            (corridor[1], nw),
+            (corridor[2], e),
+        ] {
+            map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
         }
 
-        for lane in self.lanes.values() {
-            log::debug!("{:?}", lane.id);
-            assert!(!lane.points.is_empty());
-            assert!(self.intersections.contains_key(lane.src), "{:?}", lane.src);
-            assert!(self.intersections.contains_key(lane.dst), "{:?}", lane.dst);
-            assert!(self.roads.contains_key(lane.parent), "{:?}", lane.parent);
-
-            if matches!(lane.kind, LaneKind::Parking) {
-                assert!(
-                    self.parking.spots(lane.id).is_some(),
-                    "no spots for {:?}",
-                    lane.id
-                )
+        assert!(map.make_green_wave(&corridor));
+
+        let period = map
+            .intersections()
+            .get(corridor[0])
+            .unwrap()
+            .light_sync
+            .unwrap()
+            .period;
+        for cycle in 0..4 {
+            let mut arrival = (cycle * period) as f32;
+            for (&road, &to) in roads.iter().zip(&corridor[1..]) {
+                let road = map.roads().get(road).unwrap();
+                let lane = road
+                    .incoming_lanes_to(to)
+                    .iter()
+                    .find(|(_, kind)| kind.needs_light())
+                    .and_then(|&(id, _)| map.lanes().get(id))
+                    .unwrap();
+                arrival += road.length() / lane.speed_limit;
+
+                let t = arrival.round() as u32 + 1;
+                assert_eq!(
+                    lane.control.get_behavior(t),
+                    TrafficBehavior::GREEN,
+                    "red at {:?} at {}s",
+                    to,
+                    t
+                );
             }
         }
+    }
 
-        for road in self.roads.values() {
-            log::debug!("{:?}", road.id);
-            let src = self.intersections.get(road.src).unwrap();
-            assert!(src.roads.contains(&road.id));
-            let dst = self.intersections.get(road.dst).unwrap();
-            assert!(dst.roads.contains(&road.id));
-            assert!(!road.points.is_empty());
-            assert!(road.lanes_iter().next().is_some());
-            assert!(road.points.first().is_close(src.pos, 0.001),);
-            assert!(road.points.last().is_close(dst.pos, 0.001));
-            assert!(road.interfaced_points().n_points() >= 2);
-            assert!(road.length() > 0.0);
-            assert!(self.spatial_map.contains(road.id));
-
-            for (id, _) in road.lanes_iter() {
-                let v = self.lanes.get(id).expect("lane child does not exist");
-                assert_eq!(v.parent, road.id);
-            }
-
-            // Road with parking lane has driving lane (incoming)
-            let has_parking = road
-                .incoming_lanes_to(road.src)
-                .iter()
-                .any(|(_, kind)| matches!(kind, LaneKind::Parking));
+    /// A map with a road going north across where `river` flows east
+    fn river_crossing() -> (Map, PolyLine) {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let ground =
+            |map: &Map, x: f32, y: f32| vec3(x, y, map.terrain.height(vec2(x, y)).unwrap());
+        let a = map.add_intersection(ground(&map, 1000.0, 700.0));
+        let b = map.add_intersection(ground(&map, 1000.0, 1300.0));
+        map.connect(
+            a,
+            b,
+            &LanePatternBuilder::new().build(),
+            RoadSegmentKind::Straight,
+        )
+        .unwrap();
+        let river = PolyLine::new(vec![vec2(400.0, 1000.0), vec2(1600.0, 1000.0)]);
+        (map, river)
+    }
 
-            if has_parking {
-                let has_driving = road
-                    .incoming_lanes_to(road.src)
-                    .iter()
-                    .any(|(_, kind)| matches!(kind, LaneKind::Driving));
-                assert!(has_driving);
-            }
+    #[test]
+    fn river_is_dug_below_the_water_level() {
+        let (mut map, river) = river_crossing();
+        let tree = vec2(700.0, 1000.0);
+        assert_eq!(map.terrain.add_trees(Some(tree)), 1);
+        let n_lots = map.lots().len();
+        let volume = map.terrain.river_volume(&river, 100.0);
 
-            // Road with parking lane has driving lane (outgoing)
-            let has_parking = road
-                .outgoing_lanes_from(road.src)
-                .iter()
-                .any(|(_, kind)| matches!(kind, LaneKind::Parking));
+        assert_eq!(map.add_river(&river, 100.0), volume);
+        assert!(volume > 0.0);
 
-            if has_parking {
-                let has_driving = road
-                    .outgoing_lanes_from(road.src)
-                    .iter()
-                    .any(|(_, kind)| matches!(kind, LaneKind::Driving));
-                assert!(has_driving);
-            }
+        for i in 0..=24 {
+            let p = vec2(400.0 + 50.0 * i as f32, 1000.0);
+            assert!(map.terrain.height(p).unwrap() < WATER_LEVEL, "{:?}", p);
         }
+        assert!(map.terrain.trees_near(tree, CELL_SIZE).next().is_none());
+        assert!(map
+            .terrain
+            .trees_near(vec2(1000.0, 1000.0), 200.0)
+            .all(|t| !map.terrain.is_water(t.pos)));
+
+        assert!(map.lots().len() < n_lots);
+        assert!(map
+            .lots()
+            .values()
+            .all(|lot| !map.terrain.obb_in_water(&lot.shape)));
+    }
 
-        for lot in self.lots.values() {
-            log::debug!("{:?}", lot.id);
-            assert!(lot.shape.axis().iter().all(|x| x.magnitude() > 0.0));
-            assert!(self.roads.contains_key(lot.parent), "{:?}", lot.parent);
-            assert!(self.spatial_map.contains(lot.id));
-        }
+    #[test]
+    fn nothing_is_built_in_the_water() {
+        let (mut map, river) = river_crossing();
+        map.add_river(&river, 100.0);
 
-        for obj in self.spatial_map.objects() {
-            assert!(self.spatial_map.contains(*obj));
-            log::debug!("{:?}", obj);
-            assert!(obj.check_valid(self));
-        }
+        let inter = map.add_intersection(vec3(800.0, 1000.0, WATER_LEVEL - 10.0));
+        assert_eq!(
+            map.intersections().get(inter).unwrap().pos.z,
+            WATER_LEVEL + BRIDGE_CLEARANCE
+        );
 
-        assert!(self.parking.reuse_spot.is_empty());
+        let obb = OBB::new(vec2(1300.0, 1000.0), Vec2::X, 40.0, 40.0);
+        assert!(map
+            .build_special_building(&obb, BuildingKind::GoodsCompany(0), BuildingGen::Farm, &[])
+            .is_none());
+        assert!(map.buildings().is_empty());
     }
 }
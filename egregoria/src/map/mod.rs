@@ -22,13 +22,21 @@ pub use objects::*;
 
 pub mod procgen {
     mod building;
+    mod city;
     pub mod heightmap;
+    mod osm;
     mod presets;
 
     pub use building::*;
+    pub use city::*;
+    pub use osm::*;
     pub use presets::*;
 }
 
+mod geojson;
+mod heightmap_file;
+mod invariants;
+mod landmarks;
 mod light_policy;
 mod map;
 mod pathfinding;
@@ -36,16 +44,22 @@ mod serializing;
 mod spatial_map;
 mod terrain;
 mod traffic_control;
+mod travel_times;
 mod traversable;
 mod turn_policy;
 
 // Use self or else it would be ambiguous with "pathfinding" crate
 pub use self::pathfinding::*;
+pub use geojson::*;
+pub use heightmap_file::*;
+pub use invariants::*;
+pub use landmarks::*;
 pub use light_policy::*;
 pub use map::*;
 pub use spatial_map::*;
 pub use terrain::*;
 pub use traffic_control::*;
+pub use travel_times::*;
 pub use traversable::*;
 pub use turn_policy::*;
 
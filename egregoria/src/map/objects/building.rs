@@ -1,4 +1,4 @@
-use crate::map::procgen::{gen_exterior_farm, gen_exterior_house, ColoredMesh};
+use crate::map::procgen::{gen_exterior_farm, gen_exterior_house, gen_parking, ColoredMesh};
 use crate::map::{Buildings, LanePattern, RoadID, SpatialMap, Terrain};
 use geom::{Color, Vec2, Vec3, OBB};
 use imgui_inspect::debug_inspect_impl;
@@ -11,6 +11,12 @@ new_key_type! {
 
 debug_inspect_impl!(BuildingID);
 
+impl BuildingID {
+    pub fn as_ffi(self) -> u64 {
+        self.0.as_ffi()
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum BuildingKind {
     House,
@@ -18,6 +24,8 @@ pub enum BuildingKind {
     RailFretStation,
     TrainStation,
     ExternalTrading,
+    /// Surface lot or garage where vehicles can park, see [`BuildingGen::Parking`]
+    Parking,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -30,6 +38,10 @@ pub enum BuildingGen {
     NoWalkway {
         door_pos: Vec2,
     },
+    /// One level is a surface lot, more is a garage. The entrance faces the road.
+    Parking {
+        levels: u32,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +85,9 @@ impl Building {
                 vertical_factor, ..
             } => (Default::default(), Vec2::y(-vertical_factor * 0.5 * size)),
             BuildingGen::NoWalkway { door_pos } => (Default::default(), door_pos),
+            BuildingGen::Parking { levels } => {
+                gen_parking(size, obb.corners[0].distance(obb.corners[3]), levels)
+            }
         };
 
         for (poly, _) in &mut mesh.faces {
@@ -1,6 +1,6 @@
 use crate::map::{
-    Intersections, LaneID, LaneKind, Lanes, LightPolicy, Road, RoadID, Roads, SpatialMap,
-    TraverseDirection, Turn, TurnID, TurnPolicy,
+    Intersections, LaneID, LaneKind, LaneTurns, Lanes, LightPolicy, LightSync, Road, RoadID, Roads,
+    SpatialMap, TraverseDirection, Turn, TurnID, TurnPolicy,
 };
 use geom::{pseudo_angle, Circle};
 use geom::{Vec2, Vec3};
@@ -9,7 +9,7 @@ use imgui_inspect::{InspectArgsDefault, InspectRenderDefault};
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 new_key_type! {
     pub struct IntersectionID;
@@ -33,6 +33,9 @@ pub struct Intersection {
 
     pub turn_policy: TurnPolicy,
     pub light_policy: LightPolicy,
+    pub light_sync: Option<LightSync>,
+    /// Turn restrictions of incoming lanes, lanes without an entry can turn anywhere
+    pub lane_turns: BTreeMap<LaneID, LaneTurns>,
 }
 
 impl Intersection {
@@ -44,6 +47,8 @@ impl Intersection {
             roads: Default::default(),
             turn_policy: Default::default(),
             light_policy: Default::default(),
+            light_sync: None,
+            lane_turns: Default::default(),
         });
         spatial.insert(id, pos.xy());
         id
@@ -79,6 +84,10 @@ impl Intersection {
     }
 
     pub fn update_turns(&mut self, lanes: &Lanes, roads: &Roads) {
+        let id = self.id;
+        self.lane_turns
+            .retain(|&l, _| lanes.get(l).map_or(false, |l| l.dst == id));
+
         self.turns = self
             .turn_policy
             .generate_turns(self, lanes, roads)
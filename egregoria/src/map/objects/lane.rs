@@ -110,6 +110,7 @@ pub struct LanePatternBuilder {
     pub speed_limit: f32,
     pub sidewalks: bool,
     pub parking: bool,
+    pub bike_lanes: bool,
     pub one_way: bool,
     pub rail: bool,
 }
@@ -127,6 +128,7 @@ impl LanePatternBuilder {
             speed_limit: 9.0,
             sidewalks: true,
             parking: true,
+            bike_lanes: false,
             one_way: false,
             rail: false,
         }
@@ -152,6 +154,11 @@ impl LanePatternBuilder {
         self
     }
 
+    pub fn bike_lanes(&mut self, bike_lanes: bool) -> &mut Self {
+        self.bike_lanes = bike_lanes;
+        self
+    }
+
     pub fn one_way(&mut self, one_way: bool) -> &mut Self {
         self.one_way = one_way;
         self
@@ -176,6 +183,9 @@ impl LanePatternBuilder {
         if self.parking {
             w += LaneKind::Parking.width() * wayf;
         }
+        if self.bike_lanes {
+            w += LaneKind::Biking.width() * wayf;
+        }
         w += self.n_lanes as f32 * wayf * LaneKind::Driving.width();
         w + 0.5
     }
@@ -201,6 +211,14 @@ impl LanePatternBuilder {
             forward.push(LaneKind::Parking);
         }
 
+        // Between the parked cars and the sidewalk, away from the traffic
+        if self.bike_lanes {
+            if !self.one_way {
+                backward.push(LaneKind::Biking);
+            }
+            forward.push(LaneKind::Biking);
+        }
+
         if self.sidewalks {
             backward.push(LaneKind::Walking);
             forward.push(LaneKind::Walking);
@@ -38,6 +38,9 @@ impl Lot {
         }
 
         let shape = OBB::new(at.xy() + axis * size * 0.5, axis, size, size);
+        if map.terrain.obb_in_water(&shape) {
+            return None;
+        }
 
         let proj = map.project(shape.center().z0(), size * 0.5 - 0.5, ProjectFilter::ALL);
         if !matches!(proj.kind, ProjectKind::Ground) {
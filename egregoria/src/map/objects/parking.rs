@@ -1,4 +1,5 @@
-use crate::map::{Lane, LaneID, LaneKind, CROSSWALK_WIDTH};
+use crate::map::procgen::PARKING_LEVEL_HEIGHT;
+use crate::map::{Building, BuildingID, Lane, LaneID, LaneKind, CROSSWALK_WIDTH};
 use flat_spatial::Grid;
 use geom::{Transform, Vec2, Vec3};
 use ordered_float::OrderedFloat;
@@ -11,17 +12,23 @@ new_key_type! {
 }
 
 pub const PARKING_SPOT_LENGTH: f32 = 6.0;
+/// Size of a spot in a parking building, the depth includes half of the aisle
+pub const PARKING_SPOT_WIDTH: f32 = 2.5;
+pub const PARKING_SPOT_DEPTH: f32 = 8.0;
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct ParkingSpot {
+    /// Parking lane of the spot, null for the spots of a parking building
     pub parent: LaneID,
     pub trans: Transform,
+    pub building: Option<BuildingID>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ParkingSpots {
     spots: SlotMap<ParkingSpotID, ParkingSpot>,
     lane_spots: SecondaryMap<LaneID, Vec<ParkingSpotID>>,
+    building_spots: SecondaryMap<BuildingID, Vec<ParkingSpotID>>,
     pub(crate) reuse_spot: Grid<ParkingSpotID, Vec2>,
 }
 
@@ -30,6 +37,7 @@ impl Default for ParkingSpots {
         Self {
             spots: Default::default(),
             lane_spots: Default::default(),
+            building_spots: Default::default(),
             reuse_spot: Grid::new(10),
         }
     }
@@ -104,6 +112,7 @@ impl ParkingSpots {
                             *p = ParkingSpot {
                                 parent,
                                 trans: Transform::new_dir(pos, dir),
+                                building: None,
                             };
                             return spot_id;
                         } else {
@@ -115,6 +124,7 @@ impl ParkingSpots {
                 spots.insert(ParkingSpot {
                     parent,
                     trans: Transform::new_dir(pos, dir),
+                    building: None,
                 })
             })
             .collect();
@@ -122,9 +132,57 @@ impl ParkingSpots {
         self.lane_spots.insert(lane.id, spots);
     }
 
+    /// Fills the building with rows of spots facing each other across an aisle,
+    /// on each of the levels
+    pub fn generate_building_spots(&mut self, building: &Building, levels: u32) {
+        self.remove_building_spots(building.id);
+
+        let obb = &building.obb;
+        let [across, along] = obb.axis();
+        let (width, depth) = (across.magnitude(), along.magnitude());
+        let (across, along) = unwrap_ret!(across.try_normalize().zip(along.try_normalize()));
+        let cols = (width / PARKING_SPOT_WIDTH) as u32;
+        let rows = (depth / PARKING_SPOT_DEPTH) as u32;
+        let origin = obb.corners[0];
+
+        let mut ids = Vec::with_capacity((levels.max(1) * cols * rows) as usize);
+        for level in 0..levels.max(1) {
+            let z = building.height + 0.1 + level as f32 * PARKING_LEVEL_HEIGHT;
+            for row in 0..rows {
+                // Odd rows face the previous one
+                let dir = if row % 2 == 0 { along } else { -along };
+                for col in 0..cols {
+                    let pos = origin
+                        + across * (col as f32 + 0.5) * PARKING_SPOT_WIDTH
+                        + along * (row as f32 + 0.5) * PARKING_SPOT_DEPTH;
+                    ids.push(self.spots.insert(ParkingSpot {
+                        parent: LaneID::default(),
+                        trans: Transform::new_dir(pos.z(z), dir.z0()),
+                        building: Some(building.id),
+                    }));
+                }
+            }
+        }
+
+        self.building_spots.insert(building.id, ids);
+    }
+
+    pub fn remove_building_spots(&mut self, building: BuildingID) {
+        if let Some(spots) = self.building_spots.remove(building) {
+            for spot in spots {
+                self.spots.remove(spot);
+            }
+        }
+    }
+
+    pub fn building_spots(&self, building: BuildingID) -> Option<&[ParkingSpotID]> {
+        self.building_spots.get(building).map(|x| &**x)
+    }
+
     pub fn clear(&mut self) {
         self.spots.clear();
         self.lane_spots.clear();
+        self.building_spots.clear();
         for _ in self.reuse_spot.clear() {}
     }
 
@@ -282,6 +282,12 @@ impl Road {
         }
     }
 
+    /// Regenerates the center line after one of the intersections moved
+    pub(crate) fn update_points(&mut self, src: &Intersection, dst: &Intersection) {
+        let precise = self.lanes_iter().any(|(_, kind)| kind.is_rail());
+        self.points = Self::generate_points(src, dst, self.segment, precise);
+    }
+
     fn generate_points(
         src: &Intersection,
         dst: &Intersection,
@@ -427,6 +433,23 @@ impl Road {
         -self.points.last_dir().unwrap_or(Vec3::X).xy().normalize()
     }
 
+    /// Driving lanes directly next to `lane` going in the same direction
+    pub fn parallel_lanes(&self, lane: LaneID) -> impl Iterator<Item = LaneID> + '_ {
+        let lanes = if self.lanes_forward.iter().any(|&(id, _)| id == lane) {
+            &self.lanes_forward
+        } else {
+            &self.lanes_backward
+        };
+        lanes
+            .iter()
+            .position(|&(id, _)| id == lane)
+            .into_iter()
+            .flat_map(|i| std::iter::once(i.wrapping_sub(1)).chain(std::iter::once(i + 1)))
+            .filter_map(move |i| lanes.get(i))
+            .filter(|(_, kind)| matches!(kind, LaneKind::Driving))
+            .map(|&(id, _)| id)
+    }
+
     pub fn other_end(&self, my_end: IntersectionID) -> Option<IntersectionID> {
         if self.src == my_end {
             return Some(self.dst);
@@ -1,5 +1,6 @@
 use crate::map::{
-    LaneID, LaneKind, LanePatternBuilder, Map, Traversable, TraverseDirection, TraverseKind, TurnID,
+    Lane, LaneID, LaneKind, LanePatternBuilder, Map, Traversable, TraverseDirection, TraverseKind,
+    TurnID,
 };
 use geom::{PolyLine3, Vec3};
 use ordered_float::OrderedFloat;
@@ -16,6 +17,7 @@ pub trait Pathfinder {
 pub enum PathKind {
     Pedestrian,
     Vehicle,
+    Bike,
     Rail,
 }
 
@@ -24,6 +26,7 @@ impl Pathfinder for PathKind {
         match self {
             PathKind::Pedestrian => PedestrianPath.path(map, start, end),
             PathKind::Vehicle => CarPath.path(map, start, end),
+            PathKind::Bike => BikePath.path(map, start, end),
             PathKind::Rail => RailPath.path(map, start, end),
         }
     }
@@ -32,6 +35,7 @@ impl Pathfinder for PathKind {
         match self {
             PathKind::Pedestrian => PedestrianPath.nearest_lane(map, pos),
             PathKind::Vehicle => CarPath.nearest_lane(map, pos),
+            PathKind::Bike => BikePath.nearest_lane(map, pos),
             PathKind::Rail => RailPath.nearest_lane(map, pos),
         }
     }
@@ -40,6 +44,7 @@ impl Pathfinder for PathKind {
         match self {
             PathKind::Pedestrian => PedestrianPath.local_route(map, lane, start, end),
             PathKind::Vehicle => CarPath.local_route(map, lane, start, end),
+            PathKind::Bike => BikePath.local_route(map, lane, start, end),
             PathKind::Rail => RailPath.local_route(map, lane, start, end),
         }
     }
@@ -52,17 +57,26 @@ impl Pathfinder for PedestrianPath {
         let inters = &map.intersections;
         let lanes = &map.lanes;
 
-        let end_pos = inters.get(lanes.get(end)?.dst)?.pos;
+        let end_lane = lanes.get(end)?;
+        let end_pos = inters.get(end_lane.dst)?.pos;
+        let landmarks = Some(&map.landmarks.pedestrian).filter(|l| l.is_valid(map));
 
         let heuristic = |t: &Traversable| {
-            let pos = unwrap_ret!(
-                inters.get(unwrap_ret!(
-                    t.destination_intersection(lanes),
-                    OrderedFloat(f32::INFINITY)
-                )),
+            let inter = unwrap_ret!(
+                t.destination_intersection(lanes),
                 OrderedFloat(f32::INFINITY)
-            )
-            .pos;
+            );
+
+            if let Some(l) = landmarks {
+                if let (Some(a), Some(b)) = (
+                    l.lower_bound(inter, end_lane.src),
+                    l.lower_bound(inter, end_lane.dst),
+                ) {
+                    return OrderedFloat(a.min(b));
+                }
+            }
+
+            let pos = unwrap_ret!(inters.get(inter), OrderedFloat(f32::INFINITY)).pos;
 
             OrderedFloat(pos.distance(end_pos) * 1.3) // Inexact but (much) faster
         };
@@ -143,48 +157,214 @@ impl Pathfinder for RailPath {
     }
 }
 
+/// Extra cost of taking the first turn from a lane parallel to the start lane, in seconds
+const LANE_CHANGE_COST: f32 = 5.0;
+
+/// Routes lane to lane through the intersection turns. Since turns only exist for the directions
+/// allowed by the lane restrictions (see `LaneTurns`), vehicles are routed into a lane that
+/// can make the next turn before reaching the junction.
+/// Edge costs are the measured travel times of the lanes (see `TravelTimes`).
+/// When the map hasn't changed since they were computed, the landmarks (see `Landmarks`)
+/// give an exact lower bound heuristic so the paths are optimal.
 struct CarPath;
 
 impl Pathfinder for CarPath {
+    fn path(&self, map: &Map, start: Traversable, end: LaneID) -> Option<Vec<Traversable>> {
+        vehicle_path_changing_lane(map, start, &[], end)
+    }
+
+    fn nearest_lane(&self, map: &Map, pos: Vec3) -> Option<LaneID> {
+        map.nearest_lane(pos, LaneKind::Driving, None)
+    }
+
+    fn local_route(&self, map: &Map, lane: LaneID, start: Vec3, end: Vec3) -> Option<PolyLine3> {
+        let lane = &map.lanes.get(lane)?;
+        let (p_start, seg_start) = lane.points.project_segment(start);
+        let (p_end, seg_end) = lane.points.project_segment(end);
+
+        if seg_end < seg_start
+            || (seg_end == seg_start
+                && lane.points.get(seg_end)?.distance2(p_start)
+                    < lane.points.get(seg_end)?.distance2(p_end))
+        {
+            return None;
+        }
+
+        let segs = &lane.points.get(seg_start..seg_end)?;
+        let mut v = Vec::with_capacity(3 + segs.len());
+        v.push(p_start);
+        v.extend_from_slice(segs);
+        v.push(p_end);
+        v.push(end);
+        Some(PolyLine3::new(v))
+    }
+}
+
+/// Vehicle path from `start`, where the first turn may also be taken from one of the `parallel`
+/// lanes when it is faster even with `LANE_CHANGE_COST`, for vehicles that have the room to
+/// change lanes before the junction.
+/// The path still begins with `start`, the source of the first turn is the lane to change to.
+pub fn vehicle_path_changing_lane(
+    map: &Map,
+    start: Traversable,
+    parallel: &[LaneID],
+    end: LaneID,
+) -> Option<Vec<Traversable>> {
+    let inters = &map.intersections;
+    let lanes = &map.lanes;
+
+    let start_lane = start.destination_lane();
+
+    let end_inter = lanes.get(end)?.dst;
+    let end_pos = inters.get(end_inter)?.pos;
+    let landmarks = Some(&map.landmarks.vehicle).filter(|l| l.is_valid(map));
+
+    let dummy = LaneID::null();
+
+    const HEURISTIC_SPEED: f32 = LanePatternBuilder::new().speed_limit;
+
+    let heuristic = |&p: &LaneID| {
+        let inter = unwrap_ret!(lanes.get(p), OrderedFloat(f32::INFINITY)).dst;
+
+        if let Some(bound) = landmarks.and_then(|l| l.lower_bound(inter, end_inter)) {
+            return OrderedFloat(bound);
+        }
+
+        let pos = unwrap_ret!(inters.get(inter), OrderedFloat(f32::INFINITY)).pos;
+        OrderedFloat(pos.distance(end_pos) * 1.2 / HEURISTIC_SPEED) // Inexact but (much) faster
+    };
+
+    let turns_from = |src: LaneID, extra: f32| {
+        lanes
+            .get(src)
+            .and_then(|x| inters.get(x.dst))
+            .into_iter()
+            .flat_map(move |inter| {
+                inter.turns_from(src).filter_map(move |(x, _)| {
+                    let lane = lanes.get(x.dst)?;
+                    if lane.kind == LaneKind::Biking {
+                        return None;
+                    }
+                    Some((x.dst, OrderedFloat(map.travel_times.cost(lane) + extra)))
+                })
+            })
+    };
+
+    let successors = |&p: &LaneID| {
+        let (p, others) = if p == dummy {
+            (start_lane, parallel)
+        } else {
+            (p, &[][..])
+        };
+        std::iter::once((p, 0.0))
+            .chain(others.iter().map(|&l| (l, LANE_CHANGE_COST)))
+            .flat_map(move |(src, extra)| turns_from(src, extra))
+    };
+
+    let (v, _) = pathfinding::directed::astar::astar(&dummy, successors, heuristic, |p| *p == end)?;
+
+    // Staying on the start lane is cheaper whenever it can make the same turn
+    let first_src = v.get(1).and_then(|&first| {
+        std::iter::once(start_lane)
+            .chain(parallel.iter().copied())
+            .find(|&src| {
+                lanes
+                    .get(src)
+                    .and_then(|l| inters.get(l.dst))
+                    .map_or(false, |inter| {
+                        inter.turns_from(src).any(|(t, _)| t.dst == first)
+                    })
+            })
+    });
+
+    lanes_to_path(map, start, first_src.unwrap_or(start_lane), v)
+}
+
+/// Turns the lanes found by the A* (starting with the dummy start lane) into traversables,
+/// the first turn going from `first_src`
+fn lanes_to_path(
+    map: &Map,
+    start: Traversable,
+    first_src: LaneID,
+    v: Vec<LaneID>,
+) -> Option<Vec<Traversable>> {
+    let mut path = Vec::with_capacity(v.len() * 2);
+    path.push(start);
+
+    let mut last_id = first_src;
+
+    for lane in v.into_iter().skip(1) {
+        let inter_end = &map.intersections.get(map.lanes.get(lane)?.src)?;
+        let id = TurnID::new(inter_end.id, last_id, lane, false);
+        path.push(Traversable::new(
+            TraverseKind::Turn(id),
+            TraverseDirection::Forward,
+        ));
+        path.push(Traversable::new(
+            TraverseKind::Lane(lane),
+            TraverseDirection::Forward,
+        ));
+
+        last_id = lane;
+    }
+    Some(path)
+}
+
+/// Cruising speed of a bike, in m/s
+pub const BIKE_SPEED: f32 = 5.0;
+/// Driving lanes with a higher speed limit are not shared with bikes, in m/s
+pub const BIKE_SHARED_MAX_SPEED: f32 = 10.0;
+/// How much longer riding in traffic feels compared to riding on a bike lane
+const SHARED_LANE_PENALTY: f32 = 1.5;
+
+/// Routes lane to lane through the intersection turns like `CarPath`, but only on the bike
+/// lanes and on the driving lanes slow enough to be shared with cars.
+/// Shared lanes cost more so that bike lanes are preferred when they exist.
+struct BikePath;
+
+impl BikePath {
+    fn lane_cost(lane: &Lane) -> Option<f32> {
+        let time = lane.points.length() / lane.speed_limit.min(BIKE_SPEED);
+        match lane.kind {
+            LaneKind::Biking => Some(time),
+            LaneKind::Driving if lane.speed_limit <= BIKE_SHARED_MAX_SPEED => {
+                Some(time * SHARED_LANE_PENALTY)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Pathfinder for BikePath {
     fn path(&self, map: &Map, start: Traversable, end: LaneID) -> Option<Vec<Traversable>> {
         let inters = &map.intersections;
         let lanes = &map.lanes;
 
         let start_lane = start.destination_lane();
-
         let end_pos = inters.get(lanes.get(end)?.dst)?.pos;
 
         let dummy = LaneID::null();
 
-        const HEURISTIC_SPEED: f32 = LanePatternBuilder::new().speed_limit;
-
         let heuristic = |&p: &LaneID| {
-            let pos = unwrap_ret!(
-                inters.get(unwrap_ret!(lanes.get(p), OrderedFloat(f32::INFINITY)).dst),
-                OrderedFloat(f32::INFINITY)
-            )
-            .pos;
-            OrderedFloat(pos.distance(end_pos) * 1.2 / HEURISTIC_SPEED) // Inexact but (much) faster
+            let inter = unwrap_ret!(lanes.get(p), OrderedFloat(f32::INFINITY)).dst;
+            let pos = unwrap_ret!(inters.get(inter), OrderedFloat(f32::INFINITY)).pos;
+            OrderedFloat(pos.distance(end_pos) / BIKE_SPEED)
         };
 
         let successors = |&p: &LaneID| {
-            let l;
-            let p = if p == dummy {
-                l = lanes.get(start_lane);
-                start_lane
-            } else {
-                l = lanes.get(p);
-                p
-            };
-            l.and_then(|x| inters.get(x.dst))
+            let p = if p == dummy { start_lane } else { p };
+            lanes
+                .get(p)
+                .and_then(|x| inters.get(x.dst))
                 .into_iter()
                 .flat_map(move |inter| {
-                    inter.turns_from(p).map(move |(x, _)| {
-                        let cost = lanes
-                            .get(x.dst)
-                            .map(|p| p.points.length() / p.speed_limit)
-                            .unwrap_or(f32::INFINITY);
-                        (x.dst, OrderedFloat(cost))
+                    inter.turns_from(p).filter_map(move |(x, _)| {
+                        let lane = lanes.get(x.dst)?;
+                        // The destination can be a fast lane if there is nothing else around
+                        let cost = Self::lane_cost(lane).or_else(|| {
+                            (x.dst == end).then(|| lane.points.length() / BIKE_SPEED)
+                        })?;
+                        Some((x.dst, OrderedFloat(cost)))
                     })
                 })
         };
@@ -192,51 +372,147 @@ impl Pathfinder for CarPath {
         let (v, _) =
             pathfinding::directed::astar::astar(&dummy, successors, heuristic, |p| *p == end)?;
 
-        let mut path = Vec::with_capacity(v.len() * 2);
-        path.push(start);
-
-        let mut last_id = start_lane;
-
-        for lane in v.into_iter().skip(1) {
-            let inter_end = &inters.get(lanes.get(lane)?.src)?;
-            let id = TurnID::new(inter_end.id, last_id, lane, false);
-            path.push(Traversable::new(
-                TraverseKind::Turn(id),
-                TraverseDirection::Forward,
-            ));
-            path.push(Traversable::new(
-                TraverseKind::Lane(lane),
-                TraverseDirection::Forward,
-            ));
-
-            last_id = lane;
-        }
-        Some(path)
+        lanes_to_path(map, start, start.destination_lane(), v)
     }
 
     fn nearest_lane(&self, map: &Map, pos: Vec3) -> Option<LaneID> {
-        map.nearest_lane(pos, LaneKind::Driving, None)
+        map.nearest_lane(pos, LaneKind::Biking, Some(20.0))
+            .or_else(|| map.nearest_lane(pos, LaneKind::Driving, None))
     }
 
     fn local_route(&self, map: &Map, lane: LaneID, start: Vec3, end: Vec3) -> Option<PolyLine3> {
-        let lane = &map.lanes.get(lane)?;
-        let (p_start, seg_start) = lane.points.project_segment(start);
-        let (p_end, seg_end) = lane.points.project_segment(end);
+        CarPath.local_route(map, lane, start, end)
+    }
+}
 
-        if seg_end < seg_start
-            || (seg_end == seg_start
-                && lane.points.get(seg_end)?.distance2(p_start)
-                    < lane.points.get(seg_end)?.distance2(p_end))
-        {
-            return None;
+#[cfg(test)]
+mod tests {
+    use super::{vehicle_path_changing_lane, PathKind, Pathfinder};
+    use crate::map::{
+        LaneID, LaneKind, LanePatternBuilder, LaneTurns, Map, RoadID, RoadSegmentKind, Terrain,
+        Traversable, TraverseDirection, TraverseKind, TurnDirection,
+    };
+    use geom::vec3;
+    use ordered_float::OrderedFloat;
+
+    #[test]
+    fn bikes_use_bike_lanes_and_cars_dont() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let pat = LanePatternBuilder::new().bike_lanes(true).build();
+        let a = map.add_intersection(vec3(0.0, 0.0, 0.0));
+        let b = map.add_intersection(vec3(200.0, 0.0, 0.0));
+        let c = map.add_intersection(vec3(200.0, 200.0, 0.0));
+        map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+        map.connect(b, c, &pat, RoadSegmentKind::Straight).unwrap();
+
+        for (kind, expected) in [
+            (PathKind::Bike, LaneKind::Biking),
+            (PathKind::Vehicle, LaneKind::Driving),
+        ] {
+            let start = kind.nearest_lane(&map, vec3(20.0, 0.0, 0.0)).unwrap();
+            let end = kind.nearest_lane(&map, vec3(200.0, 180.0, 0.0)).unwrap();
+            let start = Traversable::new(TraverseKind::Lane(start), TraverseDirection::Forward);
+
+            let path = kind.path(&map, start, end).unwrap();
+            assert!(path.len() > 1);
+            for t in path {
+                if let TraverseKind::Lane(id) = t.kind {
+                    assert_eq!(map.lanes().get(id).unwrap().kind, expected);
+                }
+            }
         }
+    }
 
-        let segs = &lane.points.get(seg_start..seg_end)?;
-        let mut v = Vec::with_capacity(3 + segs.len());
-        v.push(p_start);
-        v.extend_from_slice(segs);
-        v.push(p_end);
-        v.push(end);
-        Some(PolyLine3::new(v))
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn left_only_lane_is_avoided_going_straight() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let pat = LanePatternBuilder::new().n_lanes(2).parking(false).build();
+        let center = map.add_intersection(vec3(500.0, 500.0, 0.0));
+        let west = map.add_intersection(vec3(300.0, 500.0, 0.0));
+        let mut connect = |x: f32, y: f32| {
+            let other = map.add_intersection(vec3(x, y, 0.0));
+            map.connect(center, other, &pat, RoadSegmentKind::Straight)
+                .unwrap()
+        };
+        let east = connect(700.0, 500.0);
+        let north = connect(500.0, 700.0);
+        connect(500.0, 300.0);
+        let road = map
+            .connect(west, center, &pat, RoadSegmentKind::Straight)
+            .unwrap();
+
+        // Going east, the left lane is the northernmost one
+        let mut incoming: Vec<LaneID> = map.roads()[road]
+            .incoming_lanes_to(center)
+            .iter()
+            .filter(|(_, kind)| *kind == LaneKind::Driving)
+            .map(|&(id, _)| id)
+            .collect();
+        incoming.sort_by_key(|&id| OrderedFloat(-map.lanes()[id].points.last().y));
+        let (left, other) = (incoming[0], incoming[1]);
+
+        let left_only = LaneTurns {
+            left: true,
+            straight: false,
+            right: false,
+            back: false,
+        };
+        map.update_intersection(center, |i| {
+            i.lane_turns.insert(left, left_only);
+        });
+
+        let inter = &map.intersections()[center];
+        let direction = |src: LaneID, dst: LaneID| {
+            TurnDirection::from_orientations(
+                map.lanes()[src].orientation_from(center),
+                map.lanes()[dst].orientation_from(center),
+            )
+        };
+        let dirs = |src: LaneID| -> Vec<TurnDirection> {
+            inter
+                .turns_from(src)
+                .filter(|(id, _)| map.lanes()[id.dst].kind == LaneKind::Driving)
+                .map(|(id, _)| direction(id.src, id.dst))
+                .collect()
+        };
+        let left_dirs = dirs(left);
+        assert!(!left_dirs.is_empty());
+        assert!(left_dirs.iter().all(|&d| left_only.allows(d)));
+        assert!(dirs(other).contains(&TurnDirection::Straight));
+
+        let outgoing = |road: RoadID| {
+            map.roads()[road]
+                .outgoing_lanes_from(center)
+                .iter()
+                .find(|(_, kind)| *kind == LaneKind::Driving)
+                .unwrap()
+                .0
+        };
+        let first_turn = |end: LaneID| {
+            let start = Traversable::new(TraverseKind::Lane(left), TraverseDirection::Forward);
+            let path = vehicle_path_changing_lane(&map, start, &[other], end).unwrap();
+            path.iter()
+                .find_map(|t| match t.kind {
+                    TraverseKind::Turn(id) => Some(id),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let straight = first_turn(outgoing(east));
+        assert_eq!(straight.src, other);
+        assert_eq!(
+            direction(straight.src, straight.dst),
+            TurnDirection::Straight
+        );
+
+        let turn_left = first_turn(outgoing(north));
+        assert_eq!(turn_left.src, left);
+        assert_eq!(direction(turn_left.src, turn_left.dst), TurnDirection::Left);
     }
 }
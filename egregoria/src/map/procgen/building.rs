@@ -1,5 +1,8 @@
+use crate::map::PARKING_SPOT_WIDTH;
 use geom::skeleton::{faces_from_skeleton, skeleton};
-use geom::{minmax, vec2, Intersect, LinearColor, Polygon, Segment, Shape, Vec2, Vec3, AABB};
+use geom::{
+    minmax, vec2, vec3, Color, Intersect, LinearColor, Polygon, Segment, Shape, Vec2, Vec3, AABB,
+};
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 use std::panic::catch_unwind;
@@ -200,6 +203,50 @@ pub fn gen_exterior_farm(size: f32, seed: u64) -> (ColoredMesh, Vec2) {
     (mesh, door_pos)
 }
 
+/// Height of a level of a parking garage, in meters
+pub const PARKING_LEVEL_HEIGHT: f32 = 3.0;
+
+/// Asphalt with painted spots for a surface lot (one level), a concrete box for a garage.
+/// The entrance is at the middle of the front side, which faces the road.
+pub fn gen_parking(width: f32, depth: f32, levels: u32) -> (ColoredMesh, Vec2) {
+    let (hw, hd) = (width * 0.5, depth * 0.5);
+    let rect = |x0: f32, y0: f32, x1: f32, y1: f32, z: f32| {
+        vec![
+            vec3(x0, y0, z),
+            vec3(x1, y0, z),
+            vec3(x1, y1, z),
+            vec3(x0, y1, z),
+        ]
+    };
+    let mut mesh = ColoredMesh::default();
+
+    if levels <= 1 {
+        mesh.faces
+            .push((rect(-hw, -hd, hw, hd, 0.05), Color::gray(0.25).into()));
+        let n = (width / PARKING_SPOT_WIDTH) as i32;
+        for i in 0..=n {
+            let x = -hw + i as f32 * PARKING_SPOT_WIDTH;
+            mesh.faces.push((
+                rect(x - 0.05, -hd + 1.0, x + 0.05, hd - 1.0, 0.06),
+                Color::WHITE.into(),
+            ));
+        }
+        return (mesh, Vec2::y(-hd));
+    }
+
+    let h = levels as f32 * PARKING_LEVEL_HEIGHT;
+    let corners = [vec2(-hw, -hd), vec2(hw, -hd), vec2(hw, hd), vec2(-hw, hd)];
+    for (&a, &b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+        mesh.faces.push((
+            vec![a.z(0.0), b.z(0.0), b.z(h), a.z(h)],
+            Color::gray(0.6).into(),
+        ));
+    }
+    mesh.faces
+        .push((rect(-hw, -hd, hw, hd, h), Color::gray(0.45).into()));
+    (mesh, Vec2::y(-hd))
+}
+
 // How to gen a house
 // Idea: Make everything out of rectangles
 // 1. Make exterior
@@ -0,0 +1,391 @@
+#![allow(clippy::indexing_slicing)]
+
+use crate::map::{
+    BuildingGen, BuildingID, BuildingKind, IntersectionID, LanePattern, LanePatternBuilder,
+    LotKind, Map, ProjectFilter, ProjectKind, RoadSegmentKind, StraightRoadGen,
+};
+use crate::utils::rand_provider::RandProvider;
+use geom::{vec2, Vec2, OBB};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+/// Distance between two nodes of an arterial, in meters
+const ARTERIAL_STEP: f32 = 200.0;
+/// Maximum heading change of an arterial at each node, in radians
+const ARTERIAL_MAX_TURN: f32 = 0.35;
+/// Extra cost in meters of climbing per radian of turn, keeps arterials from zigzagging
+const TURN_PENALTY: f32 = 6.0;
+/// Depth of a block between two parallel local streets, in meters
+const BLOCK_DEPTH: f32 = 120.0;
+/// Maximum number of blocks on each side of an arterial
+const MAX_BLOCKS: u32 = 3;
+/// Nodes closer than this to an existing intersection are merged into it, in meters
+const MERGE_DIST: f32 = 30.0;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct CityParams {
+    pub seed: u64,
+    /// Radius of the city in meters
+    pub size: f32,
+    /// Between 0 and 1, share of the blocks, lots and houses that get built
+    pub density: f32,
+}
+
+/// An industry that can be placed by the generator: building kind, generator and size in meters
+pub type IndustryDescription = (BuildingKind, BuildingGen, f32);
+
+/// Generates a city around `center`: arterials following the terrain from the center,
+/// blocks of local streets along them, industries on the outskirts, an external trading
+/// station at the end of the longest arterial and residential lots with houses.
+/// Returns the buildings that were created.
+pub fn gen_city(
+    map: &mut Map,
+    center: Vec2,
+    params: CityParams,
+    industries: &[IndustryDescription],
+) -> Vec<BuildingID> {
+    let time = std::time::Instant::now();
+    let mut gen = CityGen {
+        map,
+        rng: RandProvider::new(params.seed),
+        center,
+        params,
+        built: vec![],
+    };
+
+    let arterial_pat = LanePatternBuilder::new()
+        .n_lanes(2)
+        .parking(false)
+        .speed_limit(14.0)
+        .build();
+    let local_pat = LanePatternBuilder::new().build();
+
+    let n_arterials = 3 + gen.rng.next_u32() % 3;
+    let offset = gen.rng.next_f32() * std::f32::consts::TAU;
+    let mut arterials = vec![];
+    for i in 0..n_arterials {
+        let jitter = (gen.rng.next_f32() - 0.5) * 0.6;
+        let angle = offset + i as f32 / n_arterials as f32 * std::f32::consts::TAU + jitter;
+        let path = gen.arterial(angle, &arterial_pat);
+        if path.len() >= 2 {
+            arterials.push(path);
+        }
+    }
+
+    gen.industries(&arterials, industries, arterial_pat.width());
+    for path in &arterials {
+        gen.blocks(path, &local_pat);
+    }
+    if let Some(longest) = arterials.iter().max_by_key(|p| p.len()) {
+        gen.external_trading(longest);
+    }
+    gen.zone();
+
+    info!(
+        "generating city took {}ms: {} arterials and {} buildings",
+        time.elapsed().as_secs_f32() * 1000.0,
+        arterials.len(),
+        gen.built.len()
+    );
+
+    #[cfg(debug_assertions)]
+    gen.map.check_invariants();
+    gen.built
+}
+
+struct CityGen<'a> {
+    map: &'a mut Map,
+    rng: RandProvider,
+    center: Vec2,
+    params: CityParams,
+    built: Vec<BuildingID>,
+}
+
+impl<'a> CityGen<'a> {
+    /// Walks from the center choosing at each node the heading with the gentlest slope
+    fn arterial(&mut self, angle: f32, pat: &LanePattern) -> Vec<Vec2> {
+        let terrain = &self.map.terrain;
+        let mut path = vec![self.center];
+        let mut heading = angle;
+        let mut p = self.center;
+
+        while p.distance(self.center) + ARTERIAL_STEP <= self.params.size {
+            let h = unwrap_or!(terrain.height(p), break);
+            let best = [-1.0, -0.5, 0.0, 0.5, 1.0]
+                .iter()
+                .filter_map(|&k: &f32| {
+                    let a = heading + k * ARTERIAL_MAX_TURN;
+                    let np = p + vec2(a.cos(), a.sin()) * ARTERIAL_STEP;
+                    if terrain.is_water(np) {
+                        return None;
+                    }
+                    let cost = (terrain.height(np)? - h).abs()
+                        + f32::abs(k * ARTERIAL_MAX_TURN) * TURN_PENALTY;
+                    Some((a, np, cost))
+                })
+                .min_by_key(|&(_, _, cost)| OrderedFloat(cost));
+            let (a, np, _) = unwrap_or!(best, break);
+            heading = a;
+            p = np;
+            path.push(p);
+        }
+
+        for (i, w) in path.windows(2).enumerate() {
+            if !self.street(w[0], w[1], pat) {
+                path.truncate(i + 1);
+                break;
+            }
+        }
+        path
+    }
+
+    /// Ladder of local streets on both sides of the arterial
+    fn blocks(&mut self, path: &[Vec2], pat: &LanePattern) {
+        // Offset direction at each node, averaged between the adjacent segments
+        let normals: Vec<Vec2> = (0..path.len())
+            .map(|i| {
+                let prev = path[i.saturating_sub(1)];
+                let next = path[(i + 1).min(path.len() - 1)];
+                (next - prev).normalize().perpendicular()
+            })
+            .collect();
+
+        let depth = 1 + (self.params.density * (MAX_BLOCKS - 1) as f32).round() as u32;
+        for side in [-1.0, 1.0] {
+            for i in 1..path.len() - 1 {
+                let at = |i: usize, d: u32| path[i] + normals[i] * side * BLOCK_DEPTH * d as f32;
+                for d in 1..=depth {
+                    if self.rng.next_f32() > self.params.density {
+                        break;
+                    }
+                    if !self.street(at(i, d - 1), at(i, d), pat) {
+                        break;
+                    }
+                    self.street(at(i, d), at(i + 1, d), pat);
+                    self.street(at(i + 1, d - 1), at(i + 1, d), pat);
+                }
+            }
+        }
+    }
+
+    /// Industries along the outer half of the arterials
+    fn industries(
+        &mut self,
+        arterials: &[Vec<Vec2>],
+        industries: &[IndustryDescription],
+        road_width: f32,
+    ) {
+        if arterials.is_empty() || industries.is_empty() {
+            return;
+        }
+        let n = (self.params.size / 400.0 * (0.5 + self.params.density) * arterials.len() as f32)
+            .ceil() as u32;
+
+        for _ in 0..n {
+            let path = &arterials[self.rng.next_u32() as usize % arterials.len()];
+            let (kind, gen, size) = industries[self.rng.next_u32() as usize % industries.len()];
+            let i = path.len() / 2 + self.rng.next_u32() as usize % (path.len() - path.len() / 2);
+            let i = i.min(path.len() - 2);
+
+            let (a, b) = (path[i], path[i + 1]);
+            let dir = unwrap_cont!((b - a).try_normalize());
+            let side = if self.rng.next_f32() < 0.5 {
+                dir.perpendicular()
+            } else {
+                -dir.perpendicular()
+            };
+            let center = (a + b) * 0.5 + side * (size + road_width + 1.0) * 0.5;
+            let obb = OBB::new(center, side, size, size);
+
+            if self.overlaps(&OBB::new(center, side, size - 2.0, size - 2.0)) {
+                continue;
+            }
+            if let Some(id) = self.map.build_special_building(&obb, kind, gen, &[]) {
+                self.built.push(id);
+            }
+        }
+    }
+
+    /// Station with rail tracks past the end of the arterial, like the one of a new world
+    fn external_trading(&mut self, path: &[Vec2]) {
+        let (a, b) = match path {
+            [.., a, b] => (*a, *b),
+            _ => return,
+        };
+        let dir = unwrap_ret!((b - a).try_normalize());
+        let c = b + dir * (ARTERIAL_STEP + 50.0);
+        let obb = OBB::new(c, dir.perpendicular(), 72.2, 200.0);
+        if self.overlaps(&obb) {
+            return;
+        }
+        let z = unwrap_ret!(self.map.terrain.road_height(c));
+
+        let [offy, offx] = obb.axis().map(|x| x.normalize());
+        let pattern = LanePatternBuilder::new().rail(true).build();
+        let tracks: Vec<StraightRoadGen> = (-1..=1)
+            .map(|i| StraightRoadGen {
+                from: (c - offx * (i as f32 * 21.0) - offy * 100.0).z(z),
+                to: (c - offx * (i as f32 * 21.0) + offy * 120.0).z(z),
+                pattern: pattern.clone(),
+            })
+            .collect();
+
+        if let Some(id) = self.map.build_special_building(
+            &obb,
+            BuildingKind::ExternalTrading,
+            BuildingGen::NoWalkway { door_pos: c },
+            &tracks,
+        ) {
+            self.built.push(id);
+        }
+    }
+
+    /// Zones the lots of the city as residential and builds houses on some of them
+    fn zone(&mut self) {
+        let lots: Vec<_> = self
+            .map
+            .lots
+            .values()
+            .filter(|lot| lot.shape.center().distance(self.center) < self.params.size)
+            .map(|lot| lot.id)
+            .collect();
+
+        for lot in lots {
+            if self.rng.next_f32() > self.params.density {
+                continue;
+            }
+            self.map.set_lot_kind(lot, LotKind::Residential);
+            if self.rng.next_f32() < self.params.density {
+                if let Some(id) = self.map.build_house(lot) {
+                    self.built.push(id);
+                }
+            }
+        }
+    }
+
+    fn overlaps(&self, obb: &OBB) -> bool {
+        self.map
+            .spatial_map
+            .query(
+                *obb,
+                ProjectFilter::ROAD | ProjectFilter::INTER | ProjectFilter::BUILDING,
+            )
+            .next()
+            .is_some()
+    }
+
+    fn node(&mut self, p: Vec2) -> IntersectionID {
+        let close = self
+            .map
+            .spatial_map
+            .query_around(p, MERGE_DIST, ProjectFilter::INTER)
+            .find_map(|k| match k {
+                ProjectKind::Inter(id) => Some(id),
+                _ => None,
+            });
+        match close {
+            Some(id) => id,
+            None => {
+                let z = self.map.terrain.road_height(p).unwrap_or(0.0);
+                self.map.add_intersection(p.z(z))
+            }
+        }
+    }
+
+    /// Connects the two points unless the street would cross the water, another road or
+    /// a building. Returns whether the street exists afterwards.
+    fn street(&mut self, a: Vec2, b: Vec2, pat: &LanePattern) -> bool {
+        let terrain = &self.map.terrain;
+        let in_terrain = |p| terrain.height(p).is_some() && !terrain.is_water(p);
+        if !in_terrain(a) || !in_terrain(b) || !in_terrain((a + b) * 0.5) {
+            return false;
+        }
+
+        let len = a.distance(b);
+        if len <= 2.0 * MERGE_DIST {
+            return false;
+        }
+        let dir = unwrap_or!((b - a).try_normalize(), return false);
+        let src = self.node(a);
+        let dst = self.node(b);
+        if src == dst {
+            self.remove_if_empty(src);
+            return false;
+        }
+        if self.map.find_road(src, dst).is_some() || self.map.find_road(dst, src).is_some() {
+            return true;
+        }
+
+        let probe = OBB::new((a + b) * 0.5, dir, len - 2.0 * MERGE_DIST, pat.width());
+        if self.overlaps(&probe) {
+            self.remove_if_empty(src);
+            self.remove_if_empty(dst);
+            return false;
+        }
+
+        self.map
+            .connect(src, dst, pat, RoadSegmentKind::Straight)
+            .is_some()
+    }
+
+    /// Removes the intersection if it was created for a street that couldn't be built
+    fn remove_if_empty(&mut self, id: IntersectionID) {
+        if self
+            .map
+            .intersections
+            .get(id)
+            .map_or(false, |i| i.roads.is_empty())
+        {
+            self.map.remove_intersection(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gen_city, CityParams};
+    use crate::map::{Map, Terrain};
+    use geom::{vec2, Vec2};
+
+    fn generate(center: Vec2) -> Map {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(3, 3);
+        let params = CityParams {
+            seed: 42,
+            size: 600.0,
+            density: 0.7,
+        };
+        gen_city(&mut map, center, params, &[]);
+        map
+    }
+
+    #[test]
+    fn same_seed_same_city() {
+        let terrain = Terrain::new(3, 3);
+        let center = (4..12)
+            .flat_map(|y| (4..12).map(move |x| vec2(x as f32, y as f32) * 128.0))
+            .find(|&p| terrain.height(p).is_some() && !terrain.is_water(p))
+            .unwrap();
+
+        let a = generate(center);
+        let b = generate(center);
+
+        let roads = |m: &Map| {
+            m.roads()
+                .values()
+                .map(|r| (r.id, r.points.as_slice().to_vec()))
+                .collect::<Vec<_>>()
+        };
+        let buildings = |m: &Map| {
+            m.buildings()
+                .values()
+                .map(|b| (b.id, b.kind, b.door_pos))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(!a.roads().is_empty());
+        assert_eq!(roads(&a), roads(&b));
+        assert_eq!(buildings(&a), buildings(&b));
+        assert_eq!(a.invariant_violations(), vec![]);
+    }
+}
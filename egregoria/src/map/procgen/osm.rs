@@ -0,0 +1,596 @@
+#![allow(clippy::indexing_slicing)]
+
+use crate::map::{
+    BuildingGen, BuildingID, BuildingKind, IntersectionID, LanePattern, LanePatternBuilder, Map,
+    ProjectFilter, ProjectKind, RoadSegmentKind, CHUNK_SIZE,
+};
+use geom::{vec2, Segment, Vec2, OBB};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Intermediate way nodes closer than this to the simplified line are dropped, in meters
+const SIMPLIFY_TOLERANCE: f32 = 5.0;
+/// Nodes closer than this to an existing intersection are merged into it, in meters
+const MERGE_DIST: f32 = 15.0;
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Area to import from an OSM extract, in degrees
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OsmBBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl OsmBBox {
+    fn contains(&self, (lat, lon): (f64, f64)) -> bool {
+        self.min_lat <= lat && lat <= self.max_lat && self.min_lon <= lon && lon <= self.max_lon
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (
+            (self.min_lat + self.max_lat) * 0.5,
+            (self.min_lon + self.max_lon) * 0.5,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct OsmWay {
+    nodes: Vec<i64>,
+    tags: BTreeMap<String, String>,
+}
+
+impl OsmWay {
+    fn tag(&self, k: &str) -> Option<&str> {
+        self.tags.get(k).map(String::as_str)
+    }
+}
+
+/// The nodes inside a bounding box and the ways of an OSM XML extract.
+/// It is parsed by the player importing it and sent in the command, so every peer
+/// builds the map from the same data.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OsmData {
+    /// (lat, lon) of each node
+    nodes: BTreeMap<i64, (f64, f64)>,
+    ways: Vec<OsmWay>,
+}
+
+/// Imports the highways and railways of the OSM data inside the bounding box,
+/// and optionally the building footprints as houses.
+/// The center of the bounding box is placed at the center of the terrain.
+/// Returns the buildings that were created.
+pub fn load_osm(map: &mut Map, data: &OsmData, bbox: OsmBBox, buildings: bool) -> Vec<BuildingID> {
+    let time = std::time::Instant::now();
+
+    let (clat, clon) = bbox.center();
+    let origin =
+        vec2(map.terrain.width as f32, map.terrain.height as f32) * CHUNK_SIZE as f32 * 0.5;
+    let lon_scale = METERS_PER_DEGREE * clat.to_radians().cos();
+    let positions: BTreeMap<i64, Vec2> = data
+        .nodes
+        .iter()
+        .filter(|&(_, &latlon)| bbox.contains(latlon))
+        .map(|(&id, &(lat, lon))| {
+            let p = vec2(
+                ((lon - clon) * lon_scale) as f32,
+                ((lat - clat) * METERS_PER_DEGREE) as f32,
+            );
+            (id, origin + p)
+        })
+        .collect();
+
+    let roads: Vec<(&OsmWay, LanePattern, bool)> = data
+        .ways
+        .iter()
+        .filter_map(|w| {
+            let (pat, reversed) = infer_pattern(w)?;
+            Some((w, pat, reversed))
+        })
+        .collect();
+
+    // Nodes shared by several ways are junctions and must become intersections
+    let mut uses: BTreeMap<i64, u32> = BTreeMap::new();
+    for (way, _, _) in &roads {
+        for n in &way.nodes {
+            *uses.entry(*n).or_default() += 1;
+        }
+    }
+
+    let mut node_inters = BTreeMap::new();
+    let mut n_roads = 0;
+
+    for (way, pat, reversed) in &roads {
+        // Split the way where it leaves the bounding box
+        for run in way
+            .nodes
+            .split(|n| !positions.contains_key(n))
+            .filter(|run| run.len() >= 2)
+        {
+            let mut start = 0;
+            for i in 1..run.len() {
+                let is_junction = uses.get(&run[i]).copied().unwrap_or(0) > 1;
+                if i != run.len() - 1 && !is_junction {
+                    continue;
+                }
+
+                let nodes = &run[start..=i];
+                let points: Vec<Vec2> = nodes.iter().map(|n| positions[n]).collect();
+                let kept = simplify(&points, SIMPLIFY_TOLERANCE);
+
+                for pair in kept.windows(2) {
+                    let (a, b) = (pair[0], pair[1]);
+                    let src = unwrap_cont!(node_intersection(
+                        map,
+                        &mut node_inters,
+                        nodes[a],
+                        points[a]
+                    ));
+                    let dst = unwrap_cont!(node_intersection(
+                        map,
+                        &mut node_inters,
+                        nodes[b],
+                        points[b]
+                    ));
+                    let (src, dst) = if *reversed { (dst, src) } else { (src, dst) };
+                    if src == dst
+                        || map.find_road(src, dst).is_some()
+                        || map.find_road(dst, src).is_some()
+                    {
+                        continue;
+                    }
+                    if map
+                        .connect(src, dst, pat, RoadSegmentKind::Straight)
+                        .is_some()
+                    {
+                        n_roads += 1;
+                    }
+                }
+                start = i;
+            }
+        }
+    }
+
+    let mut built = vec![];
+    if buildings {
+        for way in data.ways.iter().filter(|w| w.tag("building").is_some()) {
+            let points: Option<Vec<Vec2>> = way
+                .nodes
+                .iter()
+                .map(|n| positions.get(n).copied())
+                .collect();
+            let obb = unwrap_cont!(points.as_deref().and_then(footprint_obb));
+            if let Some(id) =
+                map.build_special_building(&obb, BuildingKind::House, BuildingGen::House, &[])
+            {
+                built.push(id);
+            }
+        }
+    }
+
+    info!(
+        "loading osm data took {}ms: {} roads and {} buildings",
+        time.elapsed().as_secs_f32() * 1000.0,
+        n_roads,
+        built.len()
+    );
+
+    built
+}
+
+/// Reads an OSM XML file, keeping only the nodes inside the bounding box
+pub fn parse_osm(path: &str, bbox: OsmBBox) -> Result<OsmData, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+
+    let mut data = OsmData::default();
+    let mut cur_way: Option<OsmWay> = None;
+
+    for ev in EventReader::new(BufReader::new(file)) {
+        match ev.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attr = |k: &str| {
+                    attributes
+                        .iter()
+                        .find(|a| a.name.local_name == k)
+                        .map(|a| a.value.as_str())
+                };
+
+                match name.local_name.as_str() {
+                    "node" => {
+                        let id = attr("id").and_then(|v| v.parse().ok());
+                        let lat = attr("lat").and_then(|v| v.parse().ok());
+                        let lon = attr("lon").and_then(|v| v.parse().ok());
+                        if let (Some(id), Some(lat), Some(lon)) = (id, lat, lon) {
+                            if bbox.contains((lat, lon)) {
+                                data.nodes.insert(id, (lat, lon));
+                            }
+                        }
+                    }
+                    "way" => cur_way = Some(OsmWay::default()),
+                    "nd" => {
+                        let r = attr("ref").and_then(|v| v.parse().ok());
+                        if let (Some(way), Some(r)) = (&mut cur_way, r) {
+                            way.nodes.push(r);
+                        }
+                    }
+                    "tag" => {
+                        if let (Some(way), Some(k), Some(v)) = (&mut cur_way, attr("k"), attr("v"))
+                        {
+                            way.tags.insert(k.to_string(), v.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::EndElement { name } if name.local_name == "way" => {
+                if let Some(way) = cur_way.take() {
+                    data.ways.push(way);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(data)
+}
+
+/// Returns the lane pattern of the way and whether it goes against the order of its nodes,
+/// or None if it's not a road or a railway.
+/// Tunnels and bridges are skipped as they would cross the roads below them at grade.
+fn infer_pattern(way: &OsmWay) -> Option<(LanePattern, bool)> {
+    let is = |k: &str| way.tag(k).map_or(false, |t| t != "no");
+    if is("tunnel") || is("bridge") || way.tag("area") == Some("yes") {
+        return None;
+    }
+
+    if let Some("rail" | "light_rail") = way.tag("railway") {
+        let pat = LanePatternBuilder::new()
+            .rail(true)
+            .sidewalks(false)
+            .parking(false)
+            .speed_limit(30.0)
+            .build();
+        return Some((pat, false));
+    }
+
+    // (lanes per direction, speed limit in m/s, sidewalks, parking, implied one way)
+    let (lanes, speed, sidewalks, parking, implied_oneway) = match way.tag("highway")? {
+        "motorway" => (2, 30.0, false, false, true),
+        "motorway_link" => (1, 20.0, false, false, true),
+        "trunk" | "trunk_link" => (2, 25.0, false, false, false),
+        "primary" | "primary_link" => (2, 14.0, true, false, false),
+        "secondary" | "secondary_link" => (1, 14.0, true, true, false),
+        "tertiary" | "tertiary_link" => (1, 12.0, true, true, false),
+        "residential" | "unclassified" | "living_street" => (1, 9.0, true, true, false),
+        "service" => (1, 6.0, true, false, false),
+        "pedestrian" | "footway" => (0, 9.0, true, false, false),
+        _ => return None,
+    };
+
+    let (one_way, reversed) = match way.tag("oneway") {
+        Some("yes" | "true" | "1") => (true, false),
+        Some("-1" | "reverse") => (true, true),
+        Some("no" | "false" | "0") => (false, false),
+        _ => (implied_oneway, false),
+    };
+
+    let lanes = match way.tag("lanes").and_then(|l| l.parse::<u32>().ok()) {
+        Some(total) if one_way => total.max(1),
+        Some(total) => ((total + 1) / 2).max(1),
+        None => lanes,
+    };
+
+    let sidewalks = match way.tag("sidewalk") {
+        Some("no" | "none") => false,
+        Some("both" | "left" | "right" | "yes" | "separate") => true,
+        _ => sidewalks,
+    };
+
+    let speed = way
+        .tag("maxspeed")
+        .and_then(parse_maxspeed)
+        .unwrap_or(speed);
+
+    let pat = LanePatternBuilder::new()
+        .n_lanes(lanes)
+        .one_way(one_way)
+        .sidewalks(sidewalks)
+        .parking(parking)
+        .speed_limit(speed.clamp(4.0, 40.0))
+        .build();
+    Some((pat, reversed))
+}
+
+/// Parses "50", "50 km/h" or "30 mph" into m/s
+fn parse_maxspeed(v: &str) -> Option<f32> {
+    let num: String = v
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    let num: f32 = num.parse().ok()?;
+    if v.contains("mph") {
+        return Some(num * 1.609 / 3.6);
+    }
+    Some(num / 3.6)
+}
+
+/// Returns the node or a close existing intersection, or creates one
+fn node_intersection(
+    map: &mut Map,
+    node_inters: &mut BTreeMap<i64, IntersectionID>,
+    node: i64,
+    pos: Vec2,
+) -> Option<IntersectionID> {
+    if let Some(&id) = node_inters.get(&node) {
+        if map.intersections.contains_key(id) {
+            return Some(id);
+        }
+    }
+
+    let close = map
+        .spatial_map
+        .query_around(pos, MERGE_DIST, ProjectFilter::INTER)
+        .find_map(|k| match k {
+            ProjectKind::Inter(id) => Some(id),
+            _ => None,
+        });
+
+    let id = match close {
+        Some(id) => id,
+        None => {
+            let z = map.terrain.road_height(pos)?;
+            map.add_intersection(pos.z(z))
+        }
+    };
+    node_inters.insert(node, id);
+    Some(id)
+}
+
+/// Ramer-Douglas-Peucker, returns the indices of the points to keep
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<usize> {
+    fn rec(points: &[Vec2], from: usize, to: usize, tolerance: f32, kept: &mut Vec<usize>) {
+        let seg = Segment::new(points[from], points[to]);
+        let far = (from + 1..to)
+            .map(|i| (i, seg.project(points[i]).distance(points[i])))
+            .fold(None, |best: Option<(usize, f32)>, (i, d)| match best {
+                Some((_, bd)) if bd >= d => best,
+                _ => Some((i, d)),
+            });
+
+        if let Some((i, d)) = far {
+            if d > tolerance {
+                rec(points, from, i, tolerance, kept);
+                kept.push(i);
+                rec(points, i, to, tolerance, kept);
+            }
+        }
+    }
+
+    if points.len() < 2 {
+        return (0..points.len()).collect();
+    }
+    let mut kept = vec![0];
+    rec(points, 0, points.len() - 1, tolerance, &mut kept);
+    kept.push(points.len() - 1);
+    kept
+}
+
+/// Square box centered on the footprint and aligned with its longest side
+fn footprint_obb(points: &[Vec2]) -> Option<OBB> {
+    if points.len() < 4 || points.first() != points.last() {
+        return None;
+    }
+
+    let axis = points
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .max_by_key(|v| OrderedFloat(v.magnitude2()))?
+        .try_normalize()?;
+    let perp = axis.perpendicular();
+
+    let (mut min, mut max) = (
+        vec2(f32::INFINITY, f32::INFINITY),
+        vec2(f32::NEG_INFINITY, f32::NEG_INFINITY),
+    );
+    for p in points {
+        let local = vec2(p.dot(axis), p.dot(perp));
+        min = min.min(local);
+        max = max.max(local);
+    }
+
+    let size = (max - min).x.max((max - min).y);
+    if size < 5.0 {
+        return None;
+    }
+    let c = (min + max) * 0.5;
+    Some(OBB::new(axis * c.x + perp * c.y, axis, size, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{footprint_obb, infer_pattern, parse_maxspeed, simplify, OsmWay};
+    use crate::map::LaneKind;
+    use geom::{vec2, Vec2};
+
+    fn way(tags: &[(&str, &str)]) -> OsmWay {
+        OsmWay {
+            nodes: vec![],
+            tags: tags
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn maxspeed() {
+        let cases: &[(&str, Option<f32>)] = &[
+            ("50", Some(50.0 / 3.6)),
+            ("50 km/h", Some(50.0 / 3.6)),
+            ("30 mph", Some(30.0 * 1.609 / 3.6)),
+            ("12.5", Some(12.5 / 3.6)),
+            ("none", None),
+            ("signals", None),
+            ("", None),
+        ];
+        for &(v, expected) in cases {
+            match (parse_maxspeed(v), expected) {
+                (Some(got), Some(expected)) => assert!((got - expected).abs() < 1e-4, "{}", v),
+                (got, expected) => assert_eq!(got, expected, "{}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        // tags, (forward driving lanes, backward driving lanes, reversed)
+        let cases: &[(&[(&str, &str)], Option<(usize, usize, bool)>)] = &[
+            (&[("highway", "residential")], Some((1, 1, false))),
+            (
+                &[("highway", "residential"), ("oneway", "yes")],
+                Some((1, 0, false)),
+            ),
+            (
+                &[("highway", "residential"), ("oneway", "-1")],
+                Some((1, 0, true)),
+            ),
+            (&[("highway", "motorway")], Some((2, 0, false))),
+            (
+                &[("highway", "motorway"), ("oneway", "no")],
+                Some((2, 2, false)),
+            ),
+            (
+                &[("highway", "primary"), ("lanes", "4")],
+                Some((2, 2, false)),
+            ),
+            (
+                &[("highway", "primary"), ("lanes", "3")],
+                Some((2, 2, false)),
+            ),
+            (
+                &[("highway", "primary"), ("lanes", "3"), ("oneway", "yes")],
+                Some((3, 0, false)),
+            ),
+            (
+                &[("highway", "tertiary"), ("lanes", "many")],
+                Some((1, 1, false)),
+            ),
+            (&[("highway", "footway")], Some((0, 0, false))),
+            (
+                &[("highway", "residential"), ("tunnel", "no")],
+                Some((1, 1, false)),
+            ),
+            (&[("highway", "residential"), ("tunnel", "yes")], None),
+            (
+                &[("highway", "residential"), ("tunnel", "building_passage")],
+                None,
+            ),
+            (&[("highway", "residential"), ("bridge", "yes")], None),
+            (&[("highway", "pedestrian"), ("area", "yes")], None),
+            (&[("highway", "proposed")], None),
+            (&[("building", "yes")], None),
+            (&[], None),
+        ];
+
+        let driving = |lanes: &[(LaneKind, f32)]| {
+            lanes
+                .iter()
+                .filter(|(k, _)| matches!(k, LaneKind::Driving))
+                .count()
+        };
+
+        for &(tags, expected) in cases {
+            let got = infer_pattern(&way(tags)).map(|(pat, reversed)| {
+                (
+                    driving(&pat.lanes_forward),
+                    driving(&pat.lanes_backward),
+                    reversed,
+                )
+            });
+            assert_eq!(got, expected, "{:?}", tags);
+        }
+    }
+
+    #[test]
+    fn pattern_details() {
+        let speed = |tags: &[(&str, &str)]| infer_pattern(&way(tags)).unwrap().0.lanes_forward[0].1;
+        assert_eq!(speed(&[("highway", "residential")]), 9.0);
+        assert!(
+            (speed(&[("highway", "residential"), ("maxspeed", "30 mph")]) - 13.41).abs() < 0.01
+        );
+        assert_eq!(speed(&[("highway", "motorway"), ("maxspeed", "200")]), 40.0);
+        assert_eq!(speed(&[("highway", "service"), ("maxspeed", "5 mph")]), 4.0);
+
+        let has_walking = |tags: &[(&str, &str)]| {
+            infer_pattern(&way(tags))
+                .unwrap()
+                .0
+                .lanes()
+                .any(|(k, _, _)| matches!(k, LaneKind::Walking))
+        };
+        assert!(has_walking(&[("highway", "residential")]));
+        assert!(!has_walking(&[
+            ("highway", "residential"),
+            ("sidewalk", "no")
+        ]));
+        assert!(has_walking(&[("highway", "trunk"), ("sidewalk", "both")]));
+        assert!(!has_walking(&[("highway", "trunk")]));
+
+        let (rail, _) = infer_pattern(&way(&[("railway", "rail")])).unwrap();
+        assert!(rail.lanes().all(|(k, _, _)| matches!(k, LaneKind::Rail)));
+        assert_eq!(rail.lanes().count(), 2);
+    }
+
+    #[test]
+    fn simplify_keeps_the_corners() {
+        let p = |x: f32, y: f32| vec2(x, y);
+        assert_eq!(simplify(&[], 5.0), Vec::<usize>::new());
+        assert_eq!(simplify(&[p(0.0, 0.0)], 5.0), vec![0]);
+        assert_eq!(simplify(&[p(0.0, 0.0), p(10.0, 0.0)], 5.0), vec![0, 1]);
+
+        let noisy: Vec<Vec2> = (0..=10)
+            .map(|i| p(i as f32 * 10.0, if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .collect();
+        assert_eq!(simplify(&noisy, 5.0), vec![0, 10]);
+
+        let corner = [
+            p(0.0, 0.0),
+            p(50.0, 1.0),
+            p(100.0, 0.0),
+            p(100.0, 50.0),
+            p(100.0, 100.0),
+        ];
+        assert_eq!(simplify(&corner, 5.0), vec![0, 2, 4]);
+        assert_eq!(simplify(&corner, 0.5), vec![0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn footprints() {
+        let rect = |w: f32, h: f32| {
+            let o = vec2(100.0, 50.0);
+            vec![o, o + vec2(w, 0.0), o + vec2(w, h), o + vec2(0.0, h), o]
+        };
+
+        let obb = footprint_obb(&rect(20.0, 10.0)).unwrap();
+        assert!(obb.center().is_close(vec2(110.0, 55.0), 1e-3));
+        let [a, b] = obb.axis();
+        assert!((a.magnitude() - 20.0).abs() < 1e-3);
+        assert!((b.magnitude() - 20.0).abs() < 1e-3);
+        assert!(b.y.abs() < 1e-3);
+
+        // not closed
+        assert!(footprint_obb(&rect(20.0, 10.0)[..4]).is_none());
+        // too few points
+        assert!(footprint_obb(&[vec2(0.0, 0.0), vec2(10.0, 0.0), vec2(0.0, 0.0)]).is_none());
+        // too small
+        assert!(footprint_obb(&rect(3.0, 2.0)).is_none());
+    }
+}
@@ -1,9 +1,9 @@
 #![allow(clippy::indexing_slicing)]
 
 use crate::map::{IntersectionID, LanePatternBuilder, Map, RoadSegmentKind};
-use common::FastMap;
 use flat_spatial::Grid;
 use geom::{vec2, vec3, Vec2};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
@@ -83,7 +83,7 @@ pub fn load_parismap(map: &mut Map) {
         g.insert(pos, id);
     }
 
-    let mut edges = FastMap::default();
+    let mut edges = BTreeMap::new();
 
     //Parse junctions
     for _ in 0..n_roads {
@@ -245,11 +245,61 @@ pub fn load_testfield(map: &mut Map, pos: Vec2, size: u32, spacing: f32) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::map::pathfinding_crate::directed::dijkstra::dijkstra;
+    use crate::map::{
+        LaneID, LaneKind, PathKind, Pathfinder, RoutingLandmarks, Traversable, TraverseDirection,
+        TraverseKind,
+    };
+    use ordered_float::OrderedFloat;
+
+    fn vehicle_lanes(m: &Map) -> Vec<LaneID> {
+        m.lanes()
+            .values()
+            .filter(|l| l.kind.vehicles())
+            .map(|l| l.id)
+            .collect()
+    }
+
+    /// Cost of the route found by the vehicle pathfinder
+    fn route_cost(m: &Map, start: LaneID, end: LaneID) -> Option<f32> {
+        let start = Traversable::new(TraverseKind::Lane(start), TraverseDirection::Forward);
+        let path = PathKind::Vehicle.path(m, start, end)?;
+        Some(
+            path.iter()
+                .skip(1)
+                .filter_map(|t| match t.kind {
+                    TraverseKind::Lane(l) => m.lanes().get(l),
+                    TraverseKind::Turn(_) => None,
+                })
+                .map(|l| m.travel_times().cost(l))
+                .sum(),
+        )
+    }
+
+    /// Exact cost over the same graph and costs as the vehicle pathfinder, without any heuristic
+    fn exact_cost(m: &Map, start: LaneID, end: LaneID) -> Option<f32> {
+        let successors = |&p: &LaneID| {
+            let inter = m.lanes().get(p).and_then(|l| m.intersections().get(l.dst));
+            inter
+                .into_iter()
+                .flat_map(move |inter| inter.turns_from(p))
+                .filter_map(|(t, _)| {
+                    let lane = m.lanes().get(t.dst)?;
+                    if lane.kind == LaneKind::Biking {
+                        return None;
+                    }
+                    Some((t.dst, OrderedFloat(m.travel_times().cost(lane))))
+                })
+                .collect::<Vec<_>>()
+        };
+        Some(dijkstra(&start, successors, |&p| p == end)?.1 .0)
+    }
+
     #[test]
     fn testfield_valid() {
         let mut m = Map::empty();
         load_testfield(&mut m, Vec2::ZERO, 10, 100.0);
-        m.check_invariants();
+        assert_eq!(m.invariant_violations(), vec![]);
     }
 
     #[test]
@@ -258,4 +308,96 @@ mod tests {
         load_parismap(&mut m);
         m.check_invariants();
     }
+
+    #[test]
+    fn testfield_landmark_routes_are_shortest() {
+        let mut m = Map::empty();
+        load_testfield(&mut m, Vec2::ZERO, 5, 100.0);
+        m.update_landmarks();
+        assert!(m.landmarks.vehicle.is_valid(&m));
+
+        let lanes = vehicle_lanes(&m);
+        let ends = lanes.iter().rev().step_by(7);
+        for (&start, &end) in lanes.iter().step_by(5).zip(ends) {
+            if start == end {
+                continue;
+            }
+            let alt = route_cost(&m, start, end);
+            let exact = exact_cost(&m, start, end);
+            assert_eq!(alt.is_some(), exact.is_some());
+            if let (Some(alt), Some(exact)) = (alt, exact) {
+                assert!((alt - exact).abs() < 0.01, "{} != {}", alt, exact);
+            }
+        }
+    }
+
+    /// Compares vehicle routing with and without landmarks on the Paris map,
+    /// against the exact costs given by a Dijkstra search.
+    /// Run with `cargo test --release parismap_routing_bench -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn parismap_routing_bench() {
+        use std::time::Instant;
+
+        let mut m = Map::empty();
+        load_parismap(&mut m);
+
+        let t = Instant::now();
+        m.update_landmarks();
+        println!("landmarks built in {:?}", t.elapsed());
+
+        let lanes = vehicle_lanes(&m);
+        let n = lanes.len() as f32;
+        let pairs: Vec<(LaneID, LaneID)> = (0..300u32)
+            .map(|i| {
+                (
+                    lanes[(common::rand::randu(2 * i) * n) as usize],
+                    lanes[(common::rand::randu(2 * i + 1) * n) as usize],
+                )
+            })
+            .filter(|(start, end)| start != end)
+            .collect();
+
+        let route_costs = |m: &Map| -> Vec<Option<f32>> {
+            pairs
+                .iter()
+                .map(|&(start, end)| route_cost(m, start, end))
+                .collect()
+        };
+
+        let t = Instant::now();
+        let with_landmarks = route_costs(&m);
+        println!("{} routes with landmarks: {:?}", pairs.len(), t.elapsed());
+
+        m.landmarks = RoutingLandmarks::default();
+        let t = Instant::now();
+        let without_landmarks = route_costs(&m);
+        println!(
+            "{} routes without landmarks: {:?}",
+            pairs.len(),
+            t.elapsed()
+        );
+
+        let exact: Vec<Option<f32>> = pairs
+            .iter()
+            .map(|&(start, end)| exact_cost(&m, start, end))
+            .collect();
+
+        let mut n_inexact = 0;
+        for ((a, b), e) in with_landmarks.iter().zip(&without_landmarks).zip(&exact) {
+            assert_eq!(a.is_some(), e.is_some());
+            assert_eq!(b.is_some(), e.is_some());
+            if let (Some(a), Some(b), Some(e)) = (a, b, e) {
+                // Landmarks give an admissible heuristic so their paths are optimal
+                assert!((a - e).abs() < 0.01, "{} != {}", a, e);
+                if *b > e + 0.01 {
+                    n_inexact += 1;
+                }
+            }
+        }
+        println!(
+            "{} routes without landmarks are longer than the shortest one",
+            n_inexact
+        );
+    }
 }
@@ -1,5 +1,6 @@
 use crate::map::{
     Buildings, Intersections, Lanes, Lots, Map, ParkingSpots, Roads, SpatialMap, Terrain,
+    TravelTimes,
 };
 use serde::{Deserialize, Serialize};
 use std::num::Wrapping;
@@ -13,6 +14,7 @@ pub(crate) struct SerializedMap {
     pub parking: ParkingSpots,
     pub lots: Lots,
     pub terrain: Terrain,
+    pub travel_times: TravelTimes,
     pub dirt_id: u32,
 }
 
@@ -26,6 +28,7 @@ impl From<&Map> for SerializedMap {
             parking: m.parking.clone(),
             lots: m.lots.clone(),
             terrain: m.terrain.clone(),
+            travel_times: m.travel_times.clone(),
             dirt_id: m.dirt_id.0,
         }
     }
@@ -43,6 +46,8 @@ impl From<SerializedMap> for Map {
             lots: sel.lots,
             parking: sel.parking,
             terrain: sel.terrain,
+            travel_times: sel.travel_times,
+            landmarks: Default::default(),
             dirt_id: Wrapping(sel.dirt_id),
         }
     }
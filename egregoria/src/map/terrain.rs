@@ -1,15 +1,24 @@
 use crate::map::procgen::heightmap;
 use crate::map::procgen::heightmap::tree_density;
-use geom::{vec2, Radians, Vec2, AABB};
+use geom::{vec2, PolyLine, Radians, Vec2, AABB, OBB};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::num::Wrapping;
 
 pub const CHUNK_SIZE: u32 = 1024;
 pub const CHUNK_RESOLUTION: usize = 32;
 pub const CELL_SIZE: f32 = CHUNK_SIZE as f32 / CHUNK_RESOLUTION as f32;
 
+/// Ground under this height is covered by water, matches the sea color of the terrain shader
+pub const WATER_LEVEL: f32 = -20.0;
+/// Height of roads above the water level when crossing it
+pub const BRIDGE_CLEARANCE: f32 = 5.0;
+/// Depth below the water level of a carved river bed
+const RIVER_DEPTH: f32 = 5.0;
+/// Width of the slope between a river bed and the surrounding ground
+const RIVER_BANK: f32 = 3.0 * CELL_SIZE;
+
 #[derive(Clone)]
 pub struct Chunk {
     pub trees: Vec<Tree>,
@@ -37,9 +46,22 @@ pub struct Tree {
 
 pub type ChunkID = (u32, u32);
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TerraformKind {
+    Raise,
+    Lower,
+    /// Flatten towards the given height
+    Flatten(f32),
+    /// Average each cell with its neighbors
+    Smooth,
+}
+
+/// Height change of one terrain cell, as (chunk, y, x, delta)
+type CellDelta = (ChunkID, usize, usize, f32);
+
 #[derive(Clone)]
 pub struct Terrain {
-    pub chunks: HashMap<ChunkID, Chunk>,
+    pub chunks: BTreeMap<ChunkID, Chunk>,
     pub dirt_id: Wrapping<u32>,
     pub width: u32,
     pub height: u32,
@@ -91,6 +113,261 @@ impl Terrain {
         self.dirt_id += Wrapping(v as u32)
     }
 
+    /// Edits the heights of the cells in the circle, with a linear falloff to the border.
+    /// `amount` is in meters for Raise/Lower and is a blend factor in 0..1 for Flatten/Smooth.
+    /// Returns the earthworks volume in cubic meters.
+    pub fn terraform(
+        &mut self,
+        kind: TerraformKind,
+        center: Vec2,
+        radius: f32,
+        amount: f32,
+    ) -> f32 {
+        let deltas = self.terraform_deltas(kind, center, radius, amount);
+        self.apply_deltas(deltas)
+    }
+
+    /// Digs a river bed below the water level along the path.
+    /// Returns the earthworks volume in cubic meters.
+    pub fn carve_river(&mut self, path: &PolyLine, width: f32) -> f32 {
+        let deltas = self.river_deltas(path, width);
+        self.apply_deltas(deltas)
+    }
+
+    /// Earthworks volume in cubic meters that `carve_river` would move, without editing anything
+    pub fn river_volume(&self, path: &PolyLine, width: f32) -> f32 {
+        self.river_deltas(path, width)
+            .into_iter()
+            .map(|(_, _, _, delta)| delta.abs() * CELL_SIZE * CELL_SIZE)
+            .sum()
+    }
+
+    fn river_deltas(&self, path: &PolyLine, width: f32) -> Vec<CellDelta> {
+        let half = width * 0.5;
+        let bottom = WATER_LEVEL - RIVER_DEPTH;
+        let bbox = path.bbox().expand(half + RIVER_BANK);
+
+        let mut deltas = vec![];
+        for id in self.chunks_iter(bbox) {
+            let chunk = unwrap_cont!(self.chunks.get(&id));
+            let offchunk = vec2(id.0 as f32, id.1 as f32) * CHUNK_SIZE as f32;
+
+            for (y, l) in chunk.heights.iter().enumerate() {
+                for (x, &h) in l.iter().enumerate() {
+                    let pos = offchunk + vec2(x as f32, y as f32) * CELL_SIZE;
+                    let dist = path.project_dist(pos);
+                    if dist > half + RIVER_BANK {
+                        continue;
+                    }
+                    let t = ((dist - half) / RIVER_BANK).clamp(0.0, 1.0);
+                    let target = bottom + (h - bottom) * t;
+                    if target < h {
+                        deltas.push((id, y, x, target - h));
+                    }
+                }
+            }
+        }
+        deltas
+    }
+
+    /// Returns the earthworks volume
+    fn apply_deltas(&mut self, deltas: Vec<CellDelta>) -> f32 {
+        let mut volume = 0.0;
+        let mut edited = vec![];
+        for (id, y, x, delta) in deltas {
+            let chunk = unwrap_cont!(self.chunks.get_mut(&id));
+            let h = unwrap_cont!(chunk.heights.get_mut(y).and_then(|l| l.get_mut(x)));
+            *h += delta;
+            chunk.dirt_id += Wrapping(1);
+            volume += delta.abs() * CELL_SIZE * CELL_SIZE;
+            edited.push(id);
+        }
+
+        if volume > 0.0 {
+            self.dirt_id += Wrapping(1);
+        }
+
+        edited.sort_unstable();
+        edited.dedup();
+        for id in edited {
+            let chunk = unwrap_cont!(self.chunks.get_mut(&id));
+            let heights = chunk.heights;
+            chunk.trees.retain(|t| !chunk_is_water(&heights, id, t.pos));
+        }
+
+        volume
+    }
+
+    /// Adds trees at the given positions, skipping the ones in the water or out of the terrain.
+    /// Returns the number of trees planted.
+    pub fn add_trees(&mut self, positions: impl IntoIterator<Item = Vec2>) -> usize {
+        let mut n = 0;
+        for pos in positions {
+            if self.is_water(pos) || pos.x < 0.0 || pos.y < 0.0 {
+                continue;
+            }
+            let chunk = unwrap_cont!(self.chunks.get_mut(&Self::cell(pos)));
+            chunk.trees.push(Tree::new(pos));
+            chunk.dirt_id += Wrapping(1);
+            n += 1;
+        }
+        self.dirt_id += Wrapping((n > 0) as u32);
+        n
+    }
+
+    pub fn trees_near(&self, center: Vec2, radius: f32) -> impl Iterator<Item = &Tree> + '_ {
+        let bbox = AABB::new(center - vec2(radius, radius), center + vec2(radius, radius));
+        self.chunks_iter(bbox)
+            .filter_map(move |id| self.chunks.get(&id))
+            .flat_map(|chunk| chunk.trees.iter())
+            .filter(move |t| t.pos.is_close(center, radius))
+    }
+
+    /// Removes the tree closest to `center` within `radius`, returns whether one was found
+    pub fn cut_tree_near(&mut self, center: Vec2, radius: f32) -> bool {
+        let bbox = AABB::new(center - vec2(radius, radius), center + vec2(radius, radius));
+        let mut best: Option<(ChunkID, usize, f32)> = None;
+        for id in self.chunks_iter(bbox) {
+            let chunk = unwrap_cont!(self.chunks.get(&id));
+            for (i, t) in chunk.trees.iter().enumerate() {
+                let d = t.pos.distance(center);
+                if d <= radius && best.map_or(true, |(_, _, bd)| d < bd) {
+                    best = Some((id, i, d));
+                }
+            }
+        }
+
+        let (id, i, _) = unwrap_or!(best, return false);
+        let chunk = unwrap_or!(self.chunks.get_mut(&id), return false);
+        chunk.trees.swap_remove(i);
+        chunk.dirt_id += Wrapping(1);
+        self.dirt_id += Wrapping(1);
+        true
+    }
+
+    /// Candidate tree positions of a forest with `density` trees per hectare.
+    /// They are placed on a global jittered grid so painting twice doesn't add trees.
+    pub fn forest_positions(&self, center: Vec2, radius: f32, density: f32) -> Vec<Vec2> {
+        if density <= 0.0 {
+            return vec![];
+        }
+        let spacing = (10000.0 / density).sqrt();
+
+        let min = ((center - vec2(radius, radius)) / spacing).floor();
+        let max = ((center + vec2(radius, radius)) / spacing).floor();
+
+        let mut positions = vec![];
+        for y in min.y as i32..=max.y as i32 {
+            for x in min.x as i32..=max.x as i32 {
+                let jitter = vec2(
+                    common::rand::rand3(x as f32, y as f32, 1.0),
+                    common::rand::rand3(x as f32, y as f32, 2.0),
+                );
+                let pos = (vec2(x as f32, y as f32) + jitter) * spacing;
+                if !pos.is_close(center, radius) {
+                    continue;
+                }
+                if self.trees_near(pos, spacing * 0.5).next().is_some() {
+                    continue;
+                }
+                positions.push(pos);
+            }
+        }
+        positions
+    }
+
+    pub fn is_water(&self, p: Vec2) -> bool {
+        self.height(p).map_or(false, |h| h < WATER_LEVEL)
+    }
+
+    /// Whether any corner or the center of the shape is in the water
+    pub fn obb_in_water(&self, obb: &OBB) -> bool {
+        obb.corners
+            .iter()
+            .copied()
+            .chain(std::iter::once(obb.center()))
+            .any(|p| self.is_water(p))
+    }
+
+    /// Height of a road at this position, above the water when crossing it
+    pub fn road_height(&self, p: Vec2) -> Option<f32> {
+        let h = self.height(p)?;
+        if h < WATER_LEVEL {
+            return Some(WATER_LEVEL + BRIDGE_CLEARANCE);
+        }
+        Some(h)
+    }
+
+    /// Earthworks volume in cubic meters that `terraform` would move, without editing anything
+    pub fn terraform_volume(
+        &self,
+        kind: TerraformKind,
+        center: Vec2,
+        radius: f32,
+        amount: f32,
+    ) -> f32 {
+        self.terraform_deltas(kind, center, radius, amount)
+            .into_iter()
+            .map(|(_, _, _, delta)| delta.abs() * CELL_SIZE * CELL_SIZE)
+            .sum()
+    }
+
+    fn terraform_deltas(
+        &self,
+        kind: TerraformKind,
+        center: Vec2,
+        radius: f32,
+        amount: f32,
+    ) -> Vec<CellDelta> {
+        let mut deltas = vec![];
+        if radius <= 0.0 {
+            return deltas;
+        }
+        let bbox = AABB::new(center - vec2(radius, radius), center + vec2(radius, radius));
+
+        for id in self.chunks_iter(bbox) {
+            let chunk = unwrap_cont!(self.chunks.get(&id));
+            let offchunk = vec2(id.0 as f32, id.1 as f32) * CHUNK_SIZE as f32;
+
+            for (y, l) in chunk.heights.iter().enumerate() {
+                for (x, &h) in l.iter().enumerate() {
+                    let pos = offchunk + vec2(x as f32, y as f32) * CELL_SIZE;
+                    let dist = pos.distance(center);
+                    if dist > radius {
+                        continue;
+                    }
+                    let falloff = 1.0 - dist / radius;
+
+                    let delta = match kind {
+                        TerraformKind::Raise => amount * falloff,
+                        TerraformKind::Lower => -amount * falloff,
+                        TerraformKind::Flatten(target) => {
+                            (target - h) * (amount * falloff).clamp(0.0, 1.0)
+                        }
+                        TerraformKind::Smooth => {
+                            let neighbors = [
+                                pos + Vec2::x(CELL_SIZE),
+                                pos - Vec2::x(CELL_SIZE),
+                                pos + Vec2::y(CELL_SIZE),
+                                pos - Vec2::y(CELL_SIZE),
+                            ];
+                            let (sum, n) = neighbors
+                                .iter()
+                                .filter_map(|&p| self.height_nearest(p))
+                                .fold((h, 1.0), |(sum, n), nh| (sum + nh, n + 1.0));
+                            (sum / n - h) * (amount * falloff).clamp(0.0, 1.0)
+                        }
+                    };
+
+                    if delta != 0.0 {
+                        deltas.push((id, y, x, delta));
+                    }
+                }
+            }
+        }
+        deltas
+    }
+
     pub fn cell(p: Vec2) -> (u32, u32) {
         if p.x < 0.0 || p.y < 0.0 {
             return (0, 0);
@@ -154,6 +431,17 @@ impl Terrain {
             }
         }
 
+        chunk.trees = Self::generate_trees((x, y), &chunk.heights);
+
+        Some(chunk)
+    }
+
+    /// Procedural forests following the tree density noise, out of the water
+    pub(crate) fn generate_trees(
+        (x, y): ChunkID,
+        heights: &[[f32; CHUNK_RESOLUTION]; CHUNK_RESOLUTION],
+    ) -> Vec<Tree> {
+        let mut trees = vec![];
         let rchunk = common::rand::rand2(x as f32, y as f32);
         let pchunk = CHUNK_SIZE as f32 * vec2(x as f32, y as f32);
 
@@ -174,16 +462,28 @@ impl Terrain {
 
                 let tdens = tree_density(sample);
 
-                if dens_test < tdens * 2.0 {
-                    chunk.trees.push(Tree::new(sample));
+                if dens_test < tdens * 2.0 && !chunk_is_water(heights, (x, y), sample) {
+                    trees.push(Tree::new(sample));
                 }
             }
         }
 
-        Some(chunk)
+        trees
     }
 }
 
+fn chunk_is_water(
+    heights: &[[f32; CHUNK_RESOLUTION]; CHUNK_RESOLUTION],
+    id: ChunkID,
+    p: Vec2,
+) -> bool {
+    let v = (p / CHUNK_SIZE as f32 - vec2(id.0 as f32, id.1 as f32)) * CHUNK_RESOLUTION as f32;
+    heights
+        .get(v.y as usize)
+        .and_then(|l| l.get(v.x as usize))
+        .map_or(false, |&h| h < WATER_LEVEL)
+}
+
 impl Tree {
     pub fn new(pos: Vec2) -> Self {
         let crand = common::rand::rand3(pos.x as f32, pos.y, 1.0);
@@ -293,3 +593,61 @@ impl From<&Terrain> for SerializedTerrain {
         t
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{TerraformKind, Terrain, CELL_SIZE, CHUNK_SIZE};
+    use geom::vec2;
+
+    #[test]
+    fn terraform_deltas_fall_off_to_the_border() {
+        let t = Terrain::new(2, 2);
+        let center = vec2(512.0, 512.0);
+        let (radius, amount) = (100.0, 2.0);
+
+        let raise = t.terraform_deltas(TerraformKind::Raise, center, radius, amount);
+        assert!(!raise.is_empty());
+        for &((cx, cy), y, x, delta) in &raise {
+            let pos = vec2(cx as f32, cy as f32) * CHUNK_SIZE as f32
+                + vec2(x as f32, y as f32) * CELL_SIZE;
+            let dist = pos.distance(center);
+            assert!(dist <= radius);
+            assert!((delta - amount * (1.0 - dist / radius)).abs() < 1e-4);
+        }
+        assert!(raise
+            .iter()
+            .any(|&(_, _, _, delta)| (delta - amount).abs() < 1e-4));
+
+        let lower = t.terraform_deltas(TerraformKind::Lower, center, radius, amount);
+        assert_eq!(lower.len(), raise.len());
+        for (l, r) in lower.iter().zip(&raise) {
+            assert_eq!(l.3, -r.3);
+        }
+
+        assert!(t
+            .terraform_deltas(TerraformKind::Raise, center, 0.0, amount)
+            .is_empty());
+    }
+
+    #[test]
+    fn terraform_moves_the_announced_volume() {
+        let mut t = Terrain::new(2, 2);
+        let center = vec2(512.0, 512.0);
+        let before = t.height_nearest(center).unwrap();
+
+        let volume = t.terraform_volume(TerraformKind::Raise, center, 100.0, 2.0);
+        assert!(volume > 0.0);
+        assert_eq!(
+            t.terraform(TerraformKind::Raise, center, 100.0, 2.0),
+            volume
+        );
+        assert!((t.height_nearest(center).unwrap() - before - 2.0).abs() < 1e-4);
+
+        // Flattening a single cell to the height it already has moves nothing
+        let h = t.height_nearest(center).unwrap();
+        assert_eq!(
+            t.terraform_volume(TerraformKind::Flatten(h), center, 10.0, 1.0),
+            0.0
+        );
+    }
+}
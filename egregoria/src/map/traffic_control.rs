@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TrafficBehavior {
     RED,
     ORANGE,
@@ -33,12 +33,36 @@ impl TrafficLightSchedule {
             offset,
         }
     }
+
+    /// Schedule of the pedestrians crossing the road controlled by this schedule:
+    /// they can walk while the vehicles are red, except for the last seconds
+    /// so that the crossing is clear when the vehicles get the green.
+    pub fn walk_phase(&self) -> Self {
+        let clearance = (self.red / 3).min(WALK_CLEARANCE);
+        Self {
+            period: self.period,
+            green: self.red - clearance,
+            orange: clearance,
+            red: self.green + self.orange,
+            // walking starts when the vehicles turn red, green + orange seconds later
+            offset: (self.offset + self.red) % self.period.max(1),
+        }
+    }
 }
 
+/// Seconds of flashing don't-walk before the vehicles get the green
+pub const WALK_CLEARANCE: usize = 4;
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum TrafficControl {
     Always,
     Light(TrafficLightSchedule),
+    /// Light driven by queue detection, its state is updated every tick by the traffic signals system.
+    /// `walk` is the signal of the crosswalks over the road.
+    Actuated {
+        vehicles: TrafficBehavior,
+        walk: TrafficBehavior,
+    },
     StopSign,
 }
 
@@ -52,7 +76,41 @@ impl TrafficControl {
     }
 
     pub fn is_light(&self) -> bool {
-        matches!(self, TrafficControl::Light(_))
+        matches!(
+            self,
+            TrafficControl::Light(_) | TrafficControl::Actuated { .. }
+        )
+    }
+
+    pub fn is_actuated(&self) -> bool {
+        matches!(self, TrafficControl::Actuated { .. })
+    }
+
+    /// Control of the crosswalks over a road whose incoming lanes are controlled by `self`.
+    /// Green means walk, orange means don't start crossing.
+    /// Crosswalks without lights are [`TrafficControl::Always`], pedestrians look for a gap.
+    pub fn walk_control(&self) -> TrafficControl {
+        match self {
+            TrafficControl::Light(schedule) => TrafficControl::Light(schedule.walk_phase()),
+            TrafficControl::Actuated { walk, .. } => TrafficControl::Actuated {
+                vehicles: *walk,
+                walk: *walk,
+            },
+            TrafficControl::Always | TrafficControl::StopSign => TrafficControl::Always,
+        }
+    }
+
+    /// Average time in seconds a vehicle arriving at a random moment waits here
+    pub fn expected_wait(&self) -> f32 {
+        match self {
+            TrafficControl::Always => 0.0,
+            TrafficControl::Light(schedule) => {
+                let stopped = (schedule.orange + schedule.red) as f32;
+                stopped * stopped / (2.0 * schedule.period as f32)
+            }
+            TrafficControl::Actuated { .. } => 5.0,
+            TrafficControl::StopSign => 2.0,
+        }
     }
 
     pub fn get_behavior(&self, seconds: u32) -> TrafficBehavior {
@@ -68,7 +126,72 @@ impl TrafficControl {
                     TrafficBehavior::RED
                 }
             }
+            TrafficControl::Actuated { vehicles, .. } => *vehicles,
             TrafficControl::StopSign => TrafficBehavior::STOP,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{TrafficBehavior, TrafficControl, TrafficLightSchedule, WALK_CLEARANCE};
+
+    #[test]
+    fn walk_only_while_vehicles_are_red_minus_clearance() {
+        let schedule = TrafficLightSchedule::from_basic(10, 3, 15, 7);
+        let vehicles = TrafficControl::Light(schedule);
+        let walk = vehicles.walk_control();
+
+        for t in 0..2 * 28 {
+            let red_left = match vehicles.get_behavior(t) {
+                TrafficBehavior::RED => (0..).find(|&dt| !vehicles.get_behavior(t + dt).is_red()),
+                _ => None,
+            };
+            let can_walk = red_left.map_or(false, |left| left as usize > WALK_CLEARANCE);
+            assert_eq!(
+                walk.get_behavior(t) == TrafficBehavior::GREEN,
+                can_walk,
+                "at {}",
+                t
+            );
+        }
+    }
+
+    #[test]
+    fn actuated_walk_follows_the_controller() {
+        use TrafficBehavior::*;
+        // Pairs given by the traffic signals system: green, orange, next phase, other phase
+        for &(vehicles, walk) in &[(GREEN, RED), (ORANGE, RED), (RED, ORANGE), (RED, GREEN)] {
+            let control = TrafficControl::Actuated { vehicles, walk };
+            assert_eq!(control.get_behavior(0), vehicles);
+            assert_eq!(control.walk_control().get_behavior(0), walk);
+        }
+    }
+
+    #[test]
+    fn expected_wait_is_the_mean_wait_for_the_green() {
+        let schedule = TrafficLightSchedule::from_basic(10, 3, 15, 0);
+        let light = TrafficControl::Light(schedule);
+
+        // Vehicles arriving uniformly over a period wait until the next green
+        let n = 2800;
+        let total: f32 = (0..n)
+            .map(|i| {
+                let t = i as f32 * 0.01;
+                match light.get_behavior(t as u32) {
+                    TrafficBehavior::GREEN => 0.0,
+                    _ => 28.0 - t,
+                }
+            })
+            .sum();
+        assert!((light.expected_wait() - total / n as f32).abs() < 0.05);
+
+        assert_eq!(TrafficControl::Always.expected_wait(), 0.0);
+        assert!(TrafficControl::StopSign.expected_wait() > 0.0);
+        let actuated = TrafficControl::Actuated {
+            vehicles: TrafficBehavior::GREEN,
+            walk: TrafficBehavior::RED,
+        };
+        assert!(actuated.expected_wait() > 0.0);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::map::{Lane, LaneID, Lanes};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Weight of a new measure in the exponential smoothing
+const SMOOTHING: f32 = 0.2;
+/// Time in seconds for a measure to go back to the free flow time when nobody drives on the lane
+const RELAX_TIME: f32 = 300.0;
+
+/// Travel times measured on lanes (including the wait at the end of the lane),
+/// used as edge costs by the vehicle pathfinder so traffic spreads on alternative routes.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct TravelTimes {
+    measured: BTreeMap<LaneID, f32>,
+}
+
+impl TravelTimes {
+    /// Time in seconds to drive through the lane at the speed limit and wait at its end
+    pub fn free_flow(lane: &Lane) -> f32 {
+        lane.points.length() / lane.speed_limit + lane.control.expected_wait()
+    }
+
+    pub fn cost(&self, lane: &Lane) -> f32 {
+        self.measured
+            .get(&lane.id)
+            .copied()
+            .unwrap_or_else(|| Self::free_flow(lane))
+    }
+
+    pub fn measured(&self, lane: LaneID) -> Option<f32> {
+        self.measured.get(&lane).copied()
+    }
+
+    pub fn record(&mut self, lane: &Lane, time: f32) {
+        let v = self
+            .measured
+            .entry(lane.id)
+            .or_insert_with(|| Self::free_flow(lane));
+        *v += (time - *v) * SMOOTHING;
+    }
+
+    /// Brings measures back towards the free flow time so that lanes that were congested
+    /// get used again once the congestion is gone
+    pub fn relax(&mut self, lanes: &Lanes, delta: f32) {
+        let k = (delta / RELAX_TIME).min(1.0);
+        self.measured.retain(|&id, v| {
+            let lane = unwrap_or!(lanes.get(id), return false);
+            let free = Self::free_flow(lane);
+            *v += (free - *v) * k;
+            (*v - free).abs() > 0.1
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TravelTimes, RELAX_TIME, SMOOTHING};
+    use crate::map::{
+        IntersectionID, LaneID, LaneKind, LanePatternBuilder, Map, PathKind, Pathfinder, RoadID,
+        RoadSegmentKind, Terrain, Traversable, TraverseDirection, TraverseKind,
+    };
+    use geom::vec3;
+
+    fn driving(map: &Map, road: RoadID, from: IntersectionID) -> LaneID {
+        map.roads()
+            .get(road)
+            .unwrap()
+            .outgoing_lanes_from(from)
+            .iter()
+            .find(|(_, kind)| *kind == LaneKind::Driving)
+            .unwrap()
+            .0
+    }
+
+    fn one_road() -> (Map, LaneID) {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let a = map.add_intersection(vec3(0.0, 0.0, 0.0));
+        let b = map.add_intersection(vec3(200.0, 0.0, 0.0));
+        let pat = LanePatternBuilder::new().build();
+        let road = map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+        let lane = driving(&map, road, a);
+        (map, lane)
+    }
+
+    #[test]
+    fn measures_are_smoothed() {
+        let (map, id) = one_road();
+        let lane = map.lanes().get(id).unwrap();
+        let free = TravelTimes::free_flow(lane);
+        let mut times = TravelTimes::default();
+        assert_eq!(times.measured(id), None);
+        assert_eq!(times.cost(lane), free);
+
+        times.record(lane, free + 100.0);
+        let first = free + 100.0 * SMOOTHING;
+        assert!((times.measured(id).unwrap() - first).abs() < 1e-3);
+
+        times.record(lane, free + 100.0);
+        let second = first + (free + 100.0 - first) * SMOOTHING;
+        assert!((times.cost(lane) - second).abs() < 1e-3);
+
+        for _ in 0..100 {
+            times.record(lane, 1000.0);
+        }
+        assert!((times.cost(lane) - 1000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn measures_relax_to_free_flow() {
+        let (mut map, id) = one_road();
+        let lane = map.lanes().get(id).unwrap();
+        let free = TravelTimes::free_flow(lane);
+        let mut times = TravelTimes::default();
+        times.record(lane, free + 500.0);
+        let measured = times.measured(id).unwrap();
+
+        times.relax(map.lanes(), RELAX_TIME * 0.5);
+        let half = measured + (free - measured) * 0.5;
+        assert!((times.measured(id).unwrap() - half).abs() < 1e-3);
+
+        times.relax(map.lanes(), RELAX_TIME);
+        assert_eq!(times.measured(id), None);
+        assert_eq!(times.cost(map.lanes().get(id).unwrap()), free);
+
+        // Measures of removed lanes are dropped
+        times.record(map.lanes().get(id).unwrap(), free + 500.0);
+        let road = map.lanes().get(id).unwrap().parent;
+        map.remove_road(road);
+        times.relax(map.lanes(), 1.0);
+        assert_eq!(times.measured(id), None);
+    }
+
+    #[test]
+    fn vehicles_avoid_a_congested_lane() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let pat = LanePatternBuilder::new().build();
+        let mut inter = |x: f32, y: f32| map.add_intersection(vec3(x, y, 0.0));
+        let (s, a, b, c, e) = (
+            inter(-200.0, 0.0),
+            inter(0.0, 0.0),
+            inter(400.0, 0.0),
+            inter(200.0, 100.0),
+            inter(600.0, 0.0),
+        );
+        let mut connect = |src, dst| {
+            map.connect(src, dst, &pat, RoadSegmentKind::Straight)
+                .unwrap()
+        };
+        let (first, direct, detour, last) =
+            (connect(s, a), connect(a, b), connect(a, c), connect(c, b));
+        let last_road = connect(b, e);
+
+        let start = driving(&map, first, s);
+        let end = driving(&map, last_road, b);
+        let direct = driving(&map, direct, a);
+        let detour = [driving(&map, detour, a), driving(&map, last, c)];
+
+        let route = |map: &Map| -> Vec<LaneID> {
+            let start = Traversable::new(TraverseKind::Lane(start), TraverseDirection::Forward);
+            PathKind::Vehicle
+                .path(map, start, end)
+                .unwrap()
+                .into_iter()
+                .filter_map(|t| match t.kind {
+                    TraverseKind::Lane(id) => Some(id),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let free = route(&map);
+        assert!(free.contains(&direct));
+        assert!(!free.contains(&detour[0]));
+
+        let lane = map.lanes().get(direct).unwrap().clone();
+        for _ in 0..20 {
+            map.travel_times.record(&lane, 300.0);
+        }
+        let congested = route(&map);
+        assert!(!congested.contains(&direct));
+        assert!(detour.iter().all(|l| congested.contains(l)));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::map::{IntersectionID, Intersections, LaneID, Lanes, Map, TurnID};
+use crate::map::{IntersectionID, Intersections, LaneID, Lanes, Map, TrafficBehavior, TurnID};
 use geom::PolyLine3;
 use imgui_inspect::imgui::Ui;
 use imgui_inspect::{imgui, InspectArgsDefault, InspectRenderDefault};
@@ -66,6 +66,25 @@ impl Traversable {
         }
     }
 
+    /// Whether it can be started now, pedestrians only start crossing on a walk signal
+    pub fn can_enter(&self, time: u32, map: &Map) -> bool {
+        match self.kind {
+            TraverseKind::Lane(_) => true,
+            TraverseKind::Turn(id) => {
+                let is_crosswalk = map
+                    .intersections
+                    .get(id.parent)
+                    .and_then(|inter| inter.find_turn(id))
+                    .map_or(false, |turn| turn.kind.is_crosswalk());
+                !is_crosswalk
+                    || matches!(
+                        map.crosswalk_control(id).get_behavior(time),
+                        TrafficBehavior::GREEN
+                    )
+            }
+        }
+    }
+
     pub fn destination_intersection(&self, lanes: &Lanes) -> Option<IntersectionID> {
         Some(match self.kind {
             TraverseKind::Lane(p) => match self.dir {
@@ -180,3 +199,47 @@ impl InspectRenderDefault<TraverseKind> for TraverseKind {
 }
 
 enum_inspect_impl!(TraverseDirection; TraverseDirection::Forward, TraverseDirection::Backward);
+
+#[cfg(test)]
+mod tests {
+    use super::{Traversable, TraverseDirection, TraverseKind};
+    use crate::map::{
+        LanePatternBuilder, LightPolicy, Map, RoadSegmentKind, Terrain, TrafficBehavior,
+    };
+    use geom::vec3;
+
+    #[test]
+    fn crosswalk_only_entered_on_walk() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let pat = LanePatternBuilder::new().build();
+        let center = map.add_intersection(vec3(200.0, 200.0, 0.0));
+        for &(x, y) in &[(0.0, 200.0), (400.0, 200.0), (200.0, 0.0), (200.0, 400.0)] {
+            let side = map.add_intersection(vec3(x, y, 0.0));
+            map.connect(center, side, &pat, RoadSegmentKind::Straight)
+                .unwrap();
+        }
+        map.update_intersection(center, |i| i.light_policy = LightPolicy::Lights);
+
+        let crosswalk = map
+            .intersections()
+            .get(center)
+            .unwrap()
+            .turns()
+            .find(|t| t.kind.is_crosswalk())
+            .unwrap()
+            .id;
+        let control = map.crosswalk_control(crosswalk);
+        assert!(control.is_light());
+
+        let t = Traversable::new(TraverseKind::Turn(crosswalk), TraverseDirection::Forward);
+        let mut refused = false;
+        for time in 0..200 {
+            let walk = control.get_behavior(time) == TrafficBehavior::GREEN;
+            assert_eq!(t.can_enter(time, &map), walk, "at {}", time);
+            refused |= !walk;
+        }
+        assert!(refused);
+    }
+}
@@ -21,6 +21,66 @@ impl Default for TurnPolicy {
     }
 }
 
+/// Direction of a vehicle turn relative to the incoming lane
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TurnDirection {
+    Left,
+    Straight,
+    Right,
+    Back,
+}
+
+impl TurnDirection {
+    /// Both directions are given as `Lane::orientation_from` the intersection
+    pub fn from_orientations(incoming_dir: Vec2, outgoing_dir: Vec2) -> Self {
+        let travel_dir = -incoming_dir;
+        let d = travel_dir.dot(outgoing_dir);
+        if d > 0.7 {
+            return TurnDirection::Straight;
+        }
+        if d < -0.7 {
+            return TurnDirection::Back;
+        }
+        let left = vec2(incoming_dir.y, -incoming_dir.x);
+        if left.dot(outgoing_dir) > 0.0 {
+            TurnDirection::Left
+        } else {
+            TurnDirection::Right
+        }
+    }
+}
+
+/// Directions vehicles coming from an incoming lane are allowed to take
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Inspect)]
+pub struct LaneTurns {
+    pub left: bool,
+    pub straight: bool,
+    pub right: bool,
+    pub back: bool,
+}
+
+impl Default for LaneTurns {
+    fn default() -> Self {
+        Self {
+            left: true,
+            straight: true,
+            right: true,
+            back: true,
+        }
+    }
+}
+
+impl LaneTurns {
+    pub fn allows(&self, dir: TurnDirection) -> bool {
+        match dir {
+            TurnDirection::Left => self.left,
+            TurnDirection::Straight => self.straight,
+            TurnDirection::Right => self.right,
+            TurnDirection::Back => self.back,
+        }
+    }
+}
+
 fn filter_vehicles(x: &[(LaneID, LaneKind)]) -> Vec<LaneID> {
     x.iter()
         .filter(|(_, kind)| kind.vehicles())
@@ -218,6 +278,24 @@ impl TurnPolicy {
         }
     }
 
+    /// Removes the vehicle turns forbidden by the per-lane restrictions of the intersection
+    fn apply_lane_turns(inter: &Intersection, lanes: &Lanes, turns: &mut Vec<(TurnID, TurnKind)>) {
+        if inter.lane_turns.is_empty() {
+            return;
+        }
+        turns.retain(|(id, kind)| {
+            if !matches!(kind, TurnKind::Driving) {
+                return true;
+            }
+            let allowed = unwrap_or!(inter.lane_turns.get(&id.src), return true);
+            let (src, dst) = unwrap_or!(lanes.get(id.src).zip(lanes.get(id.dst)), return true);
+            allowed.allows(TurnDirection::from_orientations(
+                src.orientation_from(inter.id),
+                dst.orientation_from(inter.id),
+            ))
+        });
+    }
+
     pub fn compatible_turn_sharpness_rail(dir1: Vec2, dir2: Vec2) -> bool {
         dir1.dot(dir2) <= -0.2
     }
@@ -308,6 +386,7 @@ impl TurnPolicy {
         let mut turns = vec![];
 
         self.generate_vehicle_turns(inter, lanes, roads, &mut turns);
+        Self::apply_lane_turns(inter, lanes, &mut turns);
         self.generate_rail_turns(inter, lanes, roads, &mut turns);
 
         self.generate_walking_turns(inter, roads, &mut turns);
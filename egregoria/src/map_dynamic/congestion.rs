@@ -0,0 +1,60 @@
+use crate::map::{Map, PathKind, TraverseKind};
+use crate::utils::time::GameTime;
+use crate::vehicles::Vehicle;
+use crate::Itinerary;
+use hecs::{Entity, World};
+use resources::Resources;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Follows vehicles from lane to lane to measure the lanes' travel times
+#[derive(Default, Serialize, Deserialize)]
+pub struct CongestionTracker {
+    /// Last traversable of each vehicle, and when it entered it if it's a lane it entered from a turn
+    travers: BTreeMap<Entity, (TraverseKind, Option<f64>)>,
+    last_relax: u32,
+}
+
+#[profiling::function]
+pub fn congestion_update(world: &mut World, resources: &mut Resources) {
+    let mut map = resources.get_mut::<Map>().unwrap();
+    let mut tracker = resources.get_mut::<CongestionTracker>().unwrap();
+    let time = resources.get::<GameTime>().unwrap();
+    let map = &mut *map;
+
+    let mut travers = BTreeMap::new();
+    for (e, (v, it)) in world.query_mut::<(&Vehicle, &Itinerary)>() {
+        // Bikes ride slower than the cars the travel times are for
+        if !matches!(v.kind.path_kind(), PathKind::Vehicle) {
+            continue;
+        }
+        let kind = unwrap_cont!(it.get_travers()).kind;
+
+        let entered = match tracker.travers.get(&e) {
+            Some(&(prev, entered)) if prev == kind => entered,
+            Some(&(prev, entered)) => {
+                // Only measure full traversals: lane changes and unparking start mid-lane
+                if let (TraverseKind::Lane(l), Some(t0), TraverseKind::Turn(_)) =
+                    (prev, entered, kind)
+                {
+                    if let Some(lane) = map.lanes.get(l) {
+                        map.travel_times.record(lane, (time.timestamp - t0) as f32);
+                    }
+                }
+                match (prev, kind) {
+                    (TraverseKind::Turn(_), TraverseKind::Lane(_)) => Some(time.timestamp),
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        travers.insert(e, (kind, entered));
+    }
+    tracker.travers = travers;
+
+    if time.seconds != tracker.last_relax {
+        let delta = time.seconds.saturating_sub(tracker.last_relax) as f32;
+        tracker.last_relax = time.seconds;
+        map.travel_times.relax(&map.lanes, delta.min(60.0));
+    }
+}
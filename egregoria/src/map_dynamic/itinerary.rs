@@ -1,7 +1,11 @@
-use crate::map::{Map, PathKind, Pathfinder, Traversable, TraverseDirection, TraverseKind};
+use crate::map::{
+    vehicle_path_changing_lane, Lane, LaneID, Map, PathKind, Pathfinder, Traversable,
+    TraverseDirection, TraverseKind, BIKE_SPEED,
+};
+use crate::pedestrians::AVERAGE_WALKING_SPEED;
 use crate::utils::time::GameTime;
 use crate::Kinematics;
-use geom::{Follower, Polyline3Queue, Transform, Vec3};
+use geom::{Follower, Polyline3Queue, Spline3, Transform, Vec3};
 use hecs::{Entity, World};
 use imgui::Ui;
 use imgui_inspect::{InspectArgsDefault, InspectRenderDefault};
@@ -50,6 +54,16 @@ pub struct Route {
 
 pub const OBJECTIVE_OK_DIST: f32 = 3.0;
 
+/// Distance in meters travelled along the road during a lane change
+pub const LANE_CHANGE_LENGTH: f32 = 15.0;
+
+/// Room needed before the end of the lane to plan a lane change for the next turn, in meters.
+/// Leaves some time to wait for a gap on the other lane.
+const LANE_CHANGE_PLAN_ROOM: f32 = 2.0 * LANE_CHANGE_LENGTH + OBJECTIVE_OK_DIST;
+
+/// Speed of vehicles in the turns when estimating durations, in m/s
+const ESTIMATED_TURN_SPEED: f32 = 6.0;
+
 impl Itinerary {
     pub const NONE: Self = Self {
         kind: ItineraryKind::None,
@@ -113,11 +127,14 @@ impl Itinerary {
             }
         }
 
-        let mut reversed_route: Vec<Traversable> = pathkind
-            .path(map, cur, end_lane)?
-            .into_iter()
-            .rev()
-            .collect();
+        let path = match pathkind {
+            PathKind::Vehicle => {
+                let parallel = Self::lane_change_options(map, start_lane, start);
+                vehicle_path_changing_lane(map, cur, &parallel, end_lane)
+            }
+            _ => pathkind.path(map, cur, end_lane),
+        };
+        let mut reversed_route: Vec<Traversable> = path?.into_iter().rev().collect();
 
         reversed_route.pop(); // Remove start
 
@@ -160,6 +177,17 @@ impl Itinerary {
         Some(it)
     }
 
+    /// Lanes parallel to `lane` that a vehicle at `pos` still has the room to change to
+    fn lane_change_options(map: &Map, lane: LaneID, pos: Vec3) -> Vec<LaneID> {
+        let l = unwrap_ret!(map.lanes().get(lane), vec![]);
+        let road = unwrap_ret!(map.roads().get(l.parent), vec![]);
+        let room = l.points.length() - l.points.length_at_proj(l.points.project(pos));
+        if room < LANE_CHANGE_PLAN_ROOM {
+            return vec![];
+        }
+        road.parallel_lanes(lane).collect()
+    }
+
     fn advance(&mut self, map: &Map) -> Option<Vec3> {
         let v = self.reversed_local_path.pop();
 
@@ -191,6 +219,150 @@ impl Itinerary {
         v
     }
 
+    /// Computes a new route to the same destination from `position`,
+    /// using the current travel times. Returns false if no route was found.
+    pub fn reroute(&mut self, map: &Map, position: Vec3) -> bool {
+        let (end_pos, kind) = match self.kind {
+            ItineraryKind::Route(ref r, kind) => (r.end_pos, kind),
+            _ => return false,
+        };
+        match Self::route(position, end_pos, map, kind) {
+            Some(it) => {
+                *self = it;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves to `new_lane`, a lane parallel to the current one, along a smooth spline.
+    /// Returns false if the lane change isn't possible: not driving on a lane, not enough room
+    /// left on the road or the route cannot continue from the new lane.
+    pub fn change_lane(&mut self, map: &Map, new_lane: LaneID, position: Vec3, dir: Vec3) -> bool {
+        let r = match self.kind {
+            ItineraryKind::Route(ref mut r, PathKind::Vehicle) => r,
+            _ => return false,
+        };
+        if r.cur.kind == TraverseKind::Lane(new_lane) || !r.cur.kind.is_lane() {
+            return false;
+        }
+        let end_lane = match r.reversed_route.first() {
+            Some(Traversable {
+                kind: TraverseKind::Lane(id),
+                ..
+            }) => *id,
+            _ => return false,
+        };
+        let next_turn = match r.reversed_route.last() {
+            Some(Traversable {
+                kind: TraverseKind::Turn(id),
+                ..
+            }) => *id,
+            _ => return false,
+        };
+
+        let lane = unwrap_or!(map.lanes().get(new_lane), return false);
+        let target_dist =
+            lane.points.length_at_proj(lane.points.project(position)) + LANE_CHANGE_LENGTH;
+        if target_dist + OBJECTIVE_OK_DIST > lane.points.length() {
+            return false;
+        }
+
+        // Keep the rest of the route if the new lane can make the same turn, otherwise reroute
+        let same_turn = map.intersections().get(lane.dst).and_then(|inter| {
+            inter
+                .turns_from(new_lane)
+                .find(|(t, _)| t.dst == next_turn.dst)
+        });
+
+        let new_cur = Traversable::new(TraverseKind::Lane(new_lane), TraverseDirection::Forward);
+        match same_turn {
+            Some((turn, dir)) => {
+                r.reversed_route.pop();
+                r.reversed_route
+                    .push(Traversable::new(TraverseKind::Turn(turn), dir));
+            }
+            None => {
+                let mut path =
+                    unwrap_or!(PathKind::Vehicle.path(map, new_cur, end_lane), return false);
+                path.reverse();
+                path.pop(); // Remove start
+                r.reversed_route = path;
+            }
+        }
+        r.cur = new_cur;
+
+        let (target, target_dir) = lane.points.point_dir_along(target_dist);
+        let transition = Spline3 {
+            from: position,
+            to: target,
+            from_derivative: dir * LANE_CHANGE_LENGTH * 0.5,
+            to_derivative: target_dir * LANE_CHANGE_LENGTH * 0.5,
+        };
+
+        let mut points = lane.points.cut_start(target_dist).into_vec();
+        points.reverse();
+        points.pop(); // the target is the end of the transition
+        let mut transition: Vec<Vec3> = transition.smart_points(0.5, 0.0, 1.0).skip(1).collect();
+        transition.reverse();
+        points.extend(transition);
+
+        self.reversed_local_path = points;
+        true
+    }
+
+    /// The current lane and the lane the next turn starts from, when the route planned
+    /// a lane change before the junction
+    pub fn planned_lane_change(&self) -> Option<(LaneID, LaneID)> {
+        let r = match self.kind {
+            ItineraryKind::Route(ref r, PathKind::Vehicle) => r,
+            _ => return None,
+        };
+        let cur = match r.cur.kind {
+            TraverseKind::Lane(id) => id,
+            TraverseKind::Turn(_) => return None,
+        };
+        match r.reversed_route.last()?.kind {
+            TraverseKind::Turn(turn) if turn.src != cur => Some((cur, turn.src)),
+            _ => None,
+        }
+    }
+
+    /// Where `skip_to_next_lane` would take the vehicle, None if there is no lane left
+    pub fn next_lane_start(&self, map: &Map) -> Option<Vec3> {
+        let r = match self.kind {
+            ItineraryKind::Route(ref r, _) => r,
+            _ => return None,
+        };
+        let next = r.reversed_route.iter().rev().find(|t| t.kind.is_lane())?;
+        Some(next.points(map)?.first())
+    }
+
+    /// Skips the rest of the current traversable up to the start of the next lane of the route.
+    /// Returns where the route continues, None if there is no lane left.
+    pub fn skip_to_next_lane(&mut self, map: &Map) -> Option<Vec3> {
+        let r = match self.kind {
+            ItineraryKind::Route(ref mut r, _) => r,
+            _ => return None,
+        };
+        let i = r.reversed_route.iter().rposition(|t| t.kind.is_lane())?;
+        let next = *r.reversed_route.get(i)?;
+        let mut points = next.points(map)?;
+
+        r.reversed_route.truncate(i);
+        r.cur = next;
+        if r.reversed_route.is_empty() {
+            let (proj_pos, id) = points.project_segment(r.end_pos);
+            self.reversed_local_path = vec![r.end_pos, proj_pos];
+            self.reversed_local_path
+                .extend(points.as_slice().get(..id)?.iter().rev());
+        } else {
+            points.reverse();
+            self.reversed_local_path = points.into_vec();
+        }
+        self.get_point()
+    }
+
     pub fn update_rail(
         &mut self,
         mut position: Vec3,
@@ -218,7 +390,8 @@ impl Itinerary {
                     return p;
                 });
 
-                if k.can_pass(time, map.lanes()) {
+                let next_ok = self.peek_travers().map_or(true, |t| t.can_enter(time, map));
+                if k.can_pass(time, map.lanes()) && next_ok {
                     self.advance(map);
                     continue;
                 }
@@ -246,6 +419,42 @@ impl Itinerary {
         position
     }
 
+    /// Rough time in seconds needed to follow the route from `from`, using the measured
+    /// travel times for vehicles and the speed limits otherwise.
+    /// None if the itinerary isn't a route or goes through objects that don't exist anymore.
+    pub fn estimated_duration(&self, from: Vec3, map: &Map) -> Option<f32> {
+        let (r, kind) = match self.kind {
+            ItineraryKind::Route(ref r, kind) => (r, kind),
+            _ => return None,
+        };
+
+        let mut local_len = 0.0;
+        let mut last = from;
+        for &p in self.reversed_local_path.iter().rev() {
+            local_len += last.distance(p);
+            last = p;
+        }
+        let cur_lane = match r.cur.kind {
+            TraverseKind::Lane(id) => map.lanes().get(id),
+            TraverseKind::Turn(_) => None,
+        };
+        let mut total = local_len / estimated_speed(kind, cur_lane);
+
+        for t in &r.reversed_route {
+            total += match t.kind {
+                TraverseKind::Lane(id) => {
+                    let lane = map.lanes().get(id)?;
+                    match kind {
+                        PathKind::Vehicle => map.travel_times.cost(lane),
+                        _ => lane.points.length() / estimated_speed(kind, Some(lane)),
+                    }
+                }
+                TraverseKind::Turn(_) => t.points(map)?.length() / estimated_speed(kind, None),
+            };
+        }
+        Some(total)
+    }
+
     pub fn end_pos(&self) -> Option<Vec3> {
         match self.kind {
             ItineraryKind::None => None,
@@ -297,6 +506,14 @@ impl Itinerary {
         }
     }
 
+    /// The traversable coming after the current one
+    pub fn peek_travers(&self) -> Option<&Traversable> {
+        match &self.kind {
+            ItineraryKind::Route(Route { reversed_route, .. }, _) => reversed_route.last(),
+            _ => None,
+        }
+    }
+
     pub fn kind(&self) -> &ItineraryKind {
         &self.kind
     }
@@ -322,6 +539,16 @@ impl Itinerary {
     }
 }
 
+/// Speed used to estimate the time spent on a lane, or in a turn if there is no lane, in m/s
+fn estimated_speed(kind: PathKind, lane: Option<&Lane>) -> f32 {
+    let limit = lane.map_or(ESTIMATED_TURN_SPEED, |l| l.speed_limit);
+    match kind {
+        PathKind::Pedestrian => AVERAGE_WALKING_SPEED,
+        PathKind::Bike => limit.min(BIKE_SPEED),
+        PathKind::Vehicle | PathKind::Rail => limit,
+    }
+}
+
 impl Default for ItineraryKind {
     fn default() -> Self {
         ItineraryKind::None
@@ -1,9 +1,21 @@
+mod congestion;
 mod house_assignment;
 mod itinerary;
+mod mode_choice;
 mod parking;
 mod router;
+mod routing_landmarks;
+mod traffic_signals;
+mod traffic_stats;
+mod trip_log;
 
+pub use congestion::*;
 pub use house_assignment::*;
 pub use itinerary::*;
+pub use mode_choice::*;
 pub use parking::*;
 pub use router::*;
+pub use routing_landmarks::*;
+pub use traffic_signals::*;
+pub use traffic_stats::*;
+pub use trip_log::*;
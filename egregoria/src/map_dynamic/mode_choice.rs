@@ -0,0 +1,246 @@
+use crate::map::{Map, PathKind};
+use crate::map_dynamic::{Itinerary, ParkingManagement};
+use crate::pedestrians::AVERAGE_WALKING_SPEED;
+use crate::utils::rand_provider::RandProvider;
+use geom::Vec3;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// How much longer than the straight line the walks are assumed to be
+const WALK_DETOUR: f32 = 1.3;
+/// Time to look for the spot and to park, on top of the walk from the spot, in seconds
+const PARKING_SEARCH_TIME: f32 = 60.0;
+/// Time to get the bike out and to store it, in seconds
+const BIKE_HANDLING_TIME: f32 = 30.0;
+/// How long paying a cent for parking feels, in seconds
+const SECONDS_PER_CENT: f32 = 2.0;
+/// Number of trips kept to compute the recent modal split
+const RECENT_TRIPS: usize = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TransportMode {
+    Walk,
+    Bike,
+    Car,
+}
+
+impl TransportMode {
+    pub const ALL: [TransportMode; 3] =
+        [TransportMode::Walk, TransportMode::Bike, TransportMode::Car];
+}
+
+/// Factors applied to the estimated duration of each mode, a lower factor means
+/// the mode is liked more
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ModePreferences {
+    pub walk: f32,
+    pub bike: f32,
+    pub car: f32,
+}
+
+debug_inspect_impl!(ModePreferences);
+
+impl Default for ModePreferences {
+    fn default() -> Self {
+        Self {
+            walk: 1.0,
+            bike: 1.0,
+            car: 1.0,
+        }
+    }
+}
+
+impl ModePreferences {
+    pub fn random(r: &mut RandProvider) -> Self {
+        Self {
+            walk: 0.7 + r.next_f32() * 0.6,
+            bike: 0.7 + r.next_f32() * 0.6,
+            car: 0.7 + r.next_f32() * 0.6,
+        }
+    }
+
+    pub fn factor(&self, mode: TransportMode) -> f32 {
+        match mode {
+            TransportMode::Walk => self.walk,
+            TransportMode::Bike => self.bike,
+            TransportMode::Car => self.car,
+        }
+    }
+}
+
+/// Where the trip goes and where the vehicles that can be used for it are
+#[derive(Debug, Copy, Clone)]
+pub struct TripOptions {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub bike: Option<Vec3>,
+    pub car: Option<Vec3>,
+}
+
+/// The chosen mode and, when it uses a vehicle, the route of the vehicle that was estimated
+/// so that it isn't computed again when the vehicle leaves
+#[derive(Debug)]
+pub struct ModeChoice {
+    pub mode: TransportMode,
+    pub route: Option<Itinerary>,
+}
+
+/// Mode with the lowest estimated duration once weighted by the preferences.
+/// Walks are estimated from the straight line distance, only the vehicles are routed.
+/// Driving includes the walk to the car, looking for a spot near the destination,
+/// the price of the spot and the walk from it.
+/// Walking is always possible so it is chosen when no route is found for the others.
+/// There is no transit mode yet.
+pub fn choose_mode(
+    map: &Map,
+    parking: &ParkingManagement,
+    prefs: &ModePreferences,
+    trip: TripOptions,
+) -> ModeChoice {
+    let estimate = |mode| match mode {
+        TransportMode::Walk => Some((straight_walk(trip.from, trip.to), None)),
+        TransportMode::Bike => {
+            let bike = trip.bike?;
+            let route = Itinerary::route(bike, trip.to, map, PathKind::Bike)?;
+            let ride = route.estimated_duration(bike, map)?;
+            Some((
+                straight_walk(trip.from, bike) + BIKE_HANDLING_TIME + ride,
+                Some(route),
+            ))
+        }
+        TransportMode::Car => {
+            let car = trip.car?;
+            let spot = parking.spot_near(trip.to, map)?;
+            let park_pos = map.parking_to_drive_pos(spot)?;
+            let route = Itinerary::route(car, park_pos, map, PathKind::Vehicle)?;
+            let drive = route.estimated_duration(car, map)?;
+            let price = parking.spot_price(spot, map).cents() as f32 * SECONDS_PER_CENT;
+            Some((
+                straight_walk(trip.from, car)
+                    + drive
+                    + PARKING_SEARCH_TIME
+                    + straight_walk(park_pos, trip.to)
+                    + price,
+                Some(route),
+            ))
+        }
+    };
+
+    TransportMode::ALL
+        .iter()
+        .filter_map(|&mode| {
+            let (duration, route) = estimate(mode)?;
+            Some((mode, duration * prefs.factor(mode), route))
+        })
+        .min_by_key(|&(mode, cost, _)| (OrderedFloat(cost), mode))
+        .map_or(
+            ModeChoice {
+                mode: TransportMode::Walk,
+                route: None,
+            },
+            |(mode, _, route)| ModeChoice { mode, route },
+        )
+}
+
+fn straight_walk(from: Vec3, to: Vec3) -> f32 {
+    from.distance(to) * WALK_DETOUR / AVERAGE_WALKING_SPEED
+}
+
+/// Modes chosen by the humans for their trips
+#[derive(Default, Serialize, Deserialize)]
+pub struct ModalSplit {
+    total: BTreeMap<TransportMode, u64>,
+    /// Latest trips, oldest first
+    recent: VecDeque<TransportMode>,
+}
+
+impl ModalSplit {
+    pub fn record(&mut self, mode: TransportMode) {
+        *self.total.entry(mode).or_default() += 1;
+        if self.recent.len() == RECENT_TRIPS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(mode);
+    }
+
+    /// Number of trips made with the mode since the start
+    pub fn total(&self, mode: TransportMode) -> u64 {
+        self.total.get(&mode).copied().unwrap_or_default()
+    }
+
+    pub fn n_recent(&self) -> usize {
+        self.recent.len()
+    }
+
+    /// Share of the latest trips made with the mode, between 0 and 1
+    pub fn recent_share(&self, mode: TransportMode) -> f32 {
+        if self.recent.is_empty() {
+            return 0.0;
+        }
+        self.recent.iter().filter(|&&m| m == mode).count() as f32 / self.recent.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        choose_mode, ModalSplit, ModePreferences, TransportMode, TripOptions, RECENT_TRIPS,
+    };
+    use crate::economy::Money;
+    use crate::map::{LaneKind, LanePatternBuilder, Map, RoadSegmentKind, Terrain};
+    use crate::map_dynamic::{ParkingManagement, ParkingPlace};
+    use geom::vec3;
+
+    #[test]
+    fn choose_mode_depends_on_distance_and_parking_price() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let a = map.add_intersection(vec3(0.0, 0.0, 0.0));
+        let b = map.add_intersection(vec3(2000.0, 0.0, 0.0));
+        let pat = LanePatternBuilder::new().one_way(true).build();
+        map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+
+        let mut pm = ParkingManagement::default();
+        let prefs = ModePreferences::default();
+        let trip = |to_x: f32| TripOptions {
+            from: vec3(100.0, 10.0, 0.0),
+            to: vec3(to_x, 10.0, 0.0),
+            bike: Some(vec3(100.0, 0.0, 0.0)),
+            car: Some(vec3(100.0, 0.0, 0.0)),
+        };
+
+        let short = choose_mode(&map, &pm, &prefs, trip(150.0));
+        assert_eq!(short.mode, TransportMode::Walk);
+        assert!(short.route.is_none());
+
+        let long = choose_mode(&map, &pm, &prefs, trip(1900.0));
+        assert_eq!(long.mode, TransportMode::Car);
+        assert!(long.route.is_some());
+
+        for l in map.lanes().values() {
+            if l.kind == LaneKind::Parking {
+                pm.set_price(ParkingPlace::Lane(l.id), Money::new_base(10));
+            }
+        }
+        let expensive = choose_mode(&map, &pm, &prefs, trip(1900.0));
+        assert_eq!(expensive.mode, TransportMode::Bike);
+    }
+
+    #[test]
+    fn modal_split_keeps_recent_trips() {
+        let mut split = ModalSplit::default();
+        for _ in 0..RECENT_TRIPS {
+            split.record(TransportMode::Car);
+        }
+        for _ in 0..RECENT_TRIPS / 4 {
+            split.record(TransportMode::Walk);
+        }
+
+        assert_eq!(split.total(TransportMode::Car), RECENT_TRIPS as u64);
+        assert_eq!(split.n_recent(), RECENT_TRIPS);
+        assert!((split.recent_share(TransportMode::Walk) - 0.25).abs() < 1e-6);
+        assert_eq!(split.recent_share(TransportMode::Bike), 0.0);
+    }
+}
@@ -1,17 +1,62 @@
-use crate::map::{LaneKind, Map, ParkingSpot, ParkingSpotID, ParkingSpots};
-use common::PtrCmp;
-use geom::Vec3;
+use crate::economy::Money;
+use crate::map::{
+    BuildingID, BuildingKind, LaneID, LaneKind, Map, ParkingSpot, ParkingSpotID, ParkingSpots,
+    ProjectFilter, ProjectKind,
+};
+use crate::utils::time::GameTime;
+use geom::{Vec2, Vec3, AABB};
+use hecs::World;
+use ordered_float::OrderedFloat;
+use resources::Resources;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::option::Option::None;
 
+/// Parking buildings further than this from the destination are only used when
+/// there is no street parking around, in meters
+const BUILDING_WALK_RADIUS: f32 = 150.0;
+/// How far to look for any free spot when there is none close to the destination, in meters
+const FALLBACK_RADIUS: f32 = 500.0;
+/// What walking one meter from the spot to the destination is worth to a driver, in cents
+const WALK_VALUE: f32 = 1.0;
+/// Lane levels explored after the first one with a free street spot,
+/// so that cheaper spots a bit further away are considered
+const EXTRA_DEPTH: u32 = 2;
+
+/// Size of the square areas the parking occupancy is measured on, in meters
+pub const PARKING_AREA_SIZE: f32 = 250.0;
+/// Time between two occupancy samples, in game seconds
+const OCCUPANCY_SAMPLE_PERIOD: u32 = 300;
+/// Samples kept for each area, a day at one sample every 5 minutes
+const OCCUPANCY_HISTORY: usize = 288;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[repr(transparent)]
 pub struct SpotReservation(ParkingSpotID);
 
+/// Where a parking price applies: all the spots of a parking lane or of a parking building
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ParkingPlace {
+    Lane(LaneID),
+    Building(BuildingID),
+}
+
+impl ParkingPlace {
+    pub fn of(spot: &ParkingSpot) -> Self {
+        match spot.building {
+            Some(b) => ParkingPlace::Building(b),
+            None => ParkingPlace::Lane(spot.parent),
+        }
+    }
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct ParkingManagement {
     reserved_spots: BTreeSet<ParkingSpotID>,
+    /// Price of parking once, places without one are free
+    prices: BTreeMap<ParkingPlace, Money>,
+    /// Total collected from the parking prices
+    pub revenue: Money,
 }
 
 impl ParkingManagement {
@@ -27,57 +72,268 @@ impl ParkingManagement {
         self.is_spot_free(spot.0)
     }
 
+    /// True if nobody reserved the spot
     pub fn is_spot_free(&self, spot: ParkingSpotID) -> bool {
-        self.reserved_spots.contains(&spot)
+        !self.reserved_spots.contains(&spot)
+    }
+
+    pub fn n_reserved(&self) -> usize {
+        self.reserved_spots.len()
+    }
+
+    /// Sets the price of parking once at `place`, a price of zero makes it free
+    pub fn set_price(&mut self, place: ParkingPlace, price: Money) {
+        if price <= Money::default() {
+            self.prices.remove(&place);
+        } else {
+            self.prices.insert(place, price);
+        }
+    }
+
+    pub fn price(&self, place: ParkingPlace) -> Money {
+        self.prices.get(&place).copied().unwrap_or_default()
+    }
+
+    pub fn spot_price(&self, spot: ParkingSpotID, map: &Map) -> Money {
+        map.parking
+            .get(spot)
+            .map(|spot| self.price(ParkingPlace::of(spot)))
+            .unwrap_or_default()
+    }
+
+    /// Collects the price of the reserved spot, returns how much was paid
+    pub fn charge(&mut self, spot: &SpotReservation, map: &Map) -> Money {
+        let price = self.spot_price(spot.0, map);
+        self.revenue += price;
+        price
     }
 
+    /// Reserves the spot given by `spot_near`
     pub fn reserve_near(&mut self, near: Vec3, map: &Map) -> Option<SpotReservation> {
-        let lane = map.nearest_lane(near, LaneKind::Driving, None)?;
-        let lane = map.lanes().get(lane)?;
+        let spot = self.spot_near(near, map)?;
+        self.reserved_spots.insert(spot);
+        Some(SpotReservation(spot))
+    }
+
+    /// Cheapest free spot between the street parking found by following the lanes
+    /// around `near` and the parking buildings within walking distance, the cost being the price
+    /// of the spot and the walk to `near`.
+    /// If there is none, looks further away in both.
+    pub fn spot_near(&self, near: Vec3, map: &Map) -> Option<ParkingSpotID> {
+        let cost = |spot: ParkingSpotID| {
+            let p = unwrap_ret!(map.parking.get(spot), OrderedFloat(f32::INFINITY));
+            let dist = match p.building.and_then(|b| map.buildings().get(b)) {
+                Some(b) => b.door_pos.distance(near),
+                None => p.trans.position.distance(near),
+            };
+            OrderedFloat(dist * WALK_VALUE + self.price(ParkingPlace::of(p)).cents() as f32)
+        };
+        let cheapest = |candidates: Vec<ParkingSpotID>| {
+            candidates
+                .into_iter()
+                .min_by_key(|&spot| (cost(spot), spot))
+        };
+
+        let mut close = self.street_spots_near(near, map);
+        close.extend(self.building_spots_near(near, map, BUILDING_WALK_RADIUS));
+
+        cheapest(close).or_else(|| {
+            let mut far = self.street_spots_around(near, map, FALLBACK_RADIUS);
+            far.extend(self.building_spots_near(near, map, FALLBACK_RADIUS));
+            cheapest(far)
+        })
+    }
 
-        let depth = 7;
+    /// Closest free spot of each parking lane found following the lanes from the one closest
+    /// to `near`, up to a few levels after the first lane with a free spot
+    fn street_spots_near(&self, near: Vec3, map: &Map) -> Vec<ParkingSpotID> {
+        let mut candidates = vec![];
+        let lane = unwrap_ret!(map.nearest_lane(near, LaneKind::Driving, None), candidates);
 
-        let mut potential = HashSet::new();
-        potential.insert(PtrCmp(lane));
-        let mut next = HashSet::new();
+        let mut depth = 7;
+
+        // Ordered by id so the candidates don't depend on where the lanes are in memory
+        let mut potential = BTreeSet::new();
+        potential.insert(lane);
+        let mut next = BTreeSet::new();
         let intersections = map.intersections();
         let roads = map.roads();
-        for _ in 0..depth {
-            for lane in potential.drain() {
-                let lane = lane.0;
+        let lanes = map.lanes();
+        let mut level = 0;
+        while level < depth {
+            for lane in std::mem::take(&mut potential) {
+                let lane = unwrap_cont!(lanes.get(lane));
                 let parent = unwrap_or!(roads.get(lane.parent), continue);
 
                 let plane = unwrap_or!(parent.parking_next_to(lane), continue);
 
-                if let Some(p_iter) = map.parking.closest_spots(plane, near) {
-                    for spot in p_iter {
-                        if self.reserved_spots.insert(spot) {
-                            return Some(SpotReservation(spot));
+                if let Some(mut p_iter) = map.parking.closest_spots(plane, near) {
+                    if let Some(spot) = p_iter.find(|spot| !self.reserved_spots.contains(spot)) {
+                        if candidates.is_empty() {
+                            depth = depth.min(level + 1 + EXTRA_DEPTH);
                         }
+                        candidates.push(spot);
                     }
                 }
 
                 let inter_dst = unwrap_or!(intersections.get(lane.dst), continue);
                 let inter_src = unwrap_or!(intersections.get(lane.src), continue);
 
-                next.extend(
-                    inter_dst
-                        .turns_from(lane.id)
-                        .flat_map(|(turn, _)| Some(PtrCmp(map.lanes().get(turn.dst)?))),
-                );
-
-                next.extend(
-                    inter_src
-                        .turns_to(lane.id)
-                        .flat_map(|(turn, _)| Some(PtrCmp(map.lanes().get(turn.src)?))),
-                )
+                next.extend(inter_dst.turns_from(lane.id).map(|(turn, _)| turn.dst));
+
+                next.extend(inter_src.turns_to(lane.id).map(|(turn, _)| turn.src))
             }
             std::mem::swap(&mut potential, &mut next);
+            level += 1;
+        }
+        candidates
+    }
+
+    /// Closest free spot of each parking lane on the roads within `radius` of `near`
+    fn street_spots_around(&self, near: Vec3, map: &Map, radius: f32) -> Vec<ParkingSpotID> {
+        map.spatial_map()
+            .query_around(near.xy(), radius, ProjectFilter::ROAD)
+            .filter_map(|kind| match kind {
+                ProjectKind::Road(id) => map.roads().get(id),
+                _ => None,
+            })
+            .flat_map(|road| road.lanes_iter())
+            .filter(|&(_, kind)| kind == LaneKind::Parking)
+            .filter_map(|(lane, _)| {
+                map.parking
+                    .closest_spots(lane, near)?
+                    .find(|spot| !self.reserved_spots.contains(spot))
+            })
+            .collect()
+    }
+
+    /// A free spot in each parking building within `radius` of `near`
+    /// that has an entrance on a road
+    fn building_spots_near(&self, near: Vec3, map: &Map, radius: f32) -> Vec<ParkingSpotID> {
+        map.spatial_map()
+            .query_around(near.xy(), radius, ProjectFilter::BUILDING)
+            .filter_map(|kind| match kind {
+                ProjectKind::Building(id) => map.buildings().get(id),
+                _ => None,
+            })
+            .filter(|b| matches!(b.kind, BuildingKind::Parking))
+            .filter(|b| map.parking_entrance(b.id).is_some())
+            .filter_map(|b| {
+                map.parking
+                    .building_spots(b.id)?
+                    .iter()
+                    .find(|spot| !self.reserved_spots.contains(spot))
+                    .copied()
+            })
+            .collect()
+    }
+}
+
+/// Occupancy of the parking spots over time, by square areas of [`PARKING_AREA_SIZE`]
+#[derive(Default, Serialize, Deserialize)]
+pub struct ParkingOccupancy {
+    last_sample: u32,
+    areas: BTreeMap<(i32, i32), AreaOccupancy>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct AreaOccupancy {
+    /// Reserved spots and total spots at each sample, oldest first
+    pub history: VecDeque<(u32, u32)>,
+}
+
+impl AreaOccupancy {
+    /// Share of the spots that were reserved at the last sample
+    pub fn ratio(&self) -> f32 {
+        match self.history.back() {
+            Some(&(used, capacity)) if capacity > 0 => used as f32 / capacity as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+impl ParkingOccupancy {
+    pub fn area(pos: Vec2) -> (i32, i32) {
+        (
+            (pos.x / PARKING_AREA_SIZE).floor() as i32,
+            (pos.y / PARKING_AREA_SIZE).floor() as i32,
+        )
+    }
+
+    pub fn area_bounds((x, y): (i32, i32)) -> AABB {
+        let ll = Vec2::new(x as f32, y as f32) * PARKING_AREA_SIZE;
+        AABB::new(ll, ll + Vec2::splat(PARKING_AREA_SIZE))
+    }
+
+    pub fn areas(&self) -> impl Iterator<Item = (AABB, &AreaOccupancy)> + '_ {
+        self.areas
+            .iter()
+            .map(|(&area, occ)| (Self::area_bounds(area), occ))
+    }
+
+    /// Share of all the spots that were reserved at each sample, oldest first
+    pub fn total_history(&self) -> Vec<f32> {
+        let len = self
+            .areas
+            .values()
+            .map(|a| a.history.len())
+            .max()
+            .unwrap_or(0);
+        let mut totals = vec![(0, 0); len];
+        for occ in self.areas.values() {
+            // Areas created later only have the most recent samples
+            for (total, &(used, capacity)) in totals
+                .iter_mut()
+                .skip(len - occ.history.len())
+                .zip(&occ.history)
+            {
+                total.0 += used;
+                total.1 += capacity;
+            }
+        }
+        totals
+            .into_iter()
+            .map(|(used, capacity)| used as f32 / capacity.max(1) as f32)
+            .collect()
+    }
+
+    pub fn sample(&mut self, map: &Map, pm: &ParkingManagement) {
+        let mut counts: BTreeMap<(i32, i32), (u32, u32)> = BTreeMap::new();
+        for (id, spot) in map.parking.all_spots() {
+            let count = counts
+                .entry(Self::area(spot.trans.position.xy()))
+                .or_default();
+            count.1 += 1;
+            if !pm.is_spot_free(id) {
+                count.0 += 1;
+            }
+        }
+
+        self.areas.retain(|area, _| counts.contains_key(area));
+        for (area, count) in counts {
+            let history = &mut self.areas.entry(area).or_default().history;
+            history.push_back(count);
+            if history.len() > OCCUPANCY_HISTORY {
+                history.pop_front();
+            }
         }
-        None
     }
 }
 
+#[profiling::function]
+pub fn parking_occupancy_update(_: &mut World, resources: &mut Resources) {
+    let time = resources.get::<GameTime>().unwrap();
+    let mut occupancy = resources.get_mut::<ParkingOccupancy>().unwrap();
+    if time.seconds.saturating_sub(occupancy.last_sample) < OCCUPANCY_SAMPLE_PERIOD {
+        return;
+    }
+    occupancy.last_sample = time.seconds;
+
+    let map = resources.get::<Map>().unwrap();
+    let pm = resources.get::<ParkingManagement>().unwrap();
+    occupancy.sample(&map, &pm);
+}
+
 impl SpotReservation {
     pub fn exists(&self, spots: &ParkingSpots) -> bool {
         spots.contains(self.0)
@@ -91,3 +347,81 @@ impl SpotReservation {
         map.parking_to_drive_pos(self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ParkingManagement, ParkingPlace};
+    use crate::economy::Money;
+    use crate::map::{
+        BuildingGen, BuildingKind, LanePatternBuilder, Map, RoadSegmentKind, Terrain,
+    };
+    use geom::{vec2, vec3, OBB};
+
+    #[test]
+    fn parking_building_without_street_parking() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let a = map.add_intersection(vec3(0.0, 0.0, 0.0));
+        let b = map.add_intersection(vec3(200.0, 0.0, 0.0));
+        let pat = LanePatternBuilder::new().parking(false).build();
+        map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+
+        let obb = OBB::new(vec2(100.0, 30.0), vec2(0.0, 1.0), 40.0, 40.0);
+        let id = map
+            .build_special_building(
+                &obb,
+                BuildingKind::Parking,
+                BuildingGen::Parking { levels: 2 },
+                &[],
+            )
+            .unwrap();
+        let capacity = map.parking.building_spots(id).unwrap().len();
+        assert_eq!(capacity, 2 * 16 * 5);
+
+        let mut pm = ParkingManagement::default();
+        let spots = map.parking.building_spots(id).unwrap();
+        assert!(spots.iter().all(|&spot| pm.is_spot_free(spot)));
+        for _ in 0..capacity {
+            let spot = pm.reserve_near(vec3(100.0, 0.0, 0.0), &map).unwrap();
+            assert!(!pm.is_spot_free(spot.0));
+            assert_eq!(spot.get(&map.parking).unwrap().building, Some(id));
+            assert!(spot.park_pos(&map).is_some());
+        }
+        assert!(pm.reserve_near(vec3(100.0, 0.0, 0.0), &map).is_none());
+    }
+
+    #[test]
+    fn drivers_avoid_expensive_parking() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+
+        let a = map.add_intersection(vec3(0.0, 0.0, 0.0));
+        let b = map.add_intersection(vec3(300.0, 0.0, 0.0));
+        let pat = LanePatternBuilder::new().parking(false).build();
+        map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+
+        let mut build = |x: f32| {
+            let obb = OBB::new(vec2(x, 30.0), vec2(0.0, 1.0), 40.0, 40.0);
+            map.build_special_building(
+                &obb,
+                BuildingKind::Parking,
+                BuildingGen::Parking { levels: 1 },
+                &[],
+            )
+            .unwrap()
+        };
+        let close = build(100.0);
+        let far = build(160.0);
+
+        let mut pm = ParkingManagement::default();
+        let spot = pm.reserve_near(vec3(100.0, 0.0, 0.0), &map).unwrap();
+        assert_eq!(spot.get(&map.parking).unwrap().building, Some(close));
+        pm.free(spot);
+
+        pm.set_price(ParkingPlace::Building(close), Money::new_base(10));
+        let spot = pm.reserve_near(vec3(100.0, 0.0, 0.0), &map).unwrap();
+        assert_eq!(spot.get(&map.parking).unwrap().building, Some(far));
+        assert_eq!(pm.charge(&spot, &map), Money::default());
+    }
+}
@@ -1,9 +1,14 @@
+use crate::economy::Government;
 use crate::map::{BuildingID, Map, PathKind};
-use crate::map_dynamic::{Itinerary, ParkingManagement, SpotReservation};
+use crate::map_dynamic::{
+    choose_mode, Itinerary, ModalSplit, ModeChoice, ModePreferences, ParkingManagement,
+    SpotReservation, TrafficStats, TransportMode, TripLog, TripOptions, TripRecord,
+};
 use crate::pedestrians::{put_pedestrian_in_coworld, Location};
 use crate::physics::{Collider, CollisionWorld, Kinematics};
 use crate::utils::par_command_buffer::ComponentDrop;
-use crate::vehicles::{unpark, Vehicle, VehicleID, VehicleState};
+use crate::utils::time::{GameInstant, GameTime};
+use crate::vehicles::{unpark, Vehicle, VehicleID, VehicleKind, VehicleState};
 use crate::{Egregoria, ParCommandBuffer};
 use geom::{Spline3, Transform, Vec3};
 use hecs::{Component, Entity, Ref, World};
@@ -20,8 +25,33 @@ pub struct Router {
     cur_dest: Option<Destination>,
     vehicle: Option<VehicleID>,
     pub personal_car: Option<VehicleID>,
+    pub personal_bike: Option<VehicleID>,
+    pub preferences: ModePreferences,
+    trip: Option<TripStart>,
+    /// Route of the vehicle estimated when choosing the mode, used by the next `DriveTo`
+    planned_route: Option<Itinerary>,
 }
 
+/// The trip being made, to measure its duration once it is over
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct TripStart {
+    pub departure: GameInstant,
+    pub mode: TransportMode,
+    pub origin: Option<BuildingID>,
+    pub from: Vec3,
+    pub destination: Option<BuildingID>,
+    pub to: Vec3,
+    /// Length of the route followed so far, in meters
+    pub distance: f32,
+    /// Position of the human when `distance` was last updated
+    pub last_pos: Vec3,
+}
+
+debug_inspect_impl!(TripStart);
+
+/// How far to the right of its lane a bike is left, in meters
+const STORE_OFFSET: f32 = 3.0;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Destination {
     Outside(Vec3),
@@ -48,28 +78,55 @@ debug_inspect_impl!(RoutingStep);
 pub fn routing_changed_system(world: &mut World, resources: &mut Resources) {
     let ra = &*resources.get().unwrap();
     let rb = &mut *resources.get_mut().unwrap();
+    let rc = &mut *resources.get_mut().unwrap();
+    let rd = &*resources.get().unwrap();
     world
-        .query::<(&mut Router, &Location)>()
+        .query::<(&mut Router, &Transform, &Location)>()
         .iter()
-        .for_each(|(_, (a, b))| {
-            routing_changed(ra, rb, a, b, world);
+        .for_each(|(_, (a, b, c))| {
+            routing_changed(ra, rb, rc, rd, a, b, c, world);
         });
 }
 
 pub fn routing_changed(
     map: &Map,
     parking: &mut ParkingManagement,
+    split: &mut ModalSplit,
+    time: &GameTime,
     router: &mut Router,
+    trans: &Transform,
     loc: &Location,
     world: &World,
 ) {
     if router.cur_dest != router.target_dest {
         let dest = unwrap_ret!(router.target_dest);
+        let origin = match *loc {
+            Location::Building(b) => Some(b),
+            _ => None,
+        };
+        let from = origin
+            .and_then(|b| map.buildings().get(b))
+            .map_or(trans.position, |b| b.door_pos);
 
         router.clear_steps(parking);
         match dest {
             Destination::Outside(pos) => {
-                router.steps = unwrap_ret!(router.steps_to(pos, parking, map, loc, world));
+                let (steps, mode) =
+                    unwrap_ret!(router.steps_to(pos, trans, parking, map, loc, world));
+                router.steps = steps;
+                router.start_trip(
+                    split,
+                    TripStart {
+                        departure: time.instant(),
+                        mode,
+                        origin,
+                        from,
+                        destination: None,
+                        to: pos,
+                        distance: 0.0,
+                        last_pos: from,
+                    },
+                );
             }
             Destination::Building(build) => {
                 if let Location::Building(cur_build) = loc {
@@ -80,8 +137,23 @@ pub fn routing_changed(
                 }
 
                 let door_pos = unwrap_ret!(map.buildings().get(build)).door_pos;
-                router.steps = unwrap_ret!(router.steps_to(door_pos, parking, map, loc, world));
+                let (steps, mode) =
+                    unwrap_ret!(router.steps_to(door_pos, trans, parking, map, loc, world));
+                router.steps = steps;
                 router.steps.push(RoutingStep::GetInBuilding(build));
+                router.start_trip(
+                    split,
+                    TripStart {
+                        departure: time.instant(),
+                        mode,
+                        origin,
+                        from,
+                        destination: Some(build),
+                        to: door_pos,
+                        distance: 0.0,
+                        last_pos: from,
+                    },
+                );
             }
         }
 
@@ -95,6 +167,7 @@ pub fn routing_changed(
 pub fn routing_update_system(world: &mut World, resources: &mut Resources) {
     let ra = &*resources.get().unwrap();
     let rb = &*resources.get().unwrap();
+    let rc = &*resources.get().unwrap();
     world
         .query::<(
             &Transform,
@@ -106,13 +179,16 @@ pub fn routing_update_system(world: &mut World, resources: &mut Resources) {
         .iter_batched(32)
         .par_bridge()
         .for_each(|batch| {
-            batch.for_each(|(e, (a, b, c, d, f))| routing_update(ra, rb, e, a, b, c, d, f, world))
+            batch.for_each(|(e, (a, b, c, d, f))| {
+                routing_update(ra, rb, rc, e, a, b, c, d, f, world)
+            })
         });
 }
 
 pub fn routing_update(
     map: &Map,
     cbuf: &ParCommandBuffer,
+    time: &GameTime,
     body: Entity,
     trans: &Transform,
     itin: &Itinerary,
@@ -137,6 +213,11 @@ pub fn routing_update(
             .unwrap_or_else(|| trans.position),
     };
 
+    if let Some(ref mut trip) = router.trip {
+        trip.distance += trip.last_pos.distance(pos);
+        trip.last_pos = pos;
+    }
+
     let mut cur_step_over = true;
 
     if let Some(ref step) = router.cur_step {
@@ -146,7 +227,7 @@ pub fn routing_update(
                 .map(|x| x.has_ended(0.0))
                 .unwrap_or(true),
             RoutingStep::Park(vehicle, _) => comp::<Vehicle>(world, vehicle.0)
-                .map(|x| matches!(x.state, VehicleState::Parked(_)))
+                .map(|x| matches!(x.state, VehicleState::Parked(_) | VehicleState::Stored))
                 .unwrap_or(true),
             RoutingStep::Unpark(_) => true,
             RoutingStep::GetInVehicle(_) => true,
@@ -182,13 +263,25 @@ pub fn routing_update(
 
     router.cur_step = router.steps.pop();
 
+    if router.cur_step.is_none() && router.cur_dest == router.target_dest {
+        if let Some(trip) = router.trip.take() {
+            end_trip(body, cbuf, time, trip);
+        }
+        return;
+    }
+
     if let Some(ref mut next_step) = router.cur_step {
         match *next_step {
             RoutingStep::WalkTo(obj) => {
                 cbuf.add_component(body, Itinerary::wait_for_reroute(PathKind::Pedestrian, obj));
             }
             RoutingStep::DriveTo(vehicle, obj) => {
-                let route = Itinerary::wait_for_reroute(PathKind::Vehicle, obj);
+                let kind = comp::<Vehicle>(world, vehicle.0)
+                    .map_or(PathKind::Vehicle, |v| v.kind.path_kind());
+                let route = match router.planned_route.take() {
+                    Some(it) if it.end_pos() == Some(obj) => it,
+                    _ => Itinerary::wait_for_reroute(kind, obj),
+                };
                 cbuf.add_component(vehicle.0, route);
             }
             RoutingStep::Park(vehicle, ref mut spot) => {
@@ -199,6 +292,9 @@ pub fn routing_update(
                     }
 
                     cbuf.exec_ent(vehicle.0, park(vehicle, x));
+                } else {
+                    cbuf.remove_component_drop::<Collider>(vehicle.0);
+                    cbuf.exec_ent(vehicle.0, store(vehicle));
                 }
             }
             RoutingStep::Unpark(vehicle) => {
@@ -248,6 +344,25 @@ fn comp<T: Component>(sw: &World, e: Entity) -> Option<Ref<T>> {
     sw.get(e).ok()
 }
 
+/// The last step of the trip is over, log it
+fn end_trip(body: Entity, cbuf: &ParCommandBuffer, time: &GameTime, trip: TripStart) {
+    let duration = trip.departure.elapsed(time) as f32;
+    cbuf.exec_on::<TrafficStats>(body, move |stats| stats.record_trip(trip.mode, duration));
+    cbuf.exec_on::<TripLog>(body, move |log| {
+        log.push(TripRecord {
+            origin: trip.origin,
+            origin_pos: trip.from,
+            destination: trip.destination,
+            destination_pos: trip.to,
+            mode: trip.mode,
+            departure: trip.departure,
+            duration,
+            distance: trip.distance,
+            crow_distance: trip.from.distance(trip.to),
+        })
+    });
+}
+
 fn walk_inside(body: Entity, cbuf: &ParCommandBuffer, kin: &mut Kinematics) {
     cbuf.remove_component_drop::<Collider>(body);
     kin.speed = 0.0;
@@ -281,6 +396,8 @@ fn park(vehicle: VehicleID, spot_resa: SpotReservation) -> impl FnOnce(&mut Egre
             from_derivative: trans.dir * 2.0,
             to_derivative: spot.trans.dir * 2.0,
         };
+        let price = goria.write::<ParkingManagement>().charge(&spot_resa, &map);
+        goria.write::<Government>().money += price;
         drop(map);
         drop(trans);
 
@@ -290,13 +407,29 @@ fn park(vehicle: VehicleID, spot_resa: SpotReservation) -> impl FnOnce(&mut Egre
     }
 }
 
+/// Leaves the vehicle on the side of its lane without taking a parking spot
+fn store(vehicle: VehicleID) -> impl FnOnce(&mut Egregoria) {
+    move |goria| {
+        let mut trans = unwrap_ret!(goria.comp_mut::<Transform>(vehicle.0));
+        trans.position += trans.dir.cross(Vec3::Z) * STORE_OFFSET;
+        drop(trans);
+
+        unwrap_ret!(goria.comp_mut::<Vehicle>(vehicle.0)).state = VehicleState::Stored;
+        unwrap_ret!(goria.comp_mut::<Kinematics>(vehicle.0)).speed = 0.0;
+    }
+}
+
 impl Router {
-    pub fn new(personal_car: Option<VehicleID>) -> Self {
+    pub fn new(personal_car: Option<VehicleID>, personal_bike: Option<VehicleID>) -> Self {
         Self {
             steps: vec![],
             cur_step: None,
             target_dest: None,
             personal_car,
+            personal_bike,
+            preferences: ModePreferences::default(),
+            trip: None,
+            planned_route: None,
             vehicle: personal_car,
             cur_dest: None,
         }
@@ -307,6 +440,7 @@ impl Router {
     }
 
     fn clear_steps(&mut self, parking: &mut ParkingManagement) {
+        self.planned_route = None;
         for s in self.steps.drain(..).chain(self.cur_step.take()) {
             if let RoutingStep::Park(_, Some(spot)) = s {
                 parking.free(spot);
@@ -314,6 +448,11 @@ impl Router {
         }
     }
 
+    fn start_trip(&mut self, split: &mut ModalSplit, trip: TripStart) {
+        split.record(trip.mode);
+        self.trip = Some(trip);
+    }
+
     pub fn reset_dest(&mut self) {
         self.cur_dest = None;
     }
@@ -329,20 +468,98 @@ impl Router {
         false
     }
 
+    /// Vehicle to use to go from `from` to `obj` and the mode it corresponds to: the one given
+    /// by `use_vehicle` if it isn't a personal one, otherwise the one of the mode chosen
+    /// by `choose_mode`, whose route is kept for the vehicle
+    fn trip_vehicle(
+        &mut self,
+        from: Vec3,
+        obj: Vec3,
+        loc: &Location,
+        map: &Map,
+        parking: &ParkingManagement,
+        world: &World,
+    ) -> (Option<VehicleID>, TransportMode) {
+        self.planned_route = None;
+        let vehicle_mode = |v: VehicleID| match comp::<Vehicle>(world, v.0).map(|x| x.kind) {
+            Some(VehicleKind::Bicycle) => TransportMode::Bike,
+            _ => TransportMode::Car,
+        };
+        if let Location::Vehicle(v) = *loc {
+            return (Some(v), vehicle_mode(v));
+        }
+        if self.vehicle != self.personal_car {
+            return (
+                self.vehicle,
+                self.vehicle.map_or(TransportMode::Walk, vehicle_mode),
+            );
+        }
+
+        let pos = |v: Option<VehicleID>| Some(comp::<Transform>(world, v?.0)?.position);
+        let trip = TripOptions {
+            from,
+            to: obj,
+            bike: pos(self.personal_bike),
+            car: pos(self.vehicle),
+        };
+        let ModeChoice { mode, route } = choose_mode(map, parking, &self.preferences, trip);
+        self.planned_route = route;
+        let vehicle = match mode {
+            TransportMode::Walk => None,
+            TransportMode::Bike => self.personal_bike,
+            TransportMode::Car => self.vehicle,
+        };
+        (vehicle, mode)
+    }
+
     fn steps_to(
         &mut self,
         obj: Vec3,
+        trans: &Transform,
         parking: &mut ParkingManagement,
         map: &Map,
         loc: &Location,
         world: &World,
-    ) -> Option<Vec<RoutingStep>> {
+    ) -> Option<(Vec<RoutingStep>, TransportMode)> {
         let mut steps = vec![];
-        if let Location::Building(cur_build) = loc {
-            steps.push(RoutingStep::GetOutBuilding(*cur_build));
-        }
+        let from = match *loc {
+            Location::Building(cur_build) => {
+                steps.push(RoutingStep::GetOutBuilding(cur_build));
+                map.buildings()
+                    .get(cur_build)
+                    .map_or(trans.position, |b| b.door_pos)
+            }
+            _ => trans.position,
+        };
+
+        let (vehicle, mode) = self.trip_vehicle(from, obj, loc, map, parking, world);
+        let parks = match vehicle {
+            Some(v) => match comp::<Vehicle>(world, v.0) {
+                Some(x) => x.kind.parks(),
+                None => {
+                    if vehicle == self.personal_bike {
+                        self.personal_bike = None;
+                    } else {
+                        self.vehicle = None;
+                    }
+                    return None;
+                }
+            },
+            None => true,
+        };
+
+        if let (Some(bike), false) = (vehicle, parks) {
+            if !matches!(loc, Location::Vehicle(_)) {
+                let trans = comp::<Transform>(world, bike.0)?;
+                steps.push(RoutingStep::WalkTo(trans.position));
+                steps.push(RoutingStep::GetInVehicle(bike));
+                steps.push(RoutingStep::Unpark(bike));
+            }
 
-        if let Some(car) = self.vehicle {
+            steps.push(RoutingStep::DriveTo(bike, obj));
+            steps.push(RoutingStep::Park(bike, None));
+            steps.push(RoutingStep::GetOutVehicle(bike));
+        } else if let Some(car) = vehicle {
             let spot_resa = parking.reserve_near(obj, map)?;
             let parking_pos = match spot_resa.park_pos(map) {
                 Some(x) => x,
@@ -370,6 +587,6 @@ impl Router {
         }
 
         steps.push(RoutingStep::WalkTo(obj));
-        Some(steps)
+        Some((steps, mode))
     }
 }
@@ -0,0 +1,63 @@
+use crate::map::{Map, RoutingLandmarks, RoutingLandmarksInput};
+use crate::utils::time::GameTime;
+use hecs::World;
+use resources::Resources;
+use serde::{Deserialize, Serialize};
+use std::thread::JoinHandle;
+
+/// Seconds the map must stay unchanged before the landmarks are rebuilt, as the player
+/// usually makes many edits in a row
+const REBUILD_DELAY: u32 = 3;
+
+/// Seconds given to the background rebuild before its result is swapped in.
+/// The swap happens at a fixed game time so the routes stay deterministic.
+const REBUILD_TIME: u32 = 2;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct LandmarksRebuild {
+    dirt_id: u32,
+    since: u32,
+    /// Running rebuild and when to swap it in
+    #[serde(skip)]
+    pending: Option<(u32, JoinHandle<RoutingLandmarks>)>,
+}
+
+/// Rebuilds all the landmarks on another thread once the map has stayed unchanged for
+/// `REBUILD_DELAY` seconds. Only the snapshot of the graphs is taken on the tick, which
+/// only blocks again if the rebuild takes longer than `REBUILD_TIME`.
+#[profiling::function]
+pub fn landmarks_update(_: &mut World, resources: &mut Resources) {
+    let mut map = resources.get_mut::<Map>().unwrap();
+    let mut state = resources.get_mut::<LandmarksRebuild>().unwrap();
+    let time = resources.get::<GameTime>().unwrap();
+
+    if let Some((swap_at, _)) = state.pending {
+        if time.seconds < swap_at {
+            return;
+        }
+        if let Some((_, job)) = state.pending.take() {
+            // If the map changed in the meantime they are not valid and will be rebuilt
+            if let Ok(landmarks) = job.join() {
+                map.landmarks = landmarks;
+            }
+        }
+    }
+
+    if map.landmarks.vehicle.is_valid(&map) {
+        return;
+    }
+
+    if state.dirt_id != map.dirt_id.0 {
+        state.dirt_id = map.dirt_id.0;
+        state.since = time.seconds;
+        return;
+    }
+
+    if time.seconds >= state.since + REBUILD_DELAY {
+        let input = RoutingLandmarksInput::new(&map);
+        state.pending = Some((
+            time.seconds + REBUILD_TIME,
+            std::thread::spawn(move || input.build()),
+        ));
+    }
+}
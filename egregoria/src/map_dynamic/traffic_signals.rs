@@ -0,0 +1,376 @@
+use crate::map::{IntersectionID, LaneID, LightPolicy, Map, TrafficBehavior, TrafficControl};
+use crate::map::{Traversable, TraverseKind, WALK_CLEARANCE};
+use crate::pedestrians::Pedestrian;
+use crate::vehicles::Vehicle;
+use crate::{GameTime, Itinerary};
+use geom::Transform;
+use hecs::World;
+use resources::Resources;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Distance in meters before the stop line in which vehicles are detected
+const DETECTOR_LENGTH: f32 = 40.0;
+/// Distance in meters from the curb in which pedestrians waiting to cross are detected
+const CROSSWALK_DETECTOR_LENGTH: f32 = 3.0;
+/// Orange duration in seconds. The crosswalks over the roads of the next phase show
+/// a flashing don't-walk meanwhile, so it is also their clearance.
+const ORANGE_LENGTH: f32 = WALK_CLEARANCE as f32;
+
+/// Vehicles and pedestrians detected around the actuated intersections
+#[derive(Default)]
+struct Detection {
+    /// Vehicles close to the stop line of each lane
+    vehicles: BTreeMap<LaneID, u32>,
+    /// Pedestrians waiting to cross the road of each lane, by the lane controlling their crosswalk
+    pedestrians: BTreeMap<LaneID, u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ActuatedController {
+    phases: Vec<Vec<LaneID>>,
+    cur_phase: usize,
+    /// Phase getting the green once the orange of the current one is over, None while green
+    next_phase: Option<usize>,
+    /// Time spent in the current state in seconds
+    elapsed: f32,
+}
+
+impl ActuatedController {
+    fn new(phases: Vec<Vec<LaneID>>) -> Self {
+        Self {
+            phases,
+            cur_phase: 0,
+            next_phase: None,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Vehicles waiting on the lanes of the phase, plus the pedestrians waiting to cross
+    /// the roads of the other phases, who can walk as soon as this one is green
+    fn demand(&self, phase: usize, detection: &Detection) -> u32 {
+        let count = |m: &BTreeMap<LaneID, u32>, l: &LaneID| m.get(l).copied().unwrap_or(0);
+        let vehicles: u32 = self
+            .phases
+            .get(phase)
+            .into_iter()
+            .flatten()
+            .map(|l| count(&detection.vehicles, l))
+            .sum();
+        let pedestrians: u32 = self
+            .phases
+            .iter()
+            .enumerate()
+            .filter(|&(p, _)| p != phase)
+            .flat_map(|(_, lanes)| lanes)
+            .map(|l| count(&detection.pedestrians, l))
+            .sum();
+        vehicles + pedestrians
+    }
+
+    fn step(&mut self, delta: f32, min_green: f32, max_green: f32, detection: &Detection) {
+        let n = self.phases.len();
+        if n == 0 {
+            return;
+        }
+        self.elapsed += delta;
+
+        if let Some(next) = self.next_phase {
+            if self.elapsed < ORANGE_LENGTH {
+                return;
+            }
+            self.cur_phase = next;
+            self.next_phase = None;
+            self.elapsed = 0.0;
+            return;
+        }
+
+        if self.elapsed < min_green {
+            return;
+        }
+
+        let others_waiting = (0..n).any(|p| p != self.cur_phase && self.demand(p, detection) > 0);
+        if !others_waiting {
+            // Rest in green
+            return;
+        }
+
+        if self.demand(self.cur_phase, detection) == 0 || self.elapsed >= max_green {
+            // Skip the phases nobody is waiting for
+            let cur = self.cur_phase;
+            self.next_phase = Some(
+                (1..n)
+                    .map(|k| (cur + k) % n)
+                    .find(|&p| self.demand(p, detection) > 0)
+                    .unwrap_or((cur + 1) % n),
+            );
+            self.elapsed = 0.0;
+        }
+    }
+
+    fn behavior(&self, phase: usize) -> TrafficBehavior {
+        if phase != self.cur_phase {
+            TrafficBehavior::RED
+        } else if self.next_phase.is_some() {
+            TrafficBehavior::ORANGE
+        } else {
+            TrafficBehavior::GREEN
+        }
+    }
+
+    /// Signal of the crosswalks over the roads of the phase: walk while it is red,
+    /// flashing don't-walk while it is about to get the green
+    fn walk(&self, phase: usize) -> TrafficBehavior {
+        if phase == self.cur_phase {
+            TrafficBehavior::RED
+        } else if self.next_phase == Some(phase) {
+            TrafficBehavior::ORANGE
+        } else {
+            TrafficBehavior::GREEN
+        }
+    }
+}
+
+/// State of the actuated traffic lights
+#[derive(Default, Serialize, Deserialize)]
+pub struct TrafficSignals {
+    controllers: BTreeMap<IntersectionID, ActuatedController>,
+    map_dirt_id: u32,
+}
+
+impl TrafficSignals {
+    fn sync_with_map(&mut self, map: &Map) {
+        if self.map_dirt_id == map.dirt_id.0 {
+            return;
+        }
+        self.map_dirt_id = map.dirt_id.0;
+
+        let mut controllers = BTreeMap::new();
+        for (id, inter) in map.intersections() {
+            if !matches!(inter.light_policy, LightPolicy::Actuated { .. }) {
+                continue;
+            }
+            let phases = LightPolicy::light_phases(inter, map.roads());
+            let controller = match self.controllers.remove(&id) {
+                Some(c) if c.phases == phases => c,
+                _ => ActuatedController::new(phases),
+            };
+            controllers.insert(id, controller);
+        }
+        self.controllers = controllers;
+    }
+}
+
+/// Counts the vehicles close to the stop line of actuated lanes and the pedestrians
+/// waiting at the curb of the crosswalks over them
+fn detect_waiting(world: &mut World, map: &Map) -> Detection {
+    let mut waiting = BTreeMap::new();
+    let lanes = map.lanes();
+    for (_, (_, trans, it)) in world.query_mut::<(&Vehicle, &Transform, &Itinerary)>() {
+        let l_id = match it.get_travers() {
+            Some(Traversable {
+                kind: TraverseKind::Lane(l_id),
+                ..
+            }) => *l_id,
+            _ => continue,
+        };
+        let lane = unwrap_cont!(lanes.get(l_id));
+        if !lane.control.is_actuated() {
+            continue;
+        }
+        if lane
+            .control_point()
+            .is_close(trans.position, DETECTOR_LENGTH)
+        {
+            *waiting.entry(l_id).or_insert(0) += 1;
+        }
+    }
+
+    let mut pedestrians = BTreeMap::new();
+    for (_, (_, trans, it)) in world.query_mut::<(&Pedestrian, &Transform, &Itinerary)>() {
+        let id = match it.peek_travers().map(|t| t.kind) {
+            Some(TraverseKind::Turn(id)) => id,
+            _ => continue,
+        };
+        let turn = unwrap_cont!(map
+            .intersections()
+            .get(id.parent)
+            .and_then(|inter| inter.find_turn(id)));
+        if !turn.kind.is_crosswalk() {
+            continue;
+        }
+        let at_curb = turn
+            .points
+            .first()
+            .is_close(trans.position, CROSSWALK_DETECTOR_LENGTH)
+            || turn
+                .points
+                .last()
+                .is_close(trans.position, CROSSWALK_DETECTOR_LENGTH);
+        if !at_curb {
+            continue;
+        }
+        let l_id = unwrap_cont!(map.crosswalk_light_lane(id));
+        if lanes.get(l_id).map_or(false, |l| l.control.is_actuated()) {
+            *pedestrians.entry(l_id).or_insert(0) += 1;
+        }
+    }
+
+    Detection {
+        vehicles: waiting,
+        pedestrians,
+    }
+}
+
+#[profiling::function]
+pub fn traffic_signals_update(world: &mut World, resources: &mut Resources) {
+    let mut map = resources.get_mut::<Map>().unwrap();
+    let mut signals = resources.get_mut::<TrafficSignals>().unwrap();
+    let time = resources.get::<GameTime>().unwrap();
+
+    signals.sync_with_map(&map);
+    if signals.controllers.is_empty() {
+        return;
+    }
+
+    let waiting = detect_waiting(world, &map);
+    let map = &mut *map;
+
+    for (id, controller) in &mut signals.controllers {
+        if let Some(LightPolicy::Actuated {
+            min_green,
+            max_green,
+        }) = map.intersections.get(*id).map(|i| i.light_policy)
+        {
+            controller.step(time.delta, min_green as f32, max_green as f32, &waiting);
+        }
+
+        for (i, phase) in controller.phases.iter().enumerate() {
+            let control = TrafficControl::Actuated {
+                vehicles: controller.behavior(i),
+                walk: controller.walk(i),
+            };
+            for &lane in phase {
+                unwrap_cont!(map.lanes.get_mut(lane)).control = control;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActuatedController, Detection, ORANGE_LENGTH};
+    use crate::map::{LaneID, TrafficBehavior};
+    use slotmap::SlotMap;
+
+    /// Controller of two phases with one lane each
+    fn two_phases() -> (ActuatedController, LaneID, LaneID) {
+        let mut keys = SlotMap::<LaneID, ()>::with_key();
+        let (a, b) = (keys.insert(()), keys.insert(()));
+        (ActuatedController::new(vec![vec![a], vec![b]]), a, b)
+    }
+
+    fn run(c: &mut ActuatedController, seconds: f32, detection: &Detection) {
+        for _ in 0..(seconds * 10.0) as u32 {
+            c.step(0.1, 5.0, 20.0, detection);
+        }
+    }
+
+    #[test]
+    fn pedestrians_get_the_walk_at_a_light_resting_in_green() {
+        let (mut c, a, _) = two_phases();
+        let mut detection = Detection::default();
+        detection.vehicles.insert(a, 3);
+
+        // Nobody waits on the side road: rest in green, the main road is never crossed
+        run(&mut c, 60.0, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+        assert_eq!(c.walk(0), TrafficBehavior::RED);
+
+        // Pedestrians waiting to cross the main road are demand for the side phase
+        detection.pedestrians.insert(a, 2);
+        let mut t = 0.0;
+        while c.walk(0) != TrafficBehavior::GREEN {
+            run(&mut c, 0.1, &detection);
+            t += 0.1;
+            assert!(t < 25.0, "no walk signal after {}s", t);
+        }
+        assert_eq!(c.behavior(0), TrafficBehavior::RED);
+
+        // The crosswalks over the side road flash don't-walk before the side road
+        // gets the green, and walking stops when the main road is about to get it back
+        detection.pedestrians.clear();
+        while c.next_phase.is_none() {
+            run(&mut c, 0.1, &detection);
+        }
+        assert_eq!(c.walk(0), TrafficBehavior::ORANGE);
+        assert_eq!(c.behavior(0), TrafficBehavior::RED);
+        run(&mut c, ORANGE_LENGTH + 0.1, &detection);
+        assert_eq!(c.walk(0), TrafficBehavior::RED);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    fn green_is_held_for_min_green() {
+        let (mut c, _, b) = two_phases();
+        let mut detection = Detection::default();
+        detection.vehicles.insert(b, 1);
+
+        run(&mut c, 4.9, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+        run(&mut c, 0.2, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::ORANGE);
+        assert_eq!(c.next_phase, Some(1));
+
+        run(&mut c, ORANGE_LENGTH + 0.1, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::RED);
+        assert_eq!(c.behavior(1), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    fn green_is_cut_at_max_green() {
+        let (mut c, a, b) = two_phases();
+        let mut detection = Detection::default();
+        detection.vehicles.insert(a, 4);
+        detection.vehicles.insert(b, 1);
+
+        run(&mut c, 19.9, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+        run(&mut c, 0.2, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::ORANGE);
+
+        run(&mut c, ORANGE_LENGTH + 0.1, &detection);
+        assert_eq!(c.behavior(1), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn phases_without_demand_are_skipped() {
+        let mut keys = SlotMap::<LaneID, ()>::with_key();
+        let lanes: Vec<LaneID> = (0..3).map(|_| keys.insert(())).collect();
+        let mut c = ActuatedController::new(lanes.iter().map(|&l| vec![l]).collect());
+        let mut detection = Detection::default();
+        detection.vehicles.insert(lanes[2], 1);
+
+        run(&mut c, 5.1, &detection);
+        assert_eq!(c.next_phase, Some(2));
+
+        run(&mut c, ORANGE_LENGTH + 0.1, &detection);
+        assert_eq!(c.behavior(1), TrafficBehavior::RED);
+        assert_eq!(c.behavior(2), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    fn rests_in_green_without_other_demand() {
+        let (mut c, a, _) = two_phases();
+        let mut detection = Detection::default();
+
+        run(&mut c, 100.0, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+
+        detection.vehicles.insert(a, 5);
+        run(&mut c, 100.0, &detection);
+        assert_eq!(c.behavior(0), TrafficBehavior::GREEN);
+        assert_eq!(c.next_phase, None);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::map::{IntersectionID, LaneID, Map, TraverseKind};
+use crate::map_dynamic::{Itinerary, TransportMode};
+use crate::physics::Kinematics;
+use crate::utils::time::{GameTime, SECONDS_PER_HOUR};
+use crate::vehicles::{Vehicle, VehicleState};
+use geom::Transform;
+use hecs::{Entity, World};
+use resources::Resources;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Number of ticks between two samples of the vehicles
+const STATS_SAMPLE_PERIOD: u32 = 5;
+/// Number of finished game hours kept
+const STATS_HISTORY: usize = 48;
+/// Vehicles slower than this close to the end of their lane are waiting for the
+/// intersection, in m/s
+const DELAY_SPEED: f32 = 1.0;
+/// How close to the end of the lane a vehicle waits for the intersection, in meters
+const DELAY_DIST: f32 = 30.0;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LaneHourStats {
+    /// Number of vehicles that entered the lane
+    pub count: u32,
+    speed_sum: f32,
+    speed_samples: u32,
+    /// Sum over the samples of the share of the lane covered by vehicles
+    occupancy_sum: f32,
+}
+
+impl LaneHourStats {
+    /// Average speed of the vehicles on the lane, in m/s
+    pub fn avg_speed(&self) -> Option<f32> {
+        (self.speed_samples > 0).then(|| self.speed_sum / self.speed_samples as f32)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct IntersectionHourStats {
+    /// Number of vehicles that went through
+    pub count: u32,
+    /// Total time vehicles spent waiting to go through, in seconds
+    pub delay: f32,
+}
+
+impl IntersectionHourStats {
+    /// Average waiting time of the vehicles going through, in seconds
+    pub fn avg_delay(&self) -> Option<f32> {
+        (self.count > 0).then(|| self.delay / self.count as f32)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TripHourStats {
+    /// Number of trips finished during the hour
+    pub count: u32,
+    /// In seconds
+    pub total_duration: f32,
+}
+
+impl TripHourStats {
+    /// In seconds
+    pub fn avg_duration(&self) -> Option<f32> {
+        (self.count > 0).then(|| self.total_duration / self.count as f32)
+    }
+}
+
+/// Traffic measured during one game hour
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HourStats {
+    /// Game hours elapsed since the start of the game
+    pub hour: u32,
+    samples: u32,
+    lanes: BTreeMap<LaneID, LaneHourStats>,
+    intersections: BTreeMap<IntersectionID, IntersectionHourStats>,
+    trips: BTreeMap<TransportMode, TripHourStats>,
+}
+
+impl HourStats {
+    fn new(hour: u32) -> Self {
+        Self {
+            hour,
+            ..Default::default()
+        }
+    }
+
+    pub fn lane(&self, id: LaneID) -> Option<&LaneHourStats> {
+        self.lanes.get(&id)
+    }
+
+    pub fn lanes(&self) -> impl Iterator<Item = (LaneID, &LaneHourStats)> + '_ {
+        self.lanes.iter().map(|(&id, s)| (id, s))
+    }
+
+    /// Average share of the lane covered by vehicles, between 0 and 1
+    pub fn lane_occupancy(&self, id: LaneID) -> f32 {
+        match self.lanes.get(&id) {
+            Some(s) if self.samples > 0 => (s.occupancy_sum / self.samples as f32).min(1.0),
+            _ => 0.0,
+        }
+    }
+
+    pub fn intersection(&self, id: IntersectionID) -> Option<&IntersectionHourStats> {
+        self.intersections.get(&id)
+    }
+
+    pub fn intersections(
+        &self,
+    ) -> impl Iterator<Item = (IntersectionID, &IntersectionHourStats)> + '_ {
+        self.intersections.iter().map(|(&id, s)| (id, s))
+    }
+
+    pub fn trips(&self, mode: TransportMode) -> Option<&TripHourStats> {
+        self.trips.get(&mode)
+    }
+}
+
+/// Traffic statistics of the last game hours, kept in the save
+#[derive(Default, Serialize, Deserialize)]
+pub struct TrafficStats {
+    /// Finished hours, oldest first
+    history: VecDeque<HourStats>,
+    current: HourStats,
+    /// Traversable each vehicle was on at the last sample, to count the vehicles entering them
+    travers: BTreeMap<Entity, TraverseKind>,
+    ticks: u32,
+}
+
+impl TrafficStats {
+    /// The hour being measured
+    pub fn current(&self) -> &HourStats {
+        &self.current
+    }
+
+    /// The last finished hour, or the current one at the start of the game
+    pub fn latest(&self) -> &HourStats {
+        self.history.back().unwrap_or(&self.current)
+    }
+
+    pub fn hour(&self, hour: u32) -> Option<&HourStats> {
+        self.hours().find(|h| h.hour == hour)
+    }
+
+    /// Kept hours, oldest first
+    pub fn hours(&self) -> impl Iterator<Item = &HourStats> + '_ {
+        self.history.iter().chain(std::iter::once(&self.current))
+    }
+
+    /// Stats of the lane for each kept hour, oldest first
+    pub fn lane_history(&self, id: LaneID) -> impl Iterator<Item = (u32, &LaneHourStats)> + '_ {
+        self.hours()
+            .filter_map(move |h| Some((h.hour, h.lanes.get(&id)?)))
+    }
+
+    pub fn record_trip(&mut self, mode: TransportMode, duration: f32) {
+        let s = self.current.trips.entry(mode).or_default();
+        s.count += 1;
+        s.total_duration += duration;
+    }
+
+    fn start_hour(&mut self, hour: u32) {
+        if self.current.hour == hour {
+            return;
+        }
+        let finished = std::mem::replace(&mut self.current, HourStats::new(hour));
+        if self.history.len() == STATS_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(finished);
+    }
+}
+
+#[profiling::function]
+pub fn traffic_stats_update(world: &mut World, resources: &mut Resources) {
+    let mut stats = resources.get_mut::<TrafficStats>().unwrap();
+    stats.ticks += 1;
+    if stats.ticks % STATS_SAMPLE_PERIOD != 0 {
+        return;
+    }
+    let map = resources.get::<Map>().unwrap();
+    let time = resources.get::<GameTime>().unwrap();
+    let stats = &mut *stats;
+
+    stats.start_hour(time.seconds / SECONDS_PER_HOUR as u32);
+    let dt = time.delta * STATS_SAMPLE_PERIOD as f32;
+    let cur = &mut stats.current;
+    cur.samples += 1;
+
+    let mut travers = BTreeMap::new();
+    for (e, (v, it, trans, kin)) in
+        world.query_mut::<(&Vehicle, &Itinerary, &Transform, &Kinematics)>()
+    {
+        if !matches!(v.state, VehicleState::Driving | VehicleState::Panicking(_)) {
+            continue;
+        }
+        let kind = unwrap_cont!(it.get_travers()).kind;
+        let entered = stats.travers.get(&e) != Some(&kind);
+        travers.insert(e, kind);
+
+        match kind {
+            TraverseKind::Lane(id) => {
+                let lane = unwrap_cont!(map.lanes().get(id));
+                let s = cur.lanes.entry(id).or_default();
+                if entered {
+                    s.count += 1;
+                }
+                s.speed_sum += kin.speed;
+                s.speed_samples += 1;
+                s.occupancy_sum += v.kind.width() / lane.points.length().max(1.0);
+
+                if kin.speed < DELAY_SPEED
+                    && trans.position.is_close(lane.points.last(), DELAY_DIST)
+                {
+                    cur.intersections.entry(lane.dst).or_default().delay += dt;
+                }
+            }
+            TraverseKind::Turn(id) => {
+                let s = cur.intersections.entry(id.parent).or_default();
+                if entered {
+                    s.count += 1;
+                }
+                if kin.speed < DELAY_SPEED {
+                    s.delay += dt;
+                }
+            }
+        }
+    }
+    stats.travers = travers;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{traffic_stats_update, TrafficStats, STATS_HISTORY, STATS_SAMPLE_PERIOD};
+    use crate::map::{LanePatternBuilder, Map, PathKind, RoadSegmentKind, Terrain, TraverseKind};
+    use crate::map_dynamic::{Itinerary, TransportMode};
+    use crate::physics::Kinematics;
+    use crate::utils::time::GameTime;
+    use crate::vehicles::{Vehicle, VehicleKind, VehicleState};
+    use geom::{vec3, Color, Transform};
+    use hecs::World;
+    use resources::Resources;
+
+    #[test]
+    fn hours_are_kept_in_order() {
+        let mut stats = TrafficStats::default();
+        stats.record_trip(TransportMode::Car, 100.0);
+        stats.record_trip(TransportMode::Car, 200.0);
+        stats.start_hour(1);
+        assert_eq!(
+            stats
+                .latest()
+                .trips(TransportMode::Car)
+                .unwrap()
+                .avg_duration(),
+            Some(150.0)
+        );
+
+        for hour in 2..STATS_HISTORY as u32 + 10 {
+            stats.start_hour(hour);
+        }
+        assert_eq!(stats.hours().count(), STATS_HISTORY + 1);
+        assert!(stats.hour(0).is_none());
+        assert_eq!(stats.current().hour, STATS_HISTORY as u32 + 9);
+    }
+
+    #[test]
+    fn vehicles_are_sampled() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let pat = LanePatternBuilder::new()
+            .one_way(true)
+            .parking(false)
+            .build();
+        let a = map.add_intersection(vec3(0.0, 500.0, 0.0));
+        let b = map.add_intersection(vec3(300.0, 500.0, 0.0));
+        let c = map.add_intersection(vec3(600.0, 500.0, 0.0));
+        map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+        map.connect(b, c, &pat, RoadSegmentKind::Straight).unwrap();
+
+        let mut world = World::new();
+        let mut spawn = |x: f32, speed: f32, state: VehicleState| {
+            let pos = vec3(x, 500.0, 0.0);
+            let it =
+                Itinerary::route(pos, vec3(580.0, 500.0, 0.0), &map, PathKind::Vehicle).unwrap();
+            let mut v = Vehicle::stored(VehicleKind::Car, Color::WHITE);
+            v.state = state;
+            world.spawn((Transform::new(pos), Kinematics { speed }, v, it))
+        };
+        // One moving at the start of the lane, one waiting at its end and one parked
+        let moving = spawn(30.0, 10.0, VehicleState::Driving);
+        spawn(290.0, 0.0, VehicleState::Driving);
+        spawn(150.0, 0.0, VehicleState::Stored);
+
+        let lane = match world
+            .get::<Itinerary>(moving)
+            .unwrap()
+            .get_travers()
+            .unwrap()
+            .kind
+        {
+            TraverseKind::Lane(id) => id,
+            TraverseKind::Turn(_) => panic!("should start on a lane"),
+        };
+        let lane_length = map.lanes().get(lane).unwrap().points.length();
+
+        let mut resources = Resources::default();
+        resources.insert(map);
+        resources.insert(GameTime::new(0.1, 100.0));
+        resources.insert(TrafficStats::default());
+
+        for _ in 0..2 * STATS_SAMPLE_PERIOD {
+            traffic_stats_update(&mut world, &mut resources);
+        }
+
+        let stats = resources.get::<TrafficStats>().unwrap();
+        let hour = stats.current();
+        let s = hour.lane(lane).unwrap();
+        // The vehicles entered the lane once, before the first sample
+        assert_eq!(s.count, 2);
+        assert_eq!(s.avg_speed(), Some(5.0));
+        let occupancy = 2.0 * VehicleKind::Car.width() / lane_length;
+        assert!((hour.lane_occupancy(lane) - occupancy).abs() < 1e-4);
+
+        // Only the waiting vehicle is delayed, for both samples
+        let inter = hour.intersection(b).unwrap();
+        assert_eq!(inter.count, 0);
+        assert!((inter.delay - 2.0 * 0.1 * STATS_SAMPLE_PERIOD as f32).abs() < 1e-4);
+        assert!(hour.intersection(a).is_none());
+        assert!(hour.intersection(c).is_none());
+    }
+}
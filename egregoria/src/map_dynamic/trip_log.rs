@@ -0,0 +1,126 @@
+use crate::map::BuildingID;
+use crate::map_dynamic::TransportMode;
+use crate::utils::time::GameInstant;
+use geom::Vec3;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Number of trips kept, the oldest ones are dropped first
+const TRIP_LOG_SIZE: usize = 10000;
+
+/// A finished trip of a human
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TripRecord {
+    /// None if the trip didn't start in a building
+    pub origin: Option<BuildingID>,
+    pub origin_pos: Vec3,
+    /// None if the trip didn't end in a building
+    pub destination: Option<BuildingID>,
+    pub destination_pos: Vec3,
+    pub mode: TransportMode,
+    pub departure: GameInstant,
+    /// In seconds
+    pub duration: f32,
+    /// Length of the route followed, in meters
+    pub distance: f32,
+    /// As the crow flies from the origin to the destination, in meters
+    pub crow_distance: f32,
+}
+
+/// Latest finished trips, to see where people are going
+#[derive(Default, Serialize, Deserialize)]
+pub struct TripLog {
+    trips: VecDeque<TripRecord>,
+}
+
+impl TripLog {
+    pub fn push(&mut self, trip: TripRecord) {
+        if self.trips.len() == TRIP_LOG_SIZE {
+            self.trips.pop_front();
+        }
+        self.trips.push_back(trip);
+    }
+
+    /// Oldest first
+    pub fn trips(&self) -> impl Iterator<Item = &TripRecord> + '_ {
+        self.trips.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trips.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trips.is_empty()
+    }
+
+    /// Writes the trips as CSV with a header line, oldest first.
+    /// Buildings are given by their numeric id, left empty when there is none.
+    pub fn write_csv(&self, mut w: impl Write) -> std::io::Result<()> {
+        let id = |b: Option<BuildingID>| b.map(|b| b.as_ffi().to_string()).unwrap_or_default();
+
+        writeln!(
+            w,
+            "origin,origin_x,origin_y,destination,destination_x,destination_y,mode,departure,duration,distance,crow_distance"
+        )?;
+        for t in &self.trips {
+            writeln!(
+                w,
+                "{},{:.1},{:.1},{},{:.1},{:.1},{:?},{:.1},{:.1},{:.1},{:.1}",
+                id(t.origin),
+                t.origin_pos.x,
+                t.origin_pos.y,
+                id(t.destination),
+                t.destination_pos.x,
+                t.destination_pos.y,
+                t.mode,
+                t.departure.timestamp,
+                t.duration,
+                t.distance,
+                t.crow_distance
+            )?;
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TripLog, TripRecord, TRIP_LOG_SIZE};
+    use crate::map_dynamic::TransportMode;
+    use crate::utils::time::GameInstant;
+    use geom::vec3;
+
+    #[test]
+    fn trip_log_is_bounded_and_exported() {
+        let mut log = TripLog::default();
+        for i in 0..TRIP_LOG_SIZE + 5 {
+            log.push(TripRecord {
+                origin: None,
+                origin_pos: vec3(0.0, 0.0, 0.0),
+                destination: None,
+                destination_pos: vec3(30.0, 40.0, 0.0),
+                mode: TransportMode::Walk,
+                departure: GameInstant {
+                    timestamp: i as f64,
+                },
+                duration: 60.0,
+                distance: 70.0,
+                crow_distance: 50.0,
+            });
+        }
+        assert_eq!(log.len(), TRIP_LOG_SIZE);
+
+        let mut csv = vec![];
+        log.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("origin,"));
+        assert_eq!(
+            lines.next().unwrap(),
+            ",0.0,0.0,,30.0,40.0,Walk,5.0,60.0,70.0,50.0"
+        );
+        assert_eq!(lines.count(), TRIP_LOG_SIZE - 1);
+    }
+}
@@ -18,6 +18,10 @@ pub struct Pedestrian {
 }
 
 const PED_SIZE: f32 = 0.5;
+/// Radius of a pedestrian in the collision world
+pub const PED_RADIUS: f32 = PED_SIZE * 0.6;
+/// Mean of the walking speeds given to pedestrians, in m/s
+pub const AVERAGE_WALKING_SPEED: f32 = 1.2;
 
 pub fn spawn_pedestrian(goria: &mut Egregoria, house: BuildingID) -> Option<Entity> {
     let _color = random_pedestrian_shirt_color(&mut *goria.write::<RandProvider>());
@@ -41,7 +45,7 @@ pub fn put_pedestrian_in_coworld(coworld: &mut CollisionWorld, pos: Vec3) -> Col
     Collider(coworld.insert(
         pos.xy(),
         PhysicsObject {
-            radius: PED_SIZE * 0.6,
+            radius: PED_RADIUS,
             group: PhysicsGroup::Pedestrians,
             ..Default::default()
         },
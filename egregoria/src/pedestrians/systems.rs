@@ -1,8 +1,10 @@
+use crate::map::{Map, TraverseKind};
 use crate::map_dynamic::Itinerary;
-use crate::pedestrians::Pedestrian;
-use crate::physics::Kinematics;
+use crate::pedestrians::{Pedestrian, PED_RADIUS};
+use crate::physics::{Collider, CollisionWorld, Kinematics, PhysicsGroup, PhysicsObject};
 use crate::utils::time::GameTime;
-use geom::{angle_lerpxy, Transform, Vec3};
+use flat_spatial::grid::GridHandle;
+use geom::{angle_lerpxy, Transform, Vec2, Vec3};
 use hecs::World;
 use rayon::iter::ParallelBridge;
 use rayon::prelude::*;
@@ -11,33 +13,230 @@ use resources::Resources;
 #[profiling::function]
 pub fn pedestrian_decision_system(world: &mut World, resources: &mut Resources) {
     let ra = &*resources.get().unwrap();
+    let rb = &*resources.get().unwrap();
+    let rc = &*resources.get().unwrap();
     world
         .query::<(
             &mut Itinerary,
             &mut Transform,
             &mut Kinematics,
             &mut Pedestrian,
+            Option<&Collider>,
         )>()
         .iter_batched(32)
         .par_bridge()
-        .for_each(|batch| batch.for_each(|(_, (a, b, c, d))| pedestrian_decision(ra, a, b, c, d)))
+        .for_each(|batch| {
+            batch.for_each(|(_, (a, b, c, d, e))| pedestrian_decision(ra, rb, rc, a, b, c, d, e))
+        })
 }
 
 pub fn pedestrian_decision(
     time: &GameTime,
+    cow: &CollisionWorld,
+    map: &Map,
     it: &mut Itinerary,
     trans: &mut Transform,
     kin: &mut Kinematics,
     pedestrian: &mut Pedestrian,
+    collider: Option<&Collider>,
 ) {
-    let (desired_v, desired_dir) = calc_decision(pedestrian, trans, it);
+    let (mut desired_v, mut desired_dir) = calc_decision(pedestrian, trans, it);
+
+    // Pedestrians inside buildings aren't in the collision world
+    if let Some(collider) = collider {
+        let neighbors: Vec<_> = cow
+            .query_around(trans.position.xy(), AVOID_RADIUS)
+            .filter(|&(id, _)| id != collider.0)
+            .filter_map(|(id, pos)| Some((id, pos, cow.get(id)?.1)))
+            .filter(|(_, _, obj)| (obj.height - trans.position.z).abs() < 2.0)
+            .collect();
+
+        let (v, dir) = avoid(collider, trans, desired_v, desired_dir, &neighbors);
+
+        // The itinerary moves the pedestrian towards its next point, so only the sideways
+        // part of the avoidance needs to be applied here
+        let rail_dir = desired_dir.xy();
+        let side = dir.xy() - rail_dir * dir.xy().dot(rail_dir);
+        let push = collision_response(collider, trans, time, &neighbors);
+        trans.position += (side * kin.speed * time.delta + push).z0();
+
+        desired_v = v;
+        desired_dir = dir;
+
+        if let (Some(curb), 1) = (it.get_point(), it.remaining_points()) {
+            let stop_dist = kin.speed * kin.speed / (2.0 * PEDESTRIAN_DEC) + 0.5;
+            if trans.position.distance(curb) <= stop_dist
+                && !accepts_gap(map, cow, it, trans, pedestrian)
+            {
+                desired_v = 0.0;
+            }
+        }
+    }
 
     pedestrian.walk_anim += 7.0 * kin.speed * time.delta / pedestrian.walking_speed;
     physics(kin, trans, time, desired_v, desired_dir);
 }
 
 const PEDESTRIAN_ACC: f32 = 1.5;
+const PEDESTRIAN_DEC: f32 = 3.0;
+/// Neighbors further than this are ignored by the avoidance, in meters
+const AVOID_RADIUS: f32 = 3.0;
+/// Strength and range of the social force pushing pedestrians away from each other
+const SOCIAL_STRENGTH: f32 = 1.2;
+const SOCIAL_RANGE: f32 = 0.4;
+/// Vehicles are mostly avoided by waiting, pedestrians only sidestep them a bit
+const VEHICLE_FACTOR: f32 = 0.3;
+/// Seconds a pedestrian wants between finishing to cross and the next vehicle arriving
+const CRITICAL_GAP_MARGIN: f32 = 2.0;
+/// Vehicles further than this from an unsignalized crosswalk are ignored, in meters
+const GAP_LOOKAHEAD: f32 = 60.0;
+/// Fraction of the overlap between two pedestrians that is resolved per second
+const SEPARATION_RATE: f32 = 4.0;
+
+/// Social force model: each neighbor repels the pedestrian with a force decreasing
+/// exponentially with the distance between their edges, stronger if they are in front.
+/// The pedestrian slows down when someone is close ahead and walking slower.
+/// Only reads the collision world, so the result doesn't depend on update order.
+fn avoid(
+    collider: &Collider,
+    trans: &Transform,
+    desired_v: f32,
+    desired_dir: Vec3,
+    neighbors: &[(GridHandle, Vec2, &PhysicsObject)],
+) -> (f32, Vec3) {
+    let pos = trans.position.xy();
+    let dir = desired_dir.xy();
+    let mut force = Vec2::ZERO;
+    let mut speed = desired_v;
+
+    for &(id, npos, obj) in neighbors {
+        let diff = pos - npos;
+        let dist = diff.magnitude();
+        let away = diff
+            .try_normalize()
+            .unwrap_or_else(|| tie_break(collider, id, trans.dir.xy()));
+
+        let edge_dist = dist - obj.radius - PED_RADIUS;
+        let mut strength = SOCIAL_STRENGTH * (-edge_dist / SOCIAL_RANGE).exp();
+        if obj.group == PhysicsGroup::Vehicles {
+            strength *= VEHICLE_FACTOR;
+        }
+
+        // Anisotropy: people behind don't matter much
+        let ahead = -away.dot(dir);
+        strength *= 0.5 + 0.5 * ahead.max(0.0);
+        force += away * strength.min(SOCIAL_STRENGTH * 4.0);
+
+        if ahead > 0.8 && edge_dist < 0.6 {
+            let their_speed = obj.speed * obj.dir.dot(dir).max(0.0);
+            speed = speed.min(their_speed.max(edge_dist.max(0.0)));
+        }
+    }
+
+    if desired_v == 0.0 {
+        return (0.0, desired_dir);
+    }
+
+    let new_dir = unwrap_or!((dir + force).try_normalize(), return (speed, desired_dir));
+    // Never walk backwards, wait instead
+    if new_dir.dot(dir) < 0.0 {
+        return (0.0, desired_dir);
+    }
+    (speed, new_dir.z(desired_dir.z))
+}
+
+/// Pushes overlapping pedestrians apart so they don't stand inside each other
+/// once the avoidance is not enough, e.g. when many leave a building at once
+fn collision_response(
+    collider: &Collider,
+    trans: &Transform,
+    time: &GameTime,
+    neighbors: &[(GridHandle, Vec2, &PhysicsObject)],
+) -> Vec2 {
+    let pos = trans.position.xy();
+    let mut push = Vec2::ZERO;
+    for &(id, npos, obj) in neighbors {
+        if obj.group != PhysicsGroup::Pedestrians {
+            continue;
+        }
+        let diff = pos - npos;
+        let overlap = obj.radius + PED_RADIUS - diff.magnitude();
+        if overlap <= 0.0 {
+            continue;
+        }
+        let away = diff
+            .try_normalize()
+            .unwrap_or_else(|| tie_break(collider, id, trans.dir.xy()));
+        // Both pedestrians move, so each resolves half of the overlap
+        push += away * overlap * 0.5;
+    }
+    push * (SEPARATION_RATE * time.delta).min(1.0)
+}
 
+/// Before starting an unsignalized crosswalk, checks that the vehicles approaching it
+/// leave enough time to cross. Returns true if there is no such crosswalk ahead.
+fn accepts_gap(
+    map: &Map,
+    cow: &CollisionWorld,
+    it: &Itinerary,
+    trans: &Transform,
+    pedestrian: &Pedestrian,
+) -> bool {
+    let id = match it.peek_travers().map(|t| t.kind) {
+        Some(TraverseKind::Turn(id)) => id,
+        _ => return true,
+    };
+    let turn = unwrap_or!(
+        map.intersections()
+            .get(id.parent)
+            .and_then(|inter| inter.find_turn(id)),
+        return true
+    );
+    if !turn.kind.is_crosswalk() || map.crosswalk_control(id).is_light() {
+        return true;
+    }
+
+    let (a, b) = (turn.points.first().xy(), turn.points.last().xy());
+    let across = unwrap_or!((b - a).try_normalize(), return true);
+    let length = a.distance(b);
+    let center = (a + b) * 0.5;
+    let crossing_time = length / pedestrian.walking_speed + CRITICAL_GAP_MARGIN;
+
+    cow.query_around(center, GAP_LOOKAHEAD)
+        .filter_map(|(id, pos)| Some((pos, cow.get(id)?.1)))
+        .filter(|(_, obj)| {
+            obj.group == PhysicsGroup::Vehicles && (obj.height - trans.position.z).abs() < 2.0
+        })
+        .all(|(pos, obj)| {
+            let to_cross = center - pos;
+            let along = to_cross.dot(obj.dir);
+            // Already past the crosswalk or driving along it
+            if along < -obj.radius || obj.dir.dot(across).abs() > 0.7 {
+                return true;
+            }
+            // Its path doesn't go through the crosswalk
+            if (to_cross - obj.dir * along).magnitude() > length * 0.5 + obj.radius {
+                return true;
+            }
+            if along < obj.radius + 1.0 {
+                return false;
+            }
+            obj.speed < 0.5 || along / obj.speed > crossing_time
+        })
+}
+
+/// Pedestrians at the exact same position are separated sideways,
+/// the side is chosen by comparing the handles so it stays deterministic
+fn tie_break(collider: &Collider, other: GridHandle, dir: Vec2) -> Vec2 {
+    if collider.0 < other {
+        dir.perpendicular()
+    } else {
+        -dir.perpendicular()
+    }
+}
+
+/// Moves the speed towards the desired one, accelerating at `PEDESTRIAN_ACC` and slowing
+/// down at `PEDESTRIAN_DEC`, e.g. when the avoidance makes them wait behind someone slower
 pub fn physics(
     kin: &mut Kinematics,
     trans: &mut Transform,
@@ -46,10 +245,7 @@ pub fn physics(
     desired_dir: Vec3,
 ) {
     let diff = desired_velocity - kin.speed;
-    let mag = diff.min(time.delta * PEDESTRIAN_ACC);
-    if mag > 0.0 {
-        kin.speed += mag;
-    }
+    kin.speed += diff.clamp(-time.delta * PEDESTRIAN_DEC, time.delta * PEDESTRIAN_ACC);
     const ANG_VEL: f32 = 1.0;
     trans.dir = angle_lerpxy(trans.dir, desired_dir, ANG_VEL * time.delta);
 }
@@ -75,3 +271,70 @@ pub fn calc_decision(
     let desired_dir = dir_to_pos.normalize();
     (pedestrian.walking_speed, desired_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{avoid, collision_response, physics, tie_break};
+    use crate::pedestrians::PED_RADIUS;
+    use crate::physics::{Collider, CollisionWorld, Kinematics, PhysicsGroup, PhysicsObject};
+    use crate::utils::time::GameTime;
+    use geom::{vec3, Transform, Vec2, Vec3};
+
+    fn ped(speed: f32) -> PhysicsObject {
+        PhysicsObject {
+            speed,
+            radius: PED_RADIUS,
+            group: PhysicsGroup::Pedestrians,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pedestrians_at_the_same_point_separate() {
+        let mut cow = CollisionWorld::new(100);
+        let obj = ped(0.0);
+        let a = Collider(cow.insert(Vec2::ZERO, obj));
+        let b = Collider(cow.insert(Vec2::ZERO, obj));
+        let time = GameTime::new(1.0 / 50.0, 0.0);
+
+        let mut pos_a = Vec2::ZERO;
+        let mut pos_b = Vec2::ZERO;
+        for i in 0..200 {
+            let ta = Transform::new_dir(pos_a.z0(), Vec3::X);
+            let tb = Transform::new_dir(pos_b.z0(), Vec3::X);
+            let push_a = collision_response(&a, &ta, &time, &[(b.0, pos_b, &obj)]);
+            let push_b = collision_response(&b, &tb, &time, &[(a.0, pos_a, &obj)]);
+            if i == 0 {
+                let side = tie_break(&a, b.0, Vec2::X);
+                assert!(push_a.dot(side) > 0.0);
+                assert!(push_b.dot(side) < 0.0);
+            }
+            pos_a += push_a;
+            pos_b += push_b;
+        }
+        assert!(pos_a.distance(pos_b) > 2.0 * PED_RADIUS * 0.95);
+    }
+
+    #[test]
+    fn pedestrian_slows_behind_slower_one() {
+        let mut cow = CollisionWorld::new(100);
+        let me = Collider(cow.insert(Vec2::ZERO, ped(1.2)));
+        let front_pos = Vec2::X * (2.0 * PED_RADIUS + 0.3);
+        let front = ped(0.5);
+        let front_h = cow.insert(front_pos, front);
+        let time = GameTime::new(1.0 / 50.0, 0.0);
+
+        let mut trans = Transform::new_dir(vec3(0.0, 0.0, 0.0), Vec3::X);
+        let mut kin = Kinematics { speed: 1.2 };
+
+        let (v, dir) = avoid(&me, &trans, 1.2, Vec3::X, &[(front_h, front_pos, &front)]);
+        assert!((v - 0.5).abs() < 1e-4);
+
+        physics(&mut kin, &mut trans, &time, v, dir);
+        assert!(kin.speed < 1.2);
+        for _ in 0..100 {
+            physics(&mut kin, &mut trans, &time, v, dir);
+        }
+        assert!((kin.speed - 0.5).abs() < 1e-4);
+    }
+}
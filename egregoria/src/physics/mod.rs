@@ -1,3 +1,4 @@
+use crate::vehicles::VehicleID;
 use flat_spatial::grid::GridHandle;
 use imgui::Ui;
 use imgui_inspect::InspectDragf;
@@ -29,7 +30,8 @@ pub struct PhysicsObject {
     pub radius: f32,
     pub height: f32,
     pub group: PhysicsGroup,
-    pub flag: u64,
+    /// The vehicle it is the collider of, if any
+    pub vehicle: Option<VehicleID>,
 }
 
 impl Default for PhysicsObject {
@@ -40,7 +42,7 @@ impl Default for PhysicsObject {
             radius: 1.0,
             height: 0.0,
             group: PhysicsGroup::Unknown,
-            flag: 0,
+            vehicle: None,
         }
     }
 }
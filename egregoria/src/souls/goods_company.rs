@@ -14,6 +14,9 @@ use resources::Resources;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+/// Distance from the building in which tree harvesting companies cut trees
+const HARVEST_RADIUS: f32 = 500.0;
+
 #[derive(Clone, Serialize, Deserialize, Inspect)]
 pub struct Recipe {
     pub consumption: Vec<(CommodityKind, i32)>,
@@ -34,6 +37,8 @@ pub struct GoodsCompanyDescription {
     pub bgen: BuildingGen,
     pub kind: CompanyKind,
     pub recipe: Recipe,
+    /// Whether the logs it produces are cut from the trees around it
+    pub harvests_trees: bool,
     pub n_workers: i32,
     pub size: f32,
     pub asset_location: &'static str,
@@ -60,6 +65,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 165.0,
                     asset_location: "coal_power_plant.glb",
@@ -77,6 +83,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 1000,
                         storage_multiplier: 0,
                     },
+                    harvests_trees: false,
                     n_workers: 100,
                     size: 100.0,
                     asset_location: "assets/warehouse.png",
@@ -102,6 +109,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/supermarket.png",
@@ -119,6 +127,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 10.0,
                     asset_location: "assets/clothes_store.png",
@@ -136,6 +145,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/cloth_factory.png",
@@ -153,6 +163,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 5,
                     size: 80.0,
                     asset_location: "assets/polyester_refinery.png",
@@ -170,6 +181,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 5,
                     size: 20.0,
                     asset_location: "assets/oil_pump.png",
@@ -187,6 +199,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/textile_processing_facility.png",
@@ -204,6 +217,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/wool_farm.png",
@@ -221,6 +235,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 10.0,
                     asset_location: "assets/florist.png",
@@ -238,6 +253,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 5,
                     size: 80.0,
                     asset_location: "assets/horticulturalist.png",
@@ -255,6 +271,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/hightech_store.png",
@@ -272,6 +289,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/hightech_facility.png",
@@ -289,6 +307,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/rare_metal_mine.png",
@@ -306,6 +325,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/furniture_store.png",
@@ -323,6 +343,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/foundry.png",
@@ -340,6 +361,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/iron_mine.png",
@@ -357,6 +379,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/woodmill.png",
@@ -372,6 +395,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 200.0,
                     asset_location: "assets/lumber_yard.png",
@@ -389,6 +413,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "assets/meat_facility.png",
@@ -406,6 +431,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 5,
                     size: 50.0,
                     asset_location: "assets/slaughterhouse.png",
@@ -421,6 +447,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 5,
                     size: 80.0,
                     asset_location: "assets/animal_farm.png",
@@ -436,6 +463,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 70.0,
                     asset_location: "assets/vegetable_farm.png",
@@ -453,6 +481,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 100,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 3,
                     size: 10.0,
                     asset_location: "bakery.glb",
@@ -470,6 +499,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 200,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 80.0,
                     asset_location: "flour_factory.glb",
@@ -485,6 +515,7 @@ impl Default for GoodsCompanyRegistry {
                         complexity: 200,
                         storage_multiplier: 5,
                     },
+                    harvests_trees: false,
                     n_workers: 10,
                     size: 120.0,
                     asset_location: "assets/cereal_farm.png",
@@ -547,6 +578,7 @@ debug_inspect_impl!(CompanyKind);
 pub struct GoodsCompany {
     pub kind: CompanyKind,
     pub recipe: Recipe,
+    pub harvests_trees: bool,
     pub building: BuildingID,
     pub max_workers: i32,
     /// In [0; 1] range, to show how much has been made until new product
@@ -555,6 +587,15 @@ pub struct GoodsCompany {
     pub trucks: Vec<VehicleID>,
 }
 
+impl GoodsCompany {
+    /// Whether it has everything it needs to work on its recipe at `pos`
+    pub fn can_produce(&self, soul: SoulID, market: &Market, map: &Map, pos: Vec2) -> bool {
+        self.recipe.should_produce(soul, market)
+            && (!self.harvests_trees
+                || map.terrain.trees_near(pos, HARVEST_RADIUS).next().is_some())
+    }
+}
+
 pub fn company_soul(goria: &mut Egregoria, company: GoodsCompany) -> Option<SoulID> {
     let map = goria.map();
     let b = &map.buildings().get(company.building)?;
@@ -626,7 +667,13 @@ pub fn company(
     let n_workers = workers.0.len();
     let soul = SoulID(me);
 
-    if company.recipe.should_produce(soul, market) {
+    let bpos = unwrap_or!(map.buildings().get(company.building), {
+        cbuf.kill(me);
+        return;
+    })
+    .door_pos;
+
+    if company.can_produce(soul, market, map, bpos.xy()) {
         company.progress += n_workers as f32
             / (company.recipe.complexity as f32 * company.max_workers as f32)
             * time.delta;
@@ -635,11 +682,15 @@ pub fn company(
     if company.progress >= 1.0 {
         company.progress = 0.0;
         let recipe = company.recipe.clone();
-        let bpos = unwrap_or!(map.buildings().get(company.building), {
-            cbuf.kill(me);
-            return;
-        })
-        .door_pos;
+
+        if company.harvests_trees {
+            cbuf.exec_ent(soul.0, move |goria| {
+                goria
+                    .map_mut()
+                    .terrain
+                    .cut_tree_near(bpos.xy(), HARVEST_RADIUS);
+            });
+        }
 
         cbuf.exec_on(soul.0, move |market| {
             recipe.act(soul, bpos.xy(), market);
@@ -698,3 +749,52 @@ pub fn company(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CompanyKind, GoodsCompany, Recipe};
+    use crate::economy::{CommodityKind, Market};
+    use crate::map::{BuildingID, Map, Terrain};
+    use crate::SoulID;
+    use geom::{vec2, AABB};
+    use slotmap::Key;
+
+    fn lumber(harvests_trees: bool) -> GoodsCompany {
+        GoodsCompany {
+            kind: CompanyKind::Factory { n_trucks: 1 },
+            recipe: Recipe {
+                consumption: vec![],
+                production: vec![(CommodityKind::TreeLog, 1)],
+                complexity: 100,
+                storage_multiplier: 5,
+            },
+            harvests_trees,
+            building: BuildingID::null(),
+            max_workers: 10,
+            progress: 0.0,
+            driver: None,
+            trucks: vec![],
+        }
+    }
+
+    #[test]
+    fn harvesting_stops_without_trees() {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let pos = (1..20)
+            .map(|i| vec2(100.0 * i as f32, 100.0 * i as f32))
+            .find(|&p| !map.terrain.is_water(p))
+            .unwrap();
+        map.terrain
+            .remove_near_filter(AABB::new(vec2(0.0, 0.0), vec2(2048.0, 2048.0)), |_| true);
+
+        let soul = SoulID(hecs::World::new().spawn(()));
+        let market = Market::default();
+
+        assert!(lumber(false).can_produce(soul, &market, &map, pos));
+        assert!(!lumber(true).can_produce(soul, &market, &map, pos));
+
+        assert_eq!(map.terrain.add_trees(Some(pos + vec2(50.0, 0.0))), 1);
+        assert!(lumber(true).can_produce(soul, &market, &map, pos));
+    }
+}
@@ -1,11 +1,12 @@
 use crate::economy::CommodityKind::JobOpening;
 use crate::economy::{Bought, Market};
 use crate::map::BuildingID;
-use crate::map_dynamic::{BuildingInfos, Destination, Router};
+use crate::map_dynamic::{BuildingInfos, Destination, ModePreferences, Router};
 use crate::pedestrians::{spawn_pedestrian, Location};
 use crate::souls::desire::{BuyFood, Home, Work};
+use crate::utils::rand_provider::RandProvider;
 use crate::utils::time::GameTime;
-use crate::vehicles::{spawn_parked_vehicle, VehicleID, VehicleKind};
+use crate::vehicles::{spawn_parked_vehicle, spawn_stored_vehicle, VehicleID, VehicleKind};
 use crate::{Egregoria, ParCommandBuffer, SoulID};
 use geom::Transform;
 use hecs::{Entity, World};
@@ -14,6 +15,9 @@ use rayon::prelude::{ParallelBridge, ParallelIterator};
 use resources::Resources;
 use serde::{Deserialize, Serialize};
 
+/// Share of the humans that own a bike
+const BIKE_OWNERSHIP: f32 = 0.4;
+
 #[derive(Inspect, Serialize, Deserialize, Default)]
 pub struct HumanDecision {
     kind: HumanDecisionKind,
@@ -166,6 +170,14 @@ pub fn spawn_human(goria: &mut Egregoria, house: BuildingID) -> Option<SoulID> {
 
     let human = SoulID(spawn_pedestrian(goria, house)?);
     let car = spawn_parked_vehicle(goria, VehicleKind::Car, housepos);
+    let bike = if goria.write::<RandProvider>().next_f32() < BIKE_OWNERSHIP {
+        Some(spawn_stored_vehicle(goria, VehicleKind::Bicycle, housepos))
+    } else {
+        None
+    };
+
+    let mut router = Router::new(car, bike);
+    router.preferences = ModePreferences::random(&mut *goria.write::<RandProvider>());
 
     let mut m = goria.write::<Market>();
     m.buy(human, housepos.xy(), JobOpening, 1);
@@ -184,7 +196,7 @@ pub fn spawn_human(goria: &mut Egregoria, house: BuildingID) -> Option<SoulID> {
                 Home::new(house),
                 BuyFood::new(time),
                 Bought::default(),
-                Router::new(car),
+                router,
             ),
         )
         .unwrap();
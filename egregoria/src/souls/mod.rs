@@ -4,8 +4,8 @@ use crate::souls::goods_company::{company_soul, CompanyKind, GoodsCompany, Goods
 use crate::souls::human::spawn_human;
 use crate::vehicles::{spawn_parked_vehicle, VehicleKind};
 use crate::Egregoria;
-use common::FastMap;
 use geom::Vec3;
+use std::collections::BTreeMap;
 
 #[macro_use]
 pub mod desire;
@@ -17,7 +17,7 @@ pub mod human;
 pub(crate) fn add_souls_to_empty_buildings(goria: &mut Egregoria) {
     let map = goria.map();
     let infos = goria.read::<BuildingInfos>();
-    let mut empty_buildings: FastMap<BuildingKind, Vec<(BuildingID, Vec3)>> = FastMap::default();
+    let mut empty_buildings: BTreeMap<BuildingKind, Vec<(BuildingID, Vec3)>> = BTreeMap::new();
 
     for (id, building) in map.buildings() {
         if unwrap_cont!(infos.get(id)).owner.is_some() {
@@ -70,6 +70,7 @@ pub(crate) fn add_souls_to_empty_buildings(goria: &mut Egregoria) {
             kind: des.kind,
             building: build_id,
             recipe: des.recipe.clone(),
+            harvests_trees: des.harvests_trees,
             max_workers: des.n_workers,
             progress: 0.0,
             driver: None,
@@ -0,0 +1,61 @@
+use super::TestCtx;
+use crate::map::{LaneKind, LanePatternBuilder, RoadSegmentKind};
+use crate::map_dynamic::Itinerary;
+use crate::utils::time::GameTime;
+use crate::vehicles::{make_vehicle_entity, Vehicle, VehicleKind, VehicleState};
+use geom::{vec3, Color, Transform};
+
+/// Rides a vehicle of the given kind through the middle road of a straight line of
+/// three roads and returns the travel time measured on it
+fn measured_after_ride(kind: VehicleKind) -> Option<f32> {
+    let mut ctx = TestCtx::init();
+
+    let mut map = ctx.g.map_mut();
+    let pat = LanePatternBuilder::new()
+        .one_way(true)
+        .parking(false)
+        .build();
+    let a = map.add_intersection(vec3(1000.0, 1000.0, 0.0));
+    let b = map.add_intersection(vec3(1200.0, 1000.0, 0.0));
+    let c = map.add_intersection(vec3(1400.0, 1000.0, 0.0));
+    let d = map.add_intersection(vec3(1600.0, 1000.0, 0.0));
+    let ab = map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+    let bc = map.connect(b, c, &pat, RoadSegmentKind::Straight).unwrap();
+    let cd = map.connect(c, d, &pat, RoadSegmentKind::Straight).unwrap();
+
+    let driving = |road, from| {
+        map.roads()
+            .get(road)
+            .unwrap()
+            .outgoing_lanes_from(from)
+            .iter()
+            .find(|(_, kind)| *kind == LaneKind::Driving)
+            .unwrap()
+            .0
+    };
+    let (first, middle, last) = (driving(ab, a), driving(bc, b), driving(cd, c));
+
+    let (pos, dir) = map.lanes().get(first).unwrap().points.point_dir_along(30.0);
+    let end = map.lanes().get(last).unwrap().points.point_along(100.0);
+    let it = Itinerary::route(pos, end, &map, kind.path_kind()).unwrap();
+    drop(map);
+
+    let mut vehicle = Vehicle::stored(kind, Color::WHITE);
+    vehicle.state = VehicleState::Driving;
+    let e = make_vehicle_entity(&mut ctx.g, Transform::new_dir(pos, dir), vehicle, it, true);
+
+    for _ in 0..5000 {
+        ctx.tick();
+        let now = ctx.g.read::<GameTime>().timestamp;
+        if ctx.g.comp::<Itinerary>(e).unwrap().has_ended(now) {
+            return ctx.g.map().travel_times().measured(middle);
+        }
+    }
+    panic!("{:?} has not arrived after 5000 ticks", kind);
+}
+
+#[test]
+fn bike_ride_leaves_driving_lane_cost_unchanged() {
+    assert!(measured_after_ride(VehicleKind::Car).is_some());
+    assert_eq!(measured_after_ride(VehicleKind::Bicycle), None);
+}
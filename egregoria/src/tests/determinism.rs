@@ -0,0 +1,83 @@
+use super::TestCtx;
+use geom::{vec2, vec3};
+use std::process::Command;
+
+/// Set in the child processes to run the scenario
+const CHILD_ENV: &str = "EGREGORIA_DETERMINISM_CHILD";
+/// Prefix of the lines printed by the children, to tell them apart from the logs
+const HASH_PREFIX: &str = "determinism-hash";
+const HASH_PERIOD: u32 = 50;
+const N_TICKS: u32 = 1500;
+
+/// Runs the scenario when started by `same_hashes_in_separate_processes`, printing the
+/// hashes of the world every `HASH_PERIOD` ticks
+#[test]
+fn determinism_child() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+
+    let mut ctx = TestCtx::init();
+    for i in 0..4 {
+        let y = i as f32 * 120.0;
+        ctx.build_roads(&[vec3(0.0, y, 0.0), vec3(360.0, y, 0.0)]);
+        let x = i as f32 * 120.0;
+        ctx.build_roads(&[vec3(x, 0.0, 0.0), vec3(x, 360.0, 0.0)]);
+    }
+    for i in 0..4 {
+        for j in 0..3 {
+            ctx.build_house_near(vec2(i as f32 * 120.0 + 60.0, j as f32 * 120.0 + 15.0));
+        }
+    }
+
+    for _ in 0..N_TICKS {
+        ctx.tick();
+        if ctx.g.get_tick() % HASH_PERIOD == 0 {
+            for (name, hash) in ctx.g.hashes() {
+                println!("{} {} {} {}", HASH_PREFIX, ctx.g.get_tick(), name, hash);
+            }
+        }
+    }
+}
+
+fn run_child() -> Vec<String> {
+    let out = Command::new(std::env::current_exe().unwrap())
+        .args(&[
+            "tests::determinism::determinism_child",
+            "--exact",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "determinism child failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter(|l| l.starts_with(HASH_PREFIX))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Lockstep multiplayer needs the same inputs to give the same world on every machine,
+/// so the scenario is run in two processes to also catch address or hasher dependent orders
+#[test]
+fn same_hashes_in_separate_processes() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        return;
+    }
+
+    let a = run_child();
+    let b = run_child();
+
+    assert!(!a.is_empty(), "the child didn't print any hash");
+    for (la, lb) in a.iter().zip(&b) {
+        assert_eq!(la, lb, "the simulations diverged");
+    }
+    assert_eq!(a.len(), b.len());
+}
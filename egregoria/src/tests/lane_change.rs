@@ -0,0 +1,88 @@
+use super::TestCtx;
+use crate::map::{
+    LaneID, LaneKind, LanePatternBuilder, LaneTurns, PathKind, RoadSegmentKind, TraverseKind,
+};
+use crate::map_dynamic::Itinerary;
+use crate::vehicles::{make_vehicle_entity, Vehicle, VehicleKind, VehicleState};
+use geom::{vec3, Color, Transform};
+
+fn driving_lanes(lanes: &[(LaneID, LaneKind)]) -> Vec<LaneID> {
+    lanes
+        .iter()
+        .filter(|(_, kind)| *kind == LaneKind::Driving)
+        .map(|&(id, _)| id)
+        .collect()
+}
+
+#[test]
+fn vehicle_changes_lane_to_take_its_planned_turn() {
+    let mut ctx = TestCtx::init();
+
+    let mut map = ctx.g.map_mut();
+    let pat = LanePatternBuilder::new().n_lanes(2).parking(false).build();
+    let a = map.add_intersection(vec3(1000.0, 1000.0, 0.0));
+    let b = map.add_intersection(vec3(1300.0, 1000.0, 0.0));
+    let c = map.add_intersection(vec3(1500.0, 1000.0, 0.0));
+    let d = map.add_intersection(vec3(1300.0, 800.0, 0.0));
+    let ab = map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+    map.connect(b, c, &pat, RoadSegmentKind::Straight).unwrap();
+    let bd = map.connect(b, d, &pat, RoadSegmentKind::Straight).unwrap();
+
+    // The vehicle starts on a lane that can only go straight towards the dead end at c,
+    // the turn to d is only allowed from the other lane
+    let incoming = driving_lanes(map.roads().get(ab).unwrap().incoming_lanes_to(b));
+    let wrong = *incoming.get(0).unwrap();
+    let planned = *incoming.get(1).unwrap();
+    map.update_intersection(b, |i| {
+        i.lane_turns.insert(
+            wrong,
+            LaneTurns {
+                left: false,
+                straight: true,
+                right: false,
+                back: false,
+            },
+        );
+        i.lane_turns.insert(
+            planned,
+            LaneTurns {
+                straight: false,
+                ..LaneTurns::default()
+            },
+        );
+    });
+
+    let (pos, dir) = map.lanes().get(wrong).unwrap().points.point_dir_along(30.0);
+    let to_d = driving_lanes(map.roads().get(bd).unwrap().outgoing_lanes_from(b));
+    let end = map
+        .lanes()
+        .get(*to_d.get(0).unwrap())
+        .unwrap()
+        .points
+        .point_along(100.0);
+
+    let it = Itinerary::route(pos, end, &map, PathKind::Vehicle).unwrap();
+    assert_eq!(it.planned_lane_change(), Some((wrong, planned)));
+    drop(map);
+
+    let mut vehicle = Vehicle::stored(VehicleKind::Car, Color::WHITE);
+    vehicle.state = VehicleState::Driving;
+    let e = make_vehicle_entity(&mut ctx.g, Transform::new_dir(pos, dir), vehicle, it, true);
+
+    for _ in 0..3000 {
+        ctx.tick();
+        let turn = ctx
+            .g
+            .comp::<Itinerary>(e)
+            .and_then(|it| match it.get_travers()?.kind {
+                TraverseKind::Turn(turn) => Some(turn),
+                TraverseKind::Lane(_) => None,
+            });
+        if let Some(turn) = turn.filter(|t| t.parent == b) {
+            assert_eq!(turn.src, planned);
+            assert!(to_d.contains(&turn.dst));
+            return;
+        }
+    }
+    panic!("the vehicle never turned at the junction");
+}
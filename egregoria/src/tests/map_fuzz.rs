@@ -0,0 +1,258 @@
+//! Randomized sequences of map edits checked against the map invariants.
+//! Run more seeds with `MAP_FUZZ_SEEDS=1000 cargo test --release fuzz_map_operations`
+
+use crate::engine_interaction::WorldCommand;
+use crate::map::{
+    BuildingGen, BuildingKind, LanePatternBuilder, Map, ProjectFilter, ProjectKind, Terrain,
+};
+use crate::utils::rand_provider::RandProvider;
+use crate::Egregoria;
+use geom::{vec2, OBB};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Once;
+
+const N_OPS: usize = 60;
+/// Positions are snapped to this grid so that edits often touch the same intersections
+const GRID: f32 = 100.0;
+const GRID_SIZE: u32 = 20;
+
+static INIT: Once = Once::new();
+
+/// A map edit referring to objects by position or by index, so it stays meaningful
+/// when the sequence is shrunk and the ids change
+#[derive(Debug, Clone)]
+enum FuzzOp {
+    Connect {
+        from: [f32; 2],
+        to: [f32; 2],
+        elbow: Option<[f32; 2]>,
+        lanes: u32,
+        one_way: bool,
+        rail: bool,
+    },
+    RemoveRoad(usize),
+    RemoveIntersection(usize),
+    BuildHouse(usize),
+    RemoveBuilding(usize),
+    BuildSpecial {
+        pos: [f32; 2],
+        axis: [f32; 2],
+        size: f32,
+    },
+}
+
+impl FuzzOp {
+    fn random(rng: &mut RandProvider) -> Self {
+        let mut grid_pos = || {
+            [
+                (rng.next_u32() % GRID_SIZE) as f32 * GRID,
+                (rng.next_u32() % GRID_SIZE) as f32 * GRID,
+            ]
+        };
+        let (from, to, other) = (grid_pos(), grid_pos(), grid_pos());
+
+        match rng.next_u32() % 10 {
+            0..=4 => FuzzOp::Connect {
+                from,
+                to,
+                elbow: if rng.next_u32() % 3 == 0 {
+                    Some(other)
+                } else {
+                    None
+                },
+                lanes: rng.next_u32() % 3,
+                one_way: rng.next_u32() % 4 == 0,
+                rail: rng.next_u32() % 8 == 0,
+            },
+            5 => FuzzOp::RemoveRoad(rng.next_u32() as usize),
+            6 => FuzzOp::RemoveIntersection(rng.next_u32() as usize),
+            7 => FuzzOp::BuildHouse(rng.next_u32() as usize),
+            8 => FuzzOp::RemoveBuilding(rng.next_u32() as usize),
+            _ => {
+                let angle = rng.next_f32() * std::f32::consts::TAU;
+                FuzzOp::BuildSpecial {
+                    pos: from,
+                    axis: [angle.cos(), angle.sin()],
+                    size: 20.0 + rng.next_f32() * 80.0,
+                }
+            }
+        }
+    }
+
+    fn to_command(&self, map: &Map) -> Option<WorldCommand> {
+        let project = |p: [f32; 2]| {
+            let p = vec2(p[0], p[1]);
+            let z = map.terrain.height(p).unwrap_or(0.0);
+            map.project(p.z(z), 0.0, ProjectFilter::INTER | ProjectFilter::ROAD)
+        };
+        let nth = |n: usize, len: usize| {
+            if len == 0 {
+                return None;
+            }
+            Some(n % len)
+        };
+
+        Some(match *self {
+            FuzzOp::Connect {
+                from,
+                to,
+                elbow,
+                lanes,
+                one_way,
+                rail,
+            } => {
+                let pat = LanePatternBuilder::new()
+                    .n_lanes(lanes)
+                    .one_way(one_way)
+                    .rail(rail)
+                    .build();
+                let (from, to) = (project(from), project(to));
+                if matches!(from.kind, ProjectKind::Building(_) | ProjectKind::Lot(_))
+                    || matches!(to.kind, ProjectKind::Building(_) | ProjectKind::Lot(_))
+                {
+                    return None;
+                }
+                WorldCommand::MapMakeConnection(from, to, elbow.map(|e| vec2(e[0], e[1])), pat)
+            }
+            FuzzOp::RemoveRoad(n) => {
+                let i = nth(n, map.roads.len())?;
+                WorldCommand::MapRemoveRoad(map.roads.keys().nth(i)?)
+            }
+            FuzzOp::RemoveIntersection(n) => {
+                let i = nth(n, map.intersections.len())?;
+                WorldCommand::MapRemoveIntersection(map.intersections.keys().nth(i)?)
+            }
+            FuzzOp::BuildHouse(n) => {
+                let i = nth(n, map.lots.len())?;
+                WorldCommand::MapBuildHouse(map.lots.keys().nth(i)?)
+            }
+            FuzzOp::RemoveBuilding(n) => {
+                let i = nth(n, map.buildings.len())?;
+                WorldCommand::MapRemoveBuilding(map.buildings.keys().nth(i)?)
+            }
+            FuzzOp::BuildSpecial { pos, axis, size } => WorldCommand::MapBuildSpecialBuilding(
+                OBB::new(vec2(pos[0], pos[1]), vec2(axis[0], axis[1]), size, size),
+                BuildingKind::GoodsCompany(0),
+                BuildingGen::CenteredDoor {
+                    vertical_factor: 1.0,
+                },
+                vec![],
+            ),
+        })
+    }
+}
+
+fn random_ops(seed: u64, n: usize) -> Vec<FuzzOp> {
+    let mut rng = RandProvider::new(seed);
+    (0..n).map(|_| FuzzOp::random(&mut rng)).collect()
+}
+
+/// Applies the ops to a fresh world and checks the invariants after each of them.
+/// Returns the index of the failing op and what went wrong.
+fn run_ops(ops: &[FuzzOp]) -> Result<(), (usize, String)> {
+    INIT.call_once(crate::init::init);
+
+    let mut g = Egregoria::new(false);
+    g.map_mut().terrain = Terrain::new(2, 2);
+
+    for (i, op) in ops.iter().enumerate() {
+        let command = op.to_command(&g.map());
+        let command = match command {
+            Some(x) => x,
+            None => continue,
+        };
+
+        catch_unwind(AssertUnwindSafe(|| command.apply(&mut g))).map_err(|e| {
+            let msg = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default();
+            (i, format!("panicked applying {:?}: {}", command, msg))
+        })?;
+
+        let violations = g.map().invariant_violations();
+        if !violations.is_empty() {
+            return Err((i, format!("after {:?}: {:?}", command, violations)));
+        }
+    }
+    Ok(())
+}
+
+/// Removes ops one by one as long as the sequence still fails
+fn shrink(ops: &[FuzzOp], failed_at: usize) -> Vec<FuzzOp> {
+    let mut ops = ops[..=failed_at].to_vec();
+    loop {
+        let mut progress = false;
+        let mut i = 0;
+        while i < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.remove(i);
+            if let Err((failed_at, _)) = run_ops(&candidate) {
+                candidate.truncate(failed_at + 1);
+                ops = candidate;
+                progress = true;
+            } else {
+                i += 1;
+            }
+        }
+        if !progress {
+            return ops;
+        }
+    }
+}
+
+fn assert_ops_valid(ops: &[FuzzOp]) {
+    if let Err((_, msg)) = run_ops(ops) {
+        panic!("{}", msg);
+    }
+}
+
+#[test]
+fn fuzz_map_operations() {
+    let seeds: u64 = std::env::var("MAP_FUZZ_SEEDS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(10);
+
+    let mut failure = None;
+    for seed in 0..seeds {
+        let ops = random_ops(seed, N_OPS);
+        if let Err((failed_at, _)) = run_ops(&ops) {
+            let ops = shrink(&ops, failed_at);
+            failure = Some((seed, run_ops(&ops).err(), ops));
+            break;
+        }
+    }
+
+    if let Some((seed, err, ops)) = failure {
+        // Paste it in this file to keep it as a regression test
+        println!(
+            "#[test]\nfn fuzz_regression_seed_{}() {{\n    use FuzzOp::*;\n    assert_ops_valid(&{:#?});\n}}",
+            seed, ops
+        );
+        panic!("seed {} failed: {:?}", seed, err);
+    }
+}
+
+/// Splits a road by connecting to its middle, then removes the intersections one by one
+#[test]
+fn fuzz_regression_split_and_remove() {
+    use FuzzOp::*;
+    let connect = |from, to| Connect {
+        from,
+        to,
+        elbow: None,
+        lanes: 1,
+        one_way: false,
+        rail: false,
+    };
+    assert_ops_valid(&[
+        connect([0.0, 0.0], [400.0, 0.0]),
+        connect([200.0, 0.0], [200.0, 200.0]),
+        BuildHouse(0),
+        RemoveIntersection(2),
+        RemoveIntersection(0),
+        RemoveRoad(0),
+    ]);
+}
@@ -9,6 +9,10 @@ use crate::Egregoria;
 use common::logger::MyLog;
 use geom::{Vec2, Vec3};
 
+mod congestion;
+mod determinism;
+mod lane_change;
+mod map_fuzz;
 mod vehicles;
 
 struct TestCtx {
@@ -1,4 +1,5 @@
 use crate::engine_interaction::Selectable;
+use crate::map::{PathKind, BIKE_SPEED};
 use crate::map_dynamic::{Itinerary, ParkingManagement, SpotReservation};
 use crate::physics::{Collider, CollisionWorld, Kinematics, PhysicsGroup, PhysicsObject};
 use crate::utils::par_command_buffer::ComponentDrop;
@@ -26,9 +27,11 @@ debug_inspect_impl!(VehicleID);
 pub enum VehicleState {
     Parked(SpotReservation),
     Driving,
-    /// Panicked when it notices it's in a gridlock
+    /// Pushes through the vehicles in front after being taken out of a gridlock
     Panicking(GameInstant),
     RoadToPark(Spline3, f32, SpotReservation),
+    /// Left where it stopped without taking a parking spot, like a locked bicycle
+    Stored,
 }
 
 debug_inspect_impl!(VehicleState);
@@ -38,6 +41,7 @@ pub enum VehicleKind {
     Car,
     Truck,
     Bus,
+    Bicycle,
 }
 
 #[derive(Debug, Serialize, Deserialize, Inspect)]
@@ -46,13 +50,16 @@ pub struct Vehicle {
     pub ang_velocity: f32,
     #[inspect(proxy_type = "InspectDragf")]
     pub wait_time: f32,
+    /// Time left before the vehicle considers changing lanes again
+    #[inspect(proxy_type = "InspectDragf")]
+    pub lane_change_cooldown: f32,
 
     pub state: VehicleState,
     pub kind: VehicleKind,
     pub tint: Color,
 
-    /// Used to detect gridlock
-    pub flag: u64,
+    /// Vehicle it is stopped behind, an edge of the wait-for graph used to detect gridlocks
+    pub waiting_for: Option<VehicleID>,
 }
 
 impl ComponentDrop for Vehicle {
@@ -84,6 +91,7 @@ impl VehicleKind {
             VehicleKind::Car => 4.5,
             VehicleKind::Truck => 6.0,
             VehicleKind::Bus => 9.0,
+            VehicleKind::Bicycle => 1.8,
         }
     }
 
@@ -92,12 +100,14 @@ impl VehicleKind {
             VehicleKind::Car => 3.0,
             VehicleKind::Truck => 2.5,
             VehicleKind::Bus => 2.0,
+            VehicleKind::Bicycle => 1.0,
         }
     }
 
     pub fn deceleration(self) -> f32 {
         match self {
             VehicleKind::Car | VehicleKind::Bus | VehicleKind::Truck => 6.0,
+            VehicleKind::Bicycle => 3.0,
         }
     }
 
@@ -106,21 +116,43 @@ impl VehicleKind {
             VehicleKind::Car => 1.5,
             VehicleKind::Truck => 3.0,
             VehicleKind::Bus => 4.0,
+            VehicleKind::Bicycle => 1.0,
         }
     }
 
     pub fn speed_factor(self) -> f32 {
         match self {
-            VehicleKind::Car => 1.0,
+            VehicleKind::Car | VehicleKind::Bicycle => 1.0,
             VehicleKind::Truck | VehicleKind::Bus => 0.8,
         }
     }
 
+    /// Speed it can't go faster than whatever the speed limit, in m/s
+    pub fn max_speed(self) -> f32 {
+        match self {
+            VehicleKind::Bicycle => BIKE_SPEED,
+            VehicleKind::Car | VehicleKind::Truck | VehicleKind::Bus => f32::INFINITY,
+        }
+    }
+
+    pub fn path_kind(self) -> PathKind {
+        match self {
+            VehicleKind::Bicycle => PathKind::Bike,
+            VehicleKind::Car | VehicleKind::Truck | VehicleKind::Bus => PathKind::Vehicle,
+        }
+    }
+
+    /// Whether it needs a parking spot, otherwise it is stored where it stops
+    pub fn parks(self) -> bool {
+        !matches!(self, VehicleKind::Bicycle)
+    }
+
     pub fn ang_acc(self) -> f32 {
         match self {
             VehicleKind::Car => 1.0,
             VehicleKind::Truck => 0.9,
             VehicleKind::Bus => 0.8,
+            VehicleKind::Bicycle => 1.5,
         }
     }
 }
@@ -129,12 +161,16 @@ pub fn unpark(goria: &mut Egregoria, vehicle: VehicleID) {
     let mut v = unwrap_ret!(goria.comp_mut::<Vehicle>(vehicle.0));
     let w = v.kind.width();
 
-    if let VehicleState::Parked(spot) = std::mem::replace(&mut (*v).state, VehicleState::Driving) {
-        drop(v);
-        goria.write::<ParkingManagement>().free(spot);
-    } else {
-        drop(v);
-        log::warn!("Trying to unpark {:?} that wasn't parked", vehicle);
+    match std::mem::replace(&mut (*v).state, VehicleState::Driving) {
+        VehicleState::Parked(spot) => {
+            drop(v);
+            goria.write::<ParkingManagement>().free(spot);
+        }
+        VehicleState::Stored => drop(v),
+        _ => {
+            drop(v);
+            log::warn!("Trying to unpark {:?} that wasn't parked", vehicle);
+        }
     }
 
     let trans = *unwrap_ret!(goria.comp::<Transform>(vehicle.0));
@@ -174,6 +210,18 @@ pub fn spawn_parked_vehicle(
     )))
 }
 
+/// Spawns a vehicle that doesn't need a parking spot, stored at `pos`
+pub fn spawn_stored_vehicle(goria: &mut Egregoria, kind: VehicleKind, pos: Vec3) -> VehicleID {
+    let tint = get_random_car_color(&mut *goria.write::<RandProvider>());
+    VehicleID(make_vehicle_entity(
+        goria,
+        Transform::new(pos),
+        Vehicle::stored(kind, tint),
+        Itinerary::NONE,
+        false,
+    ))
+}
+
 pub fn make_vehicle_entity(
     goria: &mut Egregoria,
     trans: Transform,
@@ -230,10 +278,23 @@ impl Vehicle {
         Self {
             ang_velocity: 0.0,
             wait_time: 0.0,
+            lane_change_cooldown: 0.0,
             state: VehicleState::Parked(spot),
             kind,
             tint,
-            flag: 0,
+            waiting_for: None,
+        }
+    }
+
+    pub fn stored(kind: VehicleKind, tint: Color) -> Vehicle {
+        Self {
+            ang_velocity: 0.0,
+            wait_time: 0.0,
+            lane_change_cooldown: 0.0,
+            state: VehicleState::Stored,
+            kind,
+            tint,
+            waiting_for: None,
         }
     }
 }
@@ -0,0 +1,465 @@
+use crate::map::{IntersectionID, Map, TrafficBehavior, TraverseKind};
+use crate::map_dynamic::Itinerary;
+use crate::physics::Kinematics;
+use crate::utils::time::{GameInstant, GameTime};
+use crate::vehicles::{Vehicle, VehicleState};
+use geom::{Transform, Vec3};
+use hecs::{Entity, World};
+use resources::Resources;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Number of ticks between two searches for gridlocks
+const GRIDLOCK_CHECK_PERIOD: u32 = 10;
+/// A vehicle that is picked again this soon after being rerouted skips ahead, in seconds
+const ESCALATION_DELAY: f64 = 30.0;
+/// Number of gridlock events kept for inspection
+const GRIDLOCK_HISTORY: usize = 100;
+/// A vehicle stopped this close to the end of its lane is waiting to enter the intersection, in meters
+const ENTERING_DIST: f32 = 5.0;
+/// Room needed around the start of the next lane to skip ahead to it, in meters
+const SKIP_CLEARANCE: f32 = 8.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GridlockResolution {
+    /// The vehicle got a new route and pushed through the vehicle in front
+    Reroute,
+    /// The vehicle was teleported to the start of the next lane of its route, it keeps
+    /// its entity and its itinerary, it isn't despawned
+    SkipAhead,
+    /// The start of the next lane was occupied: the vehicle was taken off the road and put
+    /// back at the end of its route, still keeping its entity
+    Respawn,
+}
+
+/// Node of the wait-for graph
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WaitNode {
+    Vehicle(Entity),
+    Intersection(IntersectionID),
+}
+
+/// Where a stopped vehicle is relative to an intersection
+#[derive(Debug, Copy, Clone)]
+pub enum StopPlace {
+    /// At the end of a lane, with the right of way to enter the intersection
+    Entering(IntersectionID),
+    /// On one of the turns of the intersection
+    Inside(IntersectionID),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridlockEvent {
+    pub time: GameInstant,
+    /// Number of vehicles in the cycle
+    pub size: usize,
+    pub pos: Vec3,
+    pub victim: Entity,
+    pub resolution: GridlockResolution,
+}
+
+/// Finds gridlocks as cycles in the wait-for graph (see `wait_for_graph`) of the vehicles
+/// and intersections.
+/// In each cycle, the vehicle with the largest id yields so the resolution doesn't depend on
+/// the iteration order.
+#[derive(Default, Serialize, Deserialize)]
+pub struct GridlockDetector {
+    ticks: u32,
+    /// Vehicles rerouted recently and when
+    rerouted: BTreeMap<Entity, GameInstant>,
+    events: VecDeque<GridlockEvent>,
+    pub n_gridlocks: u64,
+    pub n_reroutes: u64,
+    pub n_skips: u64,
+    pub n_respawns: u64,
+}
+
+impl GridlockDetector {
+    /// Latest gridlocks, oldest first
+    pub fn events(&self) -> impl Iterator<Item = &GridlockEvent> + '_ {
+        self.events.iter()
+    }
+
+    fn record(&mut self, event: GridlockEvent) {
+        log::info!(
+            "gridlock of {} vehicles at {}, resolved by {:?} of {:?}",
+            event.size,
+            event.pos,
+            event.resolution,
+            event.victim
+        );
+        self.n_gridlocks += 1;
+        match event.resolution {
+            GridlockResolution::Reroute => self.n_reroutes += 1,
+            GridlockResolution::SkipAhead => self.n_skips += 1,
+            GridlockResolution::Respawn => self.n_respawns += 1,
+        }
+        if self.events.len() == GRIDLOCK_HISTORY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+/// Wait-for graph of the stopped vehicles, given what each one waits for behind it and
+/// where it is stopped:
+/// - a vehicle waits for the one stopped in front of it, or crossing its way at an intersection
+/// - a vehicle stopped at the end of its lane while it has the right of way waits for the intersection
+/// - an intersection waits for the vehicle stopped inside it with the smallest id
+///
+/// Vehicles held by a red light have no edge as the light will change.
+pub fn wait_for_graph(
+    stopped: &[(Entity, Option<Entity>, Option<StopPlace>)],
+) -> BTreeMap<WaitNode, WaitNode> {
+    let mut edges = BTreeMap::new();
+    let mut blockers: BTreeMap<IntersectionID, Entity> = BTreeMap::new();
+
+    for &(e, waiting_for, place) in stopped {
+        match (waiting_for, place) {
+            (Some(front), _) => {
+                edges.insert(WaitNode::Vehicle(e), WaitNode::Vehicle(front));
+            }
+            (None, Some(StopPlace::Entering(inter))) => {
+                edges.insert(WaitNode::Vehicle(e), WaitNode::Intersection(inter));
+            }
+            _ => {}
+        }
+        if let Some(StopPlace::Inside(inter)) = place {
+            let b = blockers.entry(inter).or_insert(e);
+            *b = (*b).min(e);
+        }
+    }
+
+    for (inter, e) in blockers {
+        edges.insert(WaitNode::Intersection(inter), WaitNode::Vehicle(e));
+    }
+    edges
+}
+
+/// Cycles of the graph where each node has at most one outgoing edge, each cycle is
+/// given starting from its smallest node
+pub fn find_cycles<N: Copy + Ord>(edges: &BTreeMap<N, N>) -> Vec<Vec<N>> {
+    let mut done = BTreeSet::new();
+    let mut cycles = vec![];
+
+    for &start in edges.keys() {
+        let mut path = vec![];
+        let mut cur = Some(start);
+        while let Some(e) = cur {
+            if !done.insert(e) {
+                break;
+            }
+            path.push(e);
+            cur = edges.get(&e).copied();
+        }
+        // The walk came back to a node of this path: the rest of the path is a new cycle
+        if let Some(i) = cur.and_then(|cur| path.iter().position(|&e| e == cur)) {
+            let mut cycle = path.split_off(i);
+            let min = cycle
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, e)| e)
+                .map_or(0, |(i, _)| i);
+            cycle.rotate_left(min);
+            cycles.push(cycle);
+        }
+    }
+    cycles
+}
+
+#[profiling::function]
+pub fn gridlock_update(world: &mut World, resources: &mut Resources) {
+    let mut detector = resources.get_mut::<GridlockDetector>().unwrap();
+    detector.ticks += 1;
+    if detector.ticks % GRIDLOCK_CHECK_PERIOD != 0 {
+        return;
+    }
+    let map = resources.get::<Map>().unwrap();
+    let time = resources.get::<GameTime>().unwrap();
+
+    detector
+        .rerouted
+        .retain(|_, since| since.elapsed(&time) < ESCALATION_DELAY);
+
+    let mut positions = vec![];
+    let mut stopped = vec![];
+    for (e, (v, it, trans, kin)) in
+        world.query_mut::<(&Vehicle, &Itinerary, &Transform, &Kinematics)>()
+    {
+        if !matches!(v.state, VehicleState::Driving | VehicleState::Panicking(_)) {
+            continue;
+        }
+        positions.push((e, trans.position));
+        if !matches!(v.state, VehicleState::Driving) {
+            continue;
+        }
+
+        let place = match (it.get_travers().map(|t| t.kind), it.peek_travers()) {
+            _ if kin.speed.abs() >= 0.2 => None,
+            (Some(TraverseKind::Turn(turn)), _) => Some(StopPlace::Inside(turn.parent)),
+            (Some(TraverseKind::Lane(l)), Some(next)) => match (map.lanes().get(l), next.kind) {
+                (Some(lane), TraverseKind::Turn(turn))
+                    if lane.control_point().is_close(trans.position, ENTERING_DIST)
+                        && !matches!(
+                            lane.control.get_behavior(time.seconds),
+                            TrafficBehavior::RED | TrafficBehavior::ORANGE
+                        ) =>
+                {
+                    Some(StopPlace::Entering(turn.parent))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let waiting_for = v.waiting_for.map(|x| x.0);
+        if waiting_for.is_some() || place.is_some() {
+            stopped.push((e, waiting_for, place));
+        }
+    }
+
+    for cycle in find_cycles(&wait_for_graph(&stopped)) {
+        let victim = unwrap_cont!(cycle
+            .iter()
+            .filter_map(|n| match *n {
+                WaitNode::Vehicle(e) => Some(e),
+                WaitNode::Intersection(_) => None,
+            })
+            .max());
+        let (vehicle, it, trans, kin) = unwrap_cont!(world
+            .query_one_mut::<(
+                &mut Vehicle,
+                &mut Itinerary,
+                &mut Transform,
+                &mut Kinematics
+            )>(victim)
+            .ok());
+        let pos = trans.position;
+
+        vehicle.waiting_for = None;
+        let resolution = if detector.rerouted.remove(&victim).is_some() {
+            kin.speed = 0.0;
+            let is_clear = |p: Vec3| {
+                positions
+                    .iter()
+                    .all(|&(e, other)| e == victim || !other.is_close(p, SKIP_CLEARANCE))
+            };
+            match it.next_lane_start(&map) {
+                Some(start) if is_clear(start) => {
+                    if let Some(p) = it.skip_to_next_lane(&map) {
+                        trans.position = p;
+                    }
+                    GridlockResolution::SkipAhead
+                }
+                _ => {
+                    if let Some(end) = it.end_pos() {
+                        *it = Itinerary::simple(vec![end]);
+                        trans.position = end;
+                    }
+                    GridlockResolution::Respawn
+                }
+            }
+        } else {
+            it.reroute(&map, pos);
+            vehicle.state = VehicleState::Panicking(time.instant());
+            detector.rerouted.insert(victim, time.instant());
+            GridlockResolution::Reroute
+        };
+
+        detector.record(GridlockEvent {
+            time: time.instant(),
+            size: cycle.len(),
+            pos,
+            victim,
+            resolution,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_cycles, gridlock_update, wait_for_graph, GridlockDetector, GridlockEvent,
+        GridlockResolution, StopPlace, WaitNode, GRIDLOCK_CHECK_PERIOD,
+    };
+    use crate::map::{IntersectionID, LanePatternBuilder, Map, PathKind, RoadSegmentKind, Terrain};
+    use crate::map_dynamic::Itinerary;
+    use crate::physics::Kinematics;
+    use crate::utils::time::GameTime;
+    use crate::vehicles::{Vehicle, VehicleID, VehicleKind, VehicleState};
+    use geom::{vec3, Color, Transform, Vec3};
+    use hecs::{Entity, World};
+    use resources::Resources;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn finds_each_cycle_once() {
+        let mut w = World::new();
+        let e: Vec<_> = (0..7).map(|_| w.spawn(())).collect();
+        let e = |i: usize| *e.get(i).unwrap();
+
+        let edges: BTreeMap<_, _> = vec![
+            // 0 -> 1 -> 2 -> 0 with 3 waiting on the cycle
+            (e(0), e(1)),
+            (e(1), e(2)),
+            (e(2), e(0)),
+            (e(3), e(1)),
+            // 4 -> 5, a simple queue
+            (e(4), e(5)),
+            // 6 -> 6
+            (e(6), e(6)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            find_cycles(&edges),
+            vec![vec![e(0), e(1), e(2)], vec![e(6)]]
+        );
+    }
+
+    #[test]
+    fn waits_through_the_intersections() {
+        let mut w = World::new();
+        let e: Vec<_> = (0..4).map(|_| w.spawn(())).collect();
+        let e = |i: usize| *e.get(i).unwrap();
+        let mut inters = slotmap::SlotMap::<IntersectionID, ()>::with_key();
+        let inter = inters.insert(());
+
+        // 0 can't enter the intersection blocked by 2 and 3, 2 waits behind 1 which waits behind 0
+        let edges = wait_for_graph(&[
+            (e(0), None, Some(StopPlace::Entering(inter))),
+            (e(1), Some(e(0)), None),
+            (e(3), None, Some(StopPlace::Inside(inter))),
+            (e(2), Some(e(1)), Some(StopPlace::Inside(inter))),
+        ]);
+
+        assert_eq!(
+            find_cycles(&edges),
+            vec![vec![
+                WaitNode::Vehicle(e(0)),
+                WaitNode::Intersection(inter),
+                WaitNode::Vehicle(e(2)),
+                WaitNode::Vehicle(e(1)),
+            ]]
+        );
+    }
+
+    /// A straight one way road a -> b -> c -> d with 3 vehicles near a, and the resources
+    /// needed by `gridlock_update`
+    fn jam() -> (World, Resources, Vec<Entity>) {
+        let mut map = Map::empty();
+        map.terrain = Terrain::new(2, 2);
+        let pat = LanePatternBuilder::new()
+            .one_way(true)
+            .parking(false)
+            .build();
+        let inters: Vec<_> = (0..4)
+            .map(|i| map.add_intersection(vec3(300.0 * i as f32, 500.0, 0.0)))
+            .collect();
+        for w in inters.windows(2) {
+            if let [a, b] = *w {
+                map.connect(a, b, &pat, RoadSegmentKind::Straight).unwrap();
+            }
+        }
+
+        let mut world = World::new();
+        let vehicles = (0..3)
+            .map(|i| {
+                let pos = vec3(30.0 + 10.0 * i as f32, 500.0, 0.0);
+                let end = vec3(800.0, 500.0, 0.0);
+                let it = Itinerary::route(pos, end, &map, PathKind::Vehicle).unwrap();
+                let mut v = Vehicle::stored(VehicleKind::Car, Color::WHITE);
+                v.state = VehicleState::Driving;
+                world.spawn((Transform::new(pos), Kinematics::default(), v, it))
+            })
+            .collect();
+
+        let mut resources = Resources::default();
+        resources.insert(map);
+        resources.insert(GameTime::new(0.1, 100.0));
+        resources.insert(GridlockDetector::default());
+        (world, resources, vehicles)
+    }
+
+    /// Makes the vehicles wait on each other in a cycle and runs a gridlock check
+    fn lock(world: &mut World, resources: &mut Resources, vehicles: &[Entity]) -> GridlockEvent {
+        for (i, &e) in vehicles.iter().enumerate() {
+            let mut v = world.get_mut::<Vehicle>(e).unwrap();
+            v.state = VehicleState::Driving;
+            v.waiting_for = vehicles
+                .get((i + 1) % vehicles.len())
+                .copied()
+                .map(VehicleID);
+        }
+        for _ in 0..GRIDLOCK_CHECK_PERIOD {
+            gridlock_update(world, resources);
+        }
+        resources
+            .get::<GridlockDetector>()
+            .unwrap()
+            .events()
+            .last()
+            .unwrap()
+            .clone()
+    }
+
+    fn pos(world: &World, e: Entity) -> Vec3 {
+        world.get::<Transform>(e).unwrap().position
+    }
+
+    #[test]
+    fn reroutes_then_skips_ahead() {
+        let (mut world, mut resources, vehicles) = jam();
+        let victim = *vehicles.iter().max().unwrap();
+
+        let event = lock(&mut world, &mut resources, &vehicles);
+        assert_eq!(event.victim, victim);
+        assert_eq!(event.resolution, GridlockResolution::Reroute);
+        assert!(matches!(
+            world.get::<Vehicle>(victim).unwrap().state,
+            VehicleState::Panicking(_)
+        ));
+
+        let start = world
+            .get::<Itinerary>(victim)
+            .unwrap()
+            .next_lane_start(&resources.get::<Map>().unwrap())
+            .unwrap();
+        let event = lock(&mut world, &mut resources, &vehicles);
+        assert_eq!(event.resolution, GridlockResolution::SkipAhead);
+        assert!(pos(&world, victim).is_close(start, 1.0));
+
+        let detector = resources.get::<GridlockDetector>().unwrap();
+        assert_eq!(detector.n_gridlocks, 2);
+        assert_eq!(detector.n_reroutes, 1);
+        assert_eq!(detector.n_skips, 1);
+    }
+
+    #[test]
+    fn respawns_when_the_next_lane_is_occupied() {
+        let (mut world, mut resources, vehicles) = jam();
+        let victim = *vehicles.iter().max().unwrap();
+
+        lock(&mut world, &mut resources, &vehicles);
+
+        let it = world.get::<Itinerary>(victim).unwrap();
+        let start = it
+            .next_lane_start(&resources.get::<Map>().unwrap())
+            .unwrap();
+        let end = it.end_pos().unwrap();
+        drop(it);
+        let mut blocker = Vehicle::stored(VehicleKind::Car, Color::WHITE);
+        blocker.state = VehicleState::Driving;
+        world.spawn((
+            Transform::new(start),
+            Kinematics::default(),
+            blocker,
+            Itinerary::NONE,
+        ));
+
+        let event = lock(&mut world, &mut resources, &vehicles);
+        assert_eq!(event.resolution, GridlockResolution::Respawn);
+        assert_eq!(pos(&world, victim), end);
+        assert_eq!(resources.get::<GridlockDetector>().unwrap().n_respawns, 1);
+    }
+}
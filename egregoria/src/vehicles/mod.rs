@@ -1,4 +1,5 @@
 mod data;
+pub mod gridlock;
 pub mod systems;
 pub mod trains;
 
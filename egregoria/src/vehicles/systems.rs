@@ -1,9 +1,9 @@
-use crate::map::{Map, TrafficBehavior, Traversable, TraverseKind};
-use crate::map_dynamic::{Itinerary, OBJECTIVE_OK_DIST};
+use crate::map::{Lane, Map, PathKind, TrafficBehavior, Traversable, TraverseKind};
+use crate::map_dynamic::{Itinerary, LANE_CHANGE_LENGTH, OBJECTIVE_OK_DIST};
 use crate::physics::Kinematics;
 use crate::physics::{Collider, CollisionWorld, PhysicsGroup, PhysicsObject};
 use crate::utils::time::GameTime;
-use crate::vehicles::{Vehicle, VehicleState, TIME_TO_PARK};
+use crate::vehicles::{Vehicle, VehicleID, VehicleState, TIME_TO_PARK};
 use crate::ParCommandBuffer;
 use geom::{angle_lerpxy, Ray, Transform, Vec2, Vec3};
 use hecs::{Entity, World};
@@ -26,8 +26,8 @@ pub fn vehicle_decision_system(world: &mut World, resources: &mut Resources) {
         .iter_batched(32)
         .par_bridge()
         .for_each(|batch| {
-            batch.for_each(|(ent, (a, b, c, d, e))| {
-                vehicle_decision(ra, rb, rc, ent, a, b, c, d, e);
+            batch.for_each(|(_, (a, b, c, d, e))| {
+                vehicle_decision(ra, rb, rc, a, b, c, d, e);
             })
         })
 }
@@ -36,7 +36,6 @@ pub fn vehicle_decision(
     map: &Map,
     time: &GameTime,
     cow: &CollisionWorld,
-    me: Entity,
     it: &mut Itinerary,
     trans: &mut Transform,
     kin: &mut Kinematics,
@@ -54,16 +53,31 @@ pub fn vehicle_decision(
         let danger_length =
             (self_obj.speed.powi(2) / (2.0 * vehicle.kind.deceleration())).min(100.0);
         let neighbors = cow.query_around(trans.position.xy(), 12.0 + danger_length);
-        let objs = neighbors.map(|(id, pos)| {
-            (
-                pos,
-                cow.get(id).expect("Handle not in collision world").1,
-            )
-        });
-
-        let (s, d) = calc_decision(me, vehicle, map, time, trans, self_obj, it, objs);
+        let objs: Vec<_> = neighbors
+            .map(|(id, pos)| (pos, cow.get(id).expect("Handle not in collision world").1))
+            .collect();
+
+        let (s, d) = calc_decision(
+            vehicle,
+            map,
+            time,
+            trans,
+            self_obj,
+            it,
+            objs.iter().copied(),
+        );
         desired_speed = s;
         desired_dir = d;
+
+        change_lane_for_turn(map, trans, self_obj, it, &objs);
+
+        if matches!(vehicle.state, VehicleState::Driving) {
+            vehicle.lane_change_cooldown -= time.delta;
+            if vehicle.lane_change_cooldown <= 0.0 && desired_speed < 1.0 && self_obj.speed < 1.0 {
+                vehicle.lane_change_cooldown = LANE_CHANGE_COOLDOWN;
+                try_change_lane(map, trans, self_obj, it, &objs);
+            }
+        }
     }
 
     physics(
@@ -78,6 +92,126 @@ pub fn vehicle_decision(
     );
 }
 
+/// Seconds between two lane change attempts of a blocked vehicle
+const LANE_CHANGE_COOLDOWN: f32 = 3.0;
+
+/// Changes to a parallel lane if the vehicle is stuck behind a stopped vehicle
+/// and there is a big enough gap on the other lane.
+fn try_change_lane(
+    map: &Map,
+    trans: &Transform,
+    self_obj: &PhysicsObject,
+    it: &mut Itinerary,
+    neighs: &[(Vec2, &PhysicsObject)],
+) {
+    let l_id = match it.get_travers() {
+        Some(Traversable {
+            kind: TraverseKind::Lane(l_id),
+            ..
+        }) => *l_id,
+        _ => return,
+    };
+    let lane = unwrap_ret!(map.lanes().get(l_id));
+    let road = unwrap_ret!(map.roads().get(lane.parent));
+
+    let pos2 = trans.position.xy();
+    let dir2 = trans.dir.xy();
+    let blocked = neighs.iter().any(|&(his_pos, his_obj)| {
+        let towards = his_pos - pos2;
+        let along = towards.dot(dir2);
+        matches!(his_obj.group, PhysicsGroup::Vehicles)
+            && his_obj.speed < 1.0
+            && along > 0.0
+            && along < 8.0 + self_obj.radius + his_obj.radius
+            && towards.perp_dot(dir2).abs() < 1.5
+    });
+    if !blocked {
+        return;
+    }
+
+    for cand in road.parallel_lanes(l_id) {
+        let cand_lane = unwrap_cont!(map.lanes().get(cand));
+        if !lane_change_gap_ok(trans, self_obj, cand_lane, neighs) {
+            continue;
+        }
+        if it.change_lane(map, cand, trans.position, trans.dir) {
+            return;
+        }
+    }
+}
+
+/// A vehicle still waiting for a gap this close to the end of its lane gives up the planned
+/// lane change, in meters
+const LANE_CHANGE_GIVE_UP_DIST: f32 = LANE_CHANGE_LENGTH + OBJECTIVE_OK_DIST + 5.0;
+
+/// Changes to the lane the next turn starts from, when the route planned a lane change,
+/// as soon as there is a big enough gap on it.
+/// Without a gap before the end of the lane, the route is computed again from the current lane.
+fn change_lane_for_turn(
+    map: &Map,
+    trans: &Transform,
+    self_obj: &PhysicsObject,
+    it: &mut Itinerary,
+    neighs: &[(Vec2, &PhysicsObject)],
+) {
+    let (cur, wanted) = unwrap_ret!(it.planned_lane_change());
+    let lane = unwrap_ret!(map.lanes().get(cur));
+
+    let remaining = lane.points.length()
+        - lane
+            .points
+            .length_at_proj(lane.points.project(trans.position));
+    if remaining < LANE_CHANGE_GIVE_UP_DIST {
+        if !it.reroute(map, trans.position) {
+            if let Some(end) = it.end_pos() {
+                *it = Itinerary::wait_for_reroute(PathKind::Vehicle, end);
+            }
+        }
+        return;
+    }
+
+    let wanted_lane = unwrap_ret!(map.lanes().get(wanted));
+    if lane_change_gap_ok(trans, self_obj, wanted_lane, neighs) {
+        it.change_lane(map, wanted, trans.position, trans.dir);
+    }
+}
+
+/// Checks that no vehicle on `lane` is in the way of the lane change,
+/// the same way `calc_front_dist` looks for obstacles in front.
+fn lane_change_gap_ok(
+    trans: &Transform,
+    self_obj: &PhysicsObject,
+    lane: &Lane,
+    neighs: &[(Vec2, &PhysicsObject)],
+) -> bool {
+    let pos2 = trans.position.xy();
+    let dir2 = trans.dir.xy();
+
+    for &(his_pos, his_obj) in neighs {
+        if !matches!(his_obj.group, PhysicsGroup::Vehicles)
+            || (his_obj.height - trans.position.z).abs() > 5.0
+        {
+            continue;
+        }
+        if lane.points.project_dist(his_pos.z(his_obj.height)) > 2.5 {
+            continue;
+        }
+
+        let along = (his_pos - pos2).dot(dir2);
+        let margin = self_obj.radius + his_obj.radius + 2.0;
+        if along >= 0.0 {
+            // Room to finish the maneuver in front
+            if along < margin + LANE_CHANGE_LENGTH {
+                return false;
+            }
+        } else if -along < margin + his_obj.speed * 2.0 {
+            // Don't cut off vehicles coming from behind
+            return false;
+        }
+    }
+    true
+}
+
 #[profiling::function]
 pub fn vehicle_state_update_system(world: &mut World, resources: &mut Resources) {
     let ra = &*resources.get().unwrap();
@@ -154,6 +288,7 @@ fn physics(
             trans.dir = spline.derivative(t).normalize();
             return;
         }
+        VehicleState::Stored => return,
         _ => {}
     }
 
@@ -183,7 +318,6 @@ fn physics(
 
 /// Decide the appropriate velocity and direction to aim for.
 pub fn calc_decision<'a>(
-    me: Entity,
     vehicle: &mut Vehicle,
     map: &Map,
     time: &GameTime,
@@ -205,7 +339,7 @@ pub fn calc_decision<'a>(
 
     let cutoff = (0.8 + stop_dist).min(1.5);
 
-    let (front_dist, flag) = calc_front_dist(vehicle, trans, self_obj, it, neighs, cutoff);
+    let (front_dist, front_vehicle) = calc_front_dist(vehicle, trans, self_obj, it, neighs, cutoff);
 
     let position = trans.position;
     let dir_to_pos = unwrap_or!(
@@ -218,16 +352,8 @@ pub fn calc_decision<'a>(
             vehicle.state = VehicleState::Driving;
         }
     } else if speed.abs() < 0.2 && front_dist < 1.5 {
-        let me_u64: u64 = unsafe { std::mem::transmute(me) };
-        if me_u64 == flag {
-            vehicle.state = VehicleState::Panicking(time.instant());
-            log::info!("gridlock!")
-        }
-        vehicle.flag = if vehicle.flag | flag == 0 {
-            me_u64
-        } else {
-            flag
-        };
+        // Cycles of waiting vehicles are found and resolved by `gridlock_update`
+        vehicle.waiting_for = front_vehicle;
         vehicle.wait_time = (position.x * 1000.0).fract().abs() * 0.5;
         return default_return;
     } else {
@@ -237,7 +363,7 @@ pub fn calc_decision<'a>(
         }
     }
 
-    vehicle.flag = 0;
+    vehicle.waiting_for = None;
 
     if let Some(term_pos) = it.get_terminal() {
         if term_pos.is_close(position, 1.0 + stop_dist) {
@@ -283,17 +409,22 @@ pub fn calc_decision<'a>(
         }
     }
 
+    let max_speed = vehicle.kind.max_speed();
+
     // Not facing the objective
     if dir_to_pos.dot(trans.dir) < 0.8 {
-        return (6.0, dir_to_pos);
+        return (max_speed.min(6.0), dir_to_pos);
     }
 
-    (vehicle.kind.speed_factor() * speed, dir_to_pos)
+    (
+        max_speed.min(vehicle.kind.speed_factor() * speed),
+        dir_to_pos,
+    )
 }
 
 /// Calculates the distance to the closest problematic object in front of the car.
 /// It can be another car or a pedestrian, or it can be a potential collision point from a
-/// car coming perpendicularly.
+/// car coming perpendicularly. Also returns the vehicle responsible for it, if any.
 fn calc_front_dist<'a>(
     vehicle: &mut Vehicle,
     trans: &Transform,
@@ -301,7 +432,7 @@ fn calc_front_dist<'a>(
     it: &Itinerary,
     neighs: impl Iterator<Item = (Vec2, &'a PhysicsObject)>,
     cutoff: f32,
-) -> (f32, u64) {
+) -> (f32, Option<VehicleID>) {
     let position = trans.position;
     let direction = trans.dir;
     let pos2 = position.xy();
@@ -318,7 +449,7 @@ fn calc_front_dist<'a>(
     let speed = self_obj.speed;
 
     let on_lane = it.get_travers().map_or(false, |t| t.kind.is_lane());
-    let mut flag = 0;
+    let mut front_vehicle = None;
     // Collision avoidance
     for (his_pos, nei_physics_obj) in neighs {
         if (nei_physics_obj.height - position.z).abs() > 5.0 {
@@ -357,10 +488,10 @@ fn calc_front_dist<'a>(
             }
             if dist_to_obj < min_front_dist {
                 min_front_dist = dist_to_obj;
-                flag = nei_physics_obj.flag;
+                front_vehicle = nei_physics_obj.vehicle;
             }
             if min_front_dist < cutoff {
-                return (min_front_dist, flag);
+                return (min_front_dist, front_vehicle);
             }
             continue;
         }
@@ -391,8 +522,8 @@ fn calc_front_dist<'a>(
         let final_dist = dist - my_radius - nei_physics_obj.radius - 5.0;
         if final_dist < min_front_dist {
             min_front_dist = final_dist;
-            flag = nei_physics_obj.flag;
+            front_vehicle = nei_physics_obj.vehicle;
         }
     }
-    (min_front_dist, flag)
+    (min_front_dist, front_vehicle)
 }
@@ -1,8 +1,11 @@
 use common::logger::MyLog;
 use common::unwrap_or;
 use egregoria::engine_interaction::WorldCommands;
+use egregoria::map::Map;
+use egregoria::map_dynamic::TripLog;
 use egregoria::Egregoria;
 use networking::{Frame, Server, ServerConfiguration, ServerPollResult};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
@@ -27,6 +30,14 @@ struct Opt {
     /// i.e. 20ms = 50FPS
     #[structopt(long, default_value = "20")]
     timestep: u64,
+
+    /// Repair the broken map invariants of the save on load
+    #[structopt(long)]
+    repair_map: bool,
+
+    /// Export the trip log as CSV to this file at each auto save
+    #[structopt(long)]
+    trips_csv: Option<PathBuf>,
 }
 
 fn main() {
@@ -40,6 +51,18 @@ fn main() {
         Egregoria::new(true)
     });
 
+    let violations = w.map().invariant_violations();
+    if !violations.is_empty() {
+        log::warn!("map has {} broken invariants", violations.len());
+        for v in &violations {
+            log::warn!("{:?}", v);
+        }
+        if opt.repair_map {
+            let repaired = w.write::<Map>().repair_invariants(&violations);
+            log::info!("repaired {} objects", repaired);
+        }
+    }
+
     let mut sched = Egregoria::schedule();
 
     let mut server: Server<Egregoria, WorldCommands> = match Server::start(ServerConfiguration {
@@ -71,9 +94,20 @@ fn main() {
 
         if last_saved.elapsed().as_secs() > opt.autosave {
             w.save_to_disk("world");
+            if let Some(ref path) = opt.trips_csv {
+                export_trips(&w, path);
+            }
             last_saved = Instant::now();
         }
 
         std::thread::sleep(Duration::from_millis(1));
     }
 }
+
+fn export_trips(w: &Egregoria, path: &Path) {
+    let trips = w.read::<TripLog>();
+    match std::fs::File::create(path).and_then(|f| trips.write_csv(std::io::BufWriter::new(f))) {
+        Ok(()) => log::info!("exported {} trips to {}", trips.len(), path.display()),
+        Err(e) => log::error!("couldn't export trips to {}: {}", path.display(), e),
+    }
+}
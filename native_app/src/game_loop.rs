@@ -6,6 +6,7 @@ use winit::window::{Fullscreen, Window};
 
 use crate::rendering::immediate::{ImmediateDraw, ImmediateSound};
 use common::History;
+use egregoria::map_dynamic::TrafficStats;
 use egregoria::utils::time::GameTime;
 use egregoria::Egregoria;
 use geom::{Camera, LinearColor};
@@ -16,6 +17,7 @@ use crate::context::Context;
 use crate::gui::inputmap::{InputAction, InputMap};
 use crate::gui::windows::debug::DebugObjs;
 use crate::gui::windows::settings::Settings;
+use crate::gui::windows::transport::TrafficHeatmap;
 use crate::gui::{FollowEntity, Gui, UiTextures};
 use crate::input::{KeyCode, KeyboardInfo, MouseInfo};
 use crate::rendering::imgui_wrapper::ImguiWrapper;
@@ -195,6 +197,15 @@ impl State {
             ctx,
         );
 
+        if let Some(metric) = self.uiw.read::<TrafficHeatmap>().metric {
+            RoadRenderer::traffic_heatmap(
+                &goria.map(),
+                goria.read::<TrafficStats>().latest(),
+                metric,
+                &mut self.immtess,
+            );
+        }
+
         self.instanced_renderer
             .render(&self.goria.read().unwrap(), ctx);
 
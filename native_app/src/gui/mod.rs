@@ -18,6 +18,7 @@ pub mod roadbuild;
 pub mod roadeditor;
 pub mod selectable;
 pub mod specialbuilding;
+pub mod terraforming;
 pub mod topgui;
 
 pub mod addtrain;
@@ -37,6 +38,7 @@ pub fn run_ui_systems(goria: &Egregoria, uiworld: &mut UiWorld) {
     roadeditor::roadeditor(goria, uiworld);
     selectable::selectable(goria, uiworld);
     specialbuilding::specialbuilding(goria, uiworld);
+    terraforming::terraforming(goria, uiworld);
     addtrain::addtrain(goria, uiworld);
 }
 
@@ -63,6 +65,7 @@ pub enum Tool {
     RoadEditor,
     Bulldozer,
     LotBrush,
+    Terraform,
     SpecialBuilding,
     Train,
 }
@@ -81,6 +84,7 @@ pub enum UiTex {
     Bulldozer,
     Buildings,
     LotBrush,
+    Terraform,
     TrainStation,
     AddTrain,
 }
@@ -92,6 +96,8 @@ const UI_TEXTURES: &[(UiTex, &str)] = &[
     (UiTex::Bulldozer, "assets/ui/bulldozer.png"),
     (UiTex::Buildings, "assets/ui/buildings.png"),
     (UiTex::LotBrush, "assets/ui/lotbrush.png"),
+    // No terraforming icon yet, the terrain brush shares the lot brush one
+    (UiTex::Terraform, "assets/ui/lotbrush.png"),
     (UiTex::TrainStation, "assets/ui/trainstation.png"),
     (UiTex::AddTrain, "assets/ui/traintool.png"),
 ];
@@ -2,7 +2,7 @@ use crate::gui::Tool;
 use crate::input::{MouseButton, MouseInfo};
 use crate::rendering::immediate::ImmediateDraw;
 use crate::uiworld::UiWorld;
-use egregoria::map::{IntersectionID, LightPolicy, TurnPolicy};
+use egregoria::map::{IntersectionID, LaneID, LaneTurns, LightPolicy, TurnPolicy};
 use egregoria::map::{ProjectFilter, ProjectKind};
 use egregoria::Egregoria;
 use geom::Color;
@@ -18,6 +18,12 @@ pub struct IntersectionComponent {
 pub struct RoadEditorResource {
     pub inspect: Option<IntersectionComponent>,
     pub dirty: bool,
+    /// When enabled, clicked intersections are appended to the green wave corridor
+    pub corridor_mode: bool,
+    pub corridor: Vec<IntersectionID>,
+    /// Incoming lane of the inspected intersection whose turns are being edited
+    pub selected_lane: Option<(LaneID, LaneTurns)>,
+    pub lane_dirty: bool,
 }
 
 #[profiling::function]
@@ -31,13 +37,32 @@ pub fn roadeditor(goria: &Egregoria, uiworld: &mut UiWorld) {
 
     if !matches!(*tool, Tool::RoadEditor) {
         state.inspect = None;
+        state.corridor_mode = false;
+        state.corridor.clear();
         return;
     }
 
+    if state.corridor.len() >= 2 {
+        let points: Vec<_> = state
+            .corridor
+            .iter()
+            .flat_map(|&id| map.intersections().get(id))
+            .map(|i| i.pos.up(0.1))
+            .collect();
+        imm_draw
+            .polyline(points, 3.0, false)
+            .color(common::config().gui_primary);
+    }
+
+    let selected_lane = state.selected_lane.map(|x| x.0);
+
     if let Some(id) = state.inspect.as_ref().map(|x| x.id) {
         if let Some(inter) = map.intersections().get(id) {
             let lanes = map.lanes();
             for turn in inter.turns() {
+                if selected_lane.map_or(false, |l| l != turn.id.src) {
+                    continue;
+                }
                 let p = unwrap_or!(turn.points.get(turn.points.n_points() / 2), continue);
                 let r = common::rand::rand2(p.x, p.y);
                 let col = Color::hsv(r * 360.0, 0.8, 0.6, 0.5);
@@ -52,8 +77,27 @@ pub fn roadeditor(goria: &Egregoria, uiworld: &mut UiWorld) {
 
                 imm_draw.polyline(p, 1.0, false).color(col);
             }
+
+            for lane in inter
+                .roads
+                .iter()
+                .flat_map(|&r| map.roads().get(r))
+                .flat_map(|r| r.incoming_lanes_to(id))
+                .filter(|(_, kind)| kind.vehicles())
+                .flat_map(|&(l, _)| lanes.get(l))
+            {
+                let col = if Some(lane.id) == selected_lane {
+                    common::config().gui_success
+                } else {
+                    common::config().gui_primary
+                };
+                imm_draw
+                    .circle(lane.control_point().up(0.1), 1.0)
+                    .color(col);
+            }
         } else {
             state.inspect = None;
+            state.selected_lane = None;
         }
     }
 
@@ -71,17 +115,38 @@ pub fn roadeditor(goria: &Egregoria, uiworld: &mut UiWorld) {
         proj_col = common::config().gui_disabled;
     }
 
+    let clicked_lane = state.inspect.as_ref().and_then(|x| {
+        let inter = map.intersections().get(x.id)?;
+        inter
+            .roads
+            .iter()
+            .flat_map(|&r| map.roads().get(r))
+            .flat_map(|r| r.incoming_lanes_to(inter.id))
+            .filter(|(_, kind)| kind.vehicles())
+            .flat_map(|&(l, _)| map.lanes().get(l))
+            .find(|l| l.control_point().is_close(proj_pos, 2.0))
+            .map(|l| (inter, l.id))
+    });
+
     if mouseinfo.pressed.contains(&MouseButton::Left) {
-        if let ProjectKind::Inter(id) = cur_proj.kind {
+        if let Some((inter, lane)) = clicked_lane {
+            let turns = inter.lane_turns.get(&lane).copied().unwrap_or_default();
+            state.selected_lane = Some((lane, turns));
+            state.lane_dirty = false;
+        } else if let ProjectKind::Inter(id) = cur_proj.kind {
             proj_col = common::config().gui_success;
             proj_pos = cur_proj.pos;
             let inter = &map.intersections()[id];
+            if state.corridor_mode && state.corridor.last() != Some(&id) {
+                state.corridor.push(id);
+            }
             state.inspect = Some(IntersectionComponent {
                 id,
                 turn_policy: inter.turn_policy,
                 light_policy: inter.light_policy,
             });
             state.dirty = false;
+            state.selected_lane = None;
         }
     }
 
@@ -97,4 +162,11 @@ pub fn roadeditor(goria: &Egregoria, uiworld: &mut UiWorld) {
         }
         state.dirty = false;
     }
+
+    if state.lane_dirty {
+        if let (Some(interc), Some((lane, turns))) = (&state.inspect, state.selected_lane) {
+            commands.map_update_lane_turns(interc.id, lane, turns);
+        }
+        state.lane_dirty = false;
+    }
 }
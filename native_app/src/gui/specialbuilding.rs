@@ -77,6 +77,8 @@ pub fn specialbuilding(goria: &Egregoria, uiworld: &mut UiWorld) {
         } else if p.ends_with(".glb") {
             draw.mesh(p, obb.center().z(mpos.z), obb.axis()[0].normalize().z0())
                 .color(col);
+        } else {
+            draw.obb(obb, mpos.z + 0.1).color(col);
         }
     };
 
@@ -142,6 +144,7 @@ pub fn specialbuilding(goria: &Egregoria, uiworld: &mut UiWorld) {
             }
         })
         || state.last_obb.map(|x| x.intersects(&obb)).unwrap_or(false)
+        || map.terrain.obb_in_water(&obb)
     {
         draw(obb, true);
         return;
@@ -0,0 +1,121 @@
+use super::Tool;
+use crate::input::{MouseButton, MouseInfo};
+use crate::rendering::immediate::ImmediateDraw;
+use crate::uiworld::UiWorld;
+use egregoria::map::TerraformKind;
+use egregoria::Egregoria;
+use geom::Vec2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub enum TerraformMode {
+    Terrain(TerraformKind),
+    /// Clicks add points to the river path
+    River,
+    PlantTree,
+    Forest,
+    ClearTrees,
+}
+
+impl TerraformMode {
+    /// Whether both modes correspond to the same button, ignoring the flatten height
+    pub fn same_brush(self, other: TerraformMode) -> bool {
+        match (self, other) {
+            (TerraformMode::Terrain(a), TerraformMode::Terrain(b)) => {
+                std::mem::discriminant(&a) == std::mem::discriminant(&b)
+            }
+            (a, b) => std::mem::discriminant(&a) == std::mem::discriminant(&b),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TerraformingResource {
+    pub mode: TerraformMode,
+    pub radius: f32,
+    pub amount: f32,
+    /// Trees per hectare when painting forests
+    pub density: f32,
+    #[serde(skip)]
+    pub river: Vec<Vec2>,
+}
+
+#[profiling::function]
+pub fn terraforming(goria: &Egregoria, uiworld: &mut UiWorld) {
+    let mut res = uiworld.write::<TerraformingResource>();
+    let tool = *uiworld.read::<Tool>();
+    let mouseinfo = uiworld.read::<MouseInfo>();
+    let mut draw = uiworld.write::<ImmediateDraw>();
+    let map = goria.map();
+    let commands = &mut *uiworld.commands();
+
+    if !matches!(tool, Tool::Terraform) {
+        return;
+    }
+
+    let mut col = common::config().gui_primary;
+    col.a = 0.2;
+
+    let mpos = unwrap_ret!(mouseinfo.unprojected);
+    let clicked = mouseinfo.just_pressed.contains(&MouseButton::Left);
+
+    match res.mode {
+        TerraformMode::River => {
+            let mut points: Vec<_> = res.river.iter().map(|p| p.z(mpos.z + 0.8)).collect();
+            points.push(mpos.up(0.8));
+            if points.len() >= 2 {
+                draw.polyline(points, res.radius * 2.0, false).color(col);
+            } else {
+                draw.circle(mpos.up(0.8), res.radius).color(col);
+            }
+
+            if clicked {
+                res.river.push(mpos.xy());
+            }
+        }
+        TerraformMode::PlantTree => {
+            draw.circle(mpos.up(0.8), 3.0).color(col);
+            if clicked {
+                commands.map_plant_tree(mpos.xy());
+            }
+        }
+        TerraformMode::Forest => {
+            draw.circle(mpos.up(0.8), res.radius).color(col);
+            if clicked {
+                commands.map_paint_forest(mpos.xy(), res.radius, res.density);
+            }
+        }
+        TerraformMode::ClearTrees => {
+            draw.circle(mpos.up(0.8), res.radius)
+                .color(common::config().gui_danger);
+            if clicked {
+                commands.map_clear_trees(mpos.xy(), res.radius);
+            }
+        }
+        TerraformMode::Terrain(kind) => {
+            draw.circle(mpos.up(0.8), res.radius).color(col);
+            if clicked {
+                let kind = match kind {
+                    // Flatten to the height under the cursor
+                    TerraformKind::Flatten(_) => {
+                        TerraformKind::Flatten(unwrap_ret!(map.terrain.height(mpos.xy())))
+                    }
+                    kind => kind,
+                };
+                commands.map_terraform(kind, mpos.xy(), res.radius, res.amount);
+            }
+        }
+    }
+}
+
+impl Default for TerraformingResource {
+    fn default() -> Self {
+        Self {
+            mode: TerraformMode::Terrain(TerraformKind::Raise),
+            radius: 100.0,
+            amount: 5.0,
+            density: 50.0,
+            river: vec![],
+        }
+    }
+}
@@ -3,6 +3,7 @@ use crate::gui::inputmap::{InputAction, InputMap};
 use crate::gui::lotbrush::LotBrushResource;
 use crate::gui::roadeditor::RoadEditorResource;
 use crate::gui::specialbuilding::{SpecialBuildKind, SpecialBuildingResource};
+use crate::gui::terraforming::{TerraformMode, TerraformingResource};
 use crate::gui::windows::settings::Settings;
 use crate::gui::windows::ImguiWindows;
 use crate::gui::{InspectedEntity, RoadBuildResource, Tool, UiTex, UiTextures};
@@ -11,12 +12,13 @@ use crate::uiworld::UiWorld;
 use common::saveload::Encoder;
 use egregoria::economy::Government;
 use egregoria::map::{
-    BuildingGen, BuildingKind, LanePatternBuilder, LightPolicy, LotKind, StraightRoadGen,
-    TurnPolicy,
+    BuildingGen, BuildingKind, LanePatternBuilder, LaneTurns, LightPolicy, LotKind,
+    StraightRoadGen, TerraformKind, TurnPolicy,
 };
 use egregoria::souls::goods_company::GoodsCompanyRegistry;
 use egregoria::utils::time::GameTime;
 use egregoria::Egregoria;
+use geom::PolyLine;
 use imgui::{StyleColor, StyleVar, Ui, Window};
 use imgui_inspect::{
     InspectArgsDefault, InspectArgsStruct, InspectRenderDefault, InspectRenderStruct,
@@ -97,6 +99,7 @@ impl Gui {
             Roadcurved,
             Roadeditor,
             Lotbrush,
+            Terraform,
             Roadbuilding,
             Bulldozer,
             Train,
@@ -125,6 +128,7 @@ impl Gui {
             (UiTex::Curved, Tab::Roadcurved, Tool::RoadbuildCurved),
             (UiTex::RoadEdit, Tab::Roadeditor, Tool::RoadEditor),
             (UiTex::LotBrush, Tab::Lotbrush, Tool::LotBrush),
+            (UiTex::Terraform, Tab::Terraform, Tool::Terraform),
             (UiTex::Buildings, Tab::Roadbuilding, Tool::SpecialBuilding),
             (UiTex::Bulldozer, Tab::Bulldozer, Tool::Bulldozer),
             (UiTex::AddTrain, Tab::Train, Tool::Train),
@@ -168,8 +172,12 @@ impl Gui {
             let state = &mut *uiworld.write::<RoadEditorResource>();
             if let Some(ref mut v) = state.inspect {
                 let dirty = &mut state.dirty;
+                let corridor = &mut state.corridor;
+                let corridor_mode = &mut state.corridor_mode;
+                let selected_lane = &mut state.selected_lane;
+                let lane_dirty = &mut state.lane_dirty;
                 Window::new("Road Properties")
-                    .size([150.0, 200.0], imgui::Condition::Appearing)
+                    .size([150.0, 300.0], imgui::Condition::Appearing)
                     .position(
                         [w - 150.0 - toolbox_w, h * 0.5 - 30.0],
                         imgui::Condition::Appearing,
@@ -203,6 +211,34 @@ impl Gui {
                                 ..Default::default()
                             },
                         );
+                        if let Some((_, ref mut turns)) = *selected_lane {
+                            ui.new_line();
+                            ui.text("Selected lane turns");
+                            *lane_dirty |=
+                                <LaneTurns as InspectRenderDefault<LaneTurns>>::render_mut(
+                                    &mut [turns],
+                                    "Lane turns",
+                                    ui,
+                                    &InspectArgsDefault {
+                                        header: Some(false),
+                                        indent_children: Some(false),
+                                        ..Default::default()
+                                    },
+                                );
+                        }
+                        ui.new_line();
+                        ui.text("Green wave");
+                        ui.checkbox("Select corridor", corridor_mode);
+                        ui.text(format!("{} intersections", corridor.len()));
+                        if corridor.len() >= 2 && ui.small_button("Apply") {
+                            uiworld
+                                .commands()
+                                .map_make_green_wave(std::mem::take(corridor));
+                            *corridor_mode = false;
+                        }
+                        if ui.small_button("Clear") {
+                            corridor.clear();
+                        }
                     });
             }
         }
@@ -347,6 +383,10 @@ impl Gui {
                         *pat = *LanePatternBuilder::new().one_way(true);
                     }
 
+                    if ui.button_with_size("Street bike lanes", [rbw, 30.0]) {
+                        *pat = *LanePatternBuilder::new().bike_lanes(true);
+                    }
+
                     if ui.button_with_size("Avenue", [rbw, 30.0]) {
                         *pat = *LanePatternBuilder::new().n_lanes(2).speed_limit(13.0);
                     }
@@ -459,6 +499,94 @@ impl Gui {
                 });
         }
 
+        let terraform_brushes = [
+            ("Raise", TerraformMode::Terrain(TerraformKind::Raise)),
+            ("Lower", TerraformMode::Terrain(TerraformKind::Lower)),
+            (
+                "Flatten",
+                TerraformMode::Terrain(TerraformKind::Flatten(0.0)),
+            ),
+            ("Smooth", TerraformMode::Terrain(TerraformKind::Smooth)),
+            ("River", TerraformMode::River),
+            ("Plant tree", TerraformMode::PlantTree),
+            ("Forest", TerraformMode::Forest),
+            ("Clear trees", TerraformMode::ClearTrees),
+        ];
+
+        if matches!(*uiworld.read::<Tab>(), Tab::Terraform) {
+            let tw = 130.0;
+            Window::new("Terraforming")
+                .size(
+                    [tw, 100.0 + terraform_brushes.len() as f32 * 35.0],
+                    imgui::Condition::Appearing,
+                )
+                .position(
+                    [w - toolbox_w - tw, h * 0.5 - 30.0],
+                    imgui::Condition::Appearing,
+                )
+                .scroll_bar(false)
+                .title_bar(true)
+                .movable(false)
+                .collapsible(false)
+                .resizable(false)
+                .build(ui, || {
+                    let mut cur_brush = uiworld.write::<TerraformingResource>();
+
+                    for (name, brush) in &terraform_brushes {
+                        let tok = ui.push_style_var(StyleVar::Alpha(
+                            if brush.same_brush(cur_brush.mode) {
+                                1.0
+                            } else {
+                                0.5
+                            },
+                        ));
+                        if ui.button_with_size(name, [tw, 35.0]) {
+                            cur_brush.mode = *brush;
+                        }
+                        tok.pop();
+                    }
+
+                    imgui::Drag::new("size")
+                        .range(10.0, 500.0)
+                        .display_format("%.0f")
+                        .build(ui, &mut cur_brush.radius);
+
+                    match cur_brush.mode {
+                        TerraformMode::Terrain(kind) => {
+                            let (range, format) = match kind {
+                                TerraformKind::Raise | TerraformKind::Lower => (50.0, "%.1f m"),
+                                TerraformKind::Flatten(_) | TerraformKind::Smooth => (1.0, "%.2f"),
+                            };
+                            cur_brush.amount = cur_brush.amount.min(range);
+                            imgui::Drag::new("strength")
+                                .range(0.0, range)
+                                .speed(range / 100.0)
+                                .display_format(format)
+                                .build(ui, &mut cur_brush.amount);
+                        }
+                        TerraformMode::River => {
+                            ui.text(format!("{} points", cur_brush.river.len()));
+                            if cur_brush.river.len() >= 2 && ui.small_button("Dig") {
+                                let path = std::mem::take(&mut cur_brush.river);
+                                uiworld
+                                    .commands()
+                                    .map_add_river(PolyLine::new(path), cur_brush.radius * 2.0);
+                            }
+                            if ui.small_button("Clear") {
+                                cur_brush.river.clear();
+                            }
+                        }
+                        TerraformMode::Forest => {
+                            imgui::Drag::new("trees/ha")
+                                .range(1.0, 200.0)
+                                .display_format("%.0f")
+                                .build(ui, &mut cur_brush.density);
+                        }
+                        TerraformMode::PlantTree | TerraformMode::ClearTrees => {}
+                    }
+                });
+        }
+
         if matches!(*uiworld.read::<Tab>(), Tab::Bulldozer) {
             let lbw = 80.0;
             Window::new("Bulldozer")
@@ -541,6 +669,35 @@ impl Gui {
                         tok.pop();
                     }
 
+                    for (name, asset, levels, size) in [
+                        ("Parking lot", "parking_lot", 1, 40.0),
+                        ("Parking garage", "parking_garage", 4, 40.0),
+                    ] {
+                        let cur_kind = cur_build.opt.as_ref().map(|x| &*x.asset).unwrap_or("");
+                        let tok = ui.push_style_var(StyleVar::Alpha(if asset == cur_kind {
+                            1.0
+                        } else {
+                            0.5
+                        }));
+                        if ui.button_with_size(name, [building_select_w - 10.0, 35.0]) {
+                            cur_build.opt = Some(SpecialBuildKind {
+                                road_snap: true,
+                                make: Box::new(move |args, commands| {
+                                    commands.map_build_special_building(
+                                        args.obb,
+                                        BuildingKind::Parking,
+                                        BuildingGen::Parking { levels },
+                                        vec![],
+                                    );
+                                }),
+                                w: size,
+                                h: size,
+                                asset: asset.to_string(),
+                            });
+                        }
+                        tok.pop();
+                    }
+
                     let bdescrpt_w = 180.0;
 
                     if let Some(descr) = picked_descr {
@@ -3,21 +3,25 @@
 use crate::game_loop::Timings;
 use crate::gui::InspectedEntity;
 use crate::uiworld::UiWorld;
-use egregoria::map_dynamic::{Itinerary, ParkingManagement};
+use egregoria::map_dynamic::{Itinerary, ParkingManagement, ParkingOccupancy};
 use egregoria::physics::CollisionWorld;
 use egregoria::utils::time::{GameTime, SECONDS_PER_DAY};
 use egregoria::Egregoria;
 
 use crate::gui::inputmap::InputMap;
-use egregoria::map::{IntersectionID, Map, RoadSegmentKind, TraverseKind};
+use egregoria::map::{
+    IntersectionID, InvariantViolation, Map, RoadSegmentKind, TrafficBehavior, TraverseKind,
+};
+use egregoria::vehicles::gridlock::{GridlockDetector, GridlockResolution};
 use egregoria::vehicles::trains::TrainReservations;
-use geom::{Camera, Color, LinearColor, Spline3, Vec2};
+use geom::{Camera, Color, LinearColor, Spline3, Vec2, Vec3};
 use imgui::Ui;
 use wgpu_engine::Tesselator;
 
 #[derive(Default)]
 pub struct DebugState {
     connectivity: (u32, Vec<Vec<IntersectionID>>),
+    invariants: Option<Vec<InvariantViolation>>,
 }
 
 pub struct DebugObjs(
@@ -40,6 +44,9 @@ impl Default for DebugObjs {
             (false, "Debug lots", debug_lots),
             (false, "Debug road points", debug_road_points),
             (false, "Debug parking", debug_parking),
+            (false, "Debug parking occupancy", debug_parking_occupancy),
+            (false, "Debug gridlocks", debug_gridlocks),
+            (false, "Debug light phases", debug_light_phases),
         ])
     }
 }
@@ -86,6 +93,27 @@ pub fn debug(
                 time + daysecleft as f64 + 18.0 * GameTime::HOUR as f64,
             ));
         }
+
+        ui.separator();
+        let mut state = uiworld.write::<DebugState>();
+        if ui.small_button("check map invariants") {
+            state.invariants = Some(goria.map().invariant_violations());
+        }
+        if let Some(ref violations) = state.invariants {
+            ui.text(format!("{} violations", violations.len()));
+            for v in violations.iter().take(20) {
+                let fixable = if v.is_fixable() { "" } else { " (not fixable)" };
+                ui.text(format!("{:?}{}", v, fixable));
+            }
+            if violations.iter().any(InvariantViolation::is_fixable)
+                && ui.small_button("repair map")
+            {
+                uiworld.commands().map_repair_invariants();
+                state.invariants = None;
+            }
+        }
+        drop(state);
+
         let timings = uiworld.read::<Timings>();
         let mouse = uiworld.read::<InputMap>().unprojected;
         let cam = uiworld.read::<Camera>().pos;
@@ -219,6 +247,42 @@ pub fn debug_connectivity(tess: &mut Tesselator, goria: &Egregoria, uiw: &UiWorl
     Some(())
 }
 
+/// Draws the upcoming light states of each controlled lane as a timeline starting at the stop line,
+/// one segment per second. Intersections synchronized in a green wave are circled.
+pub fn debug_light_phases(tess: &mut Tesselator, goria: &Egregoria, _: &UiWorld) -> Option<()> {
+    const LOOKAHEAD: u32 = 40;
+    const SEG_LENGTH: f32 = 0.5;
+
+    let map = goria.map();
+    let seconds = goria.read::<GameTime>().seconds;
+
+    for (_, lane) in map.lanes() {
+        if !lane.control.is_light() {
+            continue;
+        }
+        let dir = -lane.points.last_dir().unwrap_or(Vec3::X);
+        let start = lane.control_point().up(0.3);
+        for k in 0..LOOKAHEAD {
+            tess.set_color(match lane.control.get_behavior(seconds + k) {
+                TrafficBehavior::GREEN => LinearColor::GREEN,
+                TrafficBehavior::ORANGE => LinearColor::ORANGE,
+                TrafficBehavior::RED | TrafficBehavior::STOP => LinearColor::RED,
+            });
+            let p1 = start + dir * (k as f32 * SEG_LENGTH);
+            tess.draw_stroke(p1, p1 + dir * SEG_LENGTH, 0.6);
+        }
+    }
+
+    tess.set_color(Color::WHITE.a(0.5));
+    for inter in map.intersections().values() {
+        if inter.light_sync.is_some() {
+            tess.draw_stroke_circle(inter.pos.up(0.3), 12.0, 0.5);
+        }
+    }
+
+    Some(())
+}
+
 fn draw_spline(tess: &mut Tesselator, mut sp: Spline3) {
     sp.from = sp.from.up(0.3);
     sp.to = sp.to.up(0.3);
@@ -333,6 +397,48 @@ pub fn debug_parking(tess: &mut Tesselator, goria: &Egregoria, _: &UiWorld) -> O
     Some(())
 }
 
+pub fn debug_parking_occupancy(
+    tess: &mut Tesselator,
+    goria: &Egregoria,
+    _: &UiWorld,
+) -> Option<()> {
+    let map = goria.map();
+    let occupancy = goria.read::<ParkingOccupancy>();
+
+    for (aabb, occ) in occupancy.areas() {
+        let ratio = occ.ratio();
+        let color = ratio * LinearColor::RED + (1.0 - ratio) * LinearColor::GREEN;
+        tess.set_color(color.a(0.3));
+
+        let center = aabb.center();
+        let z = map.terrain.height(center).unwrap_or(0.0) + 1.0;
+        tess.draw_rect_cos_sin(center.z(z), aabb.w() - 4.0, aabb.h() - 4.0, Vec2::X);
+    }
+
+    Some(())
+}
+
+pub fn debug_gridlocks(tess: &mut Tesselator, goria: &Egregoria, _: &UiWorld) -> Option<()> {
+    let time = goria.read::<GameTime>();
+    let detector = goria.read::<GridlockDetector>();
+
+    for event in detector.events() {
+        let color = match event.resolution {
+            GridlockResolution::Reroute => LinearColor::ORANGE,
+            GridlockResolution::SkipAhead | GridlockResolution::Respawn => LinearColor::RED,
+        };
+        // Fades out over a few minutes
+        let alpha = (1.0 - event.time.elapsed(&time) as f32 / 300.0).max(0.0);
+        if alpha <= 0.0 {
+            continue;
+        }
+        tess.set_color(color.a(alpha));
+        tess.draw_stroke_circle(event.pos.up(0.5), 5.0 + event.size as f32, 1.0);
+    }
+
+    Some(())
+}
+
 pub fn debug_trainreservations(
     tess: &mut Tesselator,
     goria: &Egregoria,
@@ -1,12 +1,29 @@
 use crate::uiworld::UiWorld;
-use egregoria::economy::{CommodityKind, Market};
+use egregoria::economy::{CommodityKind, Market, Money};
+use egregoria::map::{BuildingKind, LaneKind, ProjectFilter, ProjectKind};
+use egregoria::map_dynamic::{ParkingManagement, ParkingOccupancy, ParkingPlace};
 use egregoria::Egregoria;
+use geom::Camera;
 use imgui::{Condition, Ui};
 
+struct ParkingPriceProperties {
+    price: f32,
+    radius: f32,
+}
+
+impl Default for ParkingPriceProperties {
+    fn default() -> Self {
+        Self {
+            price: 1.0,
+            radius: 200.0,
+        }
+    }
+}
+
 pub fn economy(
     window: imgui::Window<'_, &'static str>,
     ui: &Ui<'_>,
-    _: &mut UiWorld,
+    uiworld: &mut UiWorld,
     goria: &Egregoria,
 ) {
     let market = goria.read::<Market>();
@@ -74,5 +91,68 @@ pub fn economy(
                 ui.text(format!("{}", tot_capital));
                 ui.next_column();
             }
+
+            ui.columns(1, "", false);
+            ui.separator();
+            parking(ui, uiworld, goria);
         });
 }
+
+fn parking(ui: &Ui<'_>, uiworld: &mut UiWorld, goria: &Egregoria) {
+    let pm = goria.read::<ParkingManagement>();
+    ui.text(format!("Parking revenue: {}", pm.revenue));
+    ui.text(format!("Reserved spots: {}", pm.n_reserved()));
+    drop(pm);
+
+    let history = goria.read::<ParkingOccupancy>().total_history();
+    imgui::PlotLines::new(ui, "occupancy", &history)
+        .scale_min(0.0)
+        .scale_max(1.0)
+        .graph_size([0.0, 60.0])
+        .build();
+
+    uiworld.check_present(ParkingPriceProperties::default);
+    let mut state = uiworld.write::<ParkingPriceProperties>();
+
+    imgui::Drag::new("parking price")
+        .range(0.0, 100.0)
+        .speed(0.1)
+        .display_format("%.2f")
+        .build(ui, &mut state.price);
+    imgui::Drag::new("around the camera")
+        .range(10.0, 2000.0)
+        .speed(10.0)
+        .display_format("%.0f m")
+        .build(ui, &mut state.radius);
+
+    if ui.small_button("set parking price") {
+        let center = uiworld.read::<Camera>().pos.xy();
+        let price = Money::new_cents((state.price * 100.0).round() as i64);
+
+        let map = goria.map();
+        let mut commands = uiworld.commands();
+        for kind in map.spatial_map().query_around(
+            center,
+            state.radius,
+            ProjectFilter::ROAD | ProjectFilter::BUILDING,
+        ) {
+            match kind {
+                ProjectKind::Road(id) => {
+                    let road = unwrap_cont!(map.roads().get(id));
+                    for (lane, kind) in road.lanes_iter() {
+                        if kind == LaneKind::Parking {
+                            commands.set_parking_price(ParkingPlace::Lane(lane), price);
+                        }
+                    }
+                }
+                ProjectKind::Building(id) => {
+                    let b = unwrap_cont!(map.buildings().get(id));
+                    if matches!(b.kind, BuildingKind::Parking) {
+                        commands.set_parking_price(ParkingPlace::Building(id), price);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use crate::network::NetworkState;
 use crate::uiworld::UiWorld;
+use egregoria::map::procgen::{parse_osm, CityParams, OsmBBox};
+use egregoria::map::{GeoJsonMap, Heightmap, HeightmapScale};
 use egregoria::pedestrians::Pedestrian;
 use egregoria::vehicles::Vehicle;
 use egregoria::Egregoria;
 use geom::Camera;
 use imgui::Ui;
+use std::path::Path;
 
 #[derive(Clone)]
 struct TestFieldProperties {
@@ -12,6 +15,75 @@ struct TestFieldProperties {
     spacing: f32,
 }
 
+struct CityProperties {
+    seed: u32,
+    size: f32,
+    density: f32,
+}
+
+struct HeightmapProperties {
+    path: String,
+    w_chunks: u32,
+    h_chunks: u32,
+    scale: HeightmapScale,
+    status: String,
+}
+
+struct OsmProperties {
+    path: String,
+    bbox: OsmBBox,
+    buildings: bool,
+    status: String,
+}
+
+struct GeoJsonProperties {
+    path: String,
+    status: String,
+}
+
+/// Reads a grayscale png or a headerless little-endian 16-bit raw file
+fn read_heightmap(path: &str, w_chunks: u32, h_chunks: u32) -> Result<Heightmap, String> {
+    if is_png(path) {
+        let img = image::open(path).map_err(|e| e.to_string())?.into_luma16();
+        let (width, height) = img.dimensions();
+        return Ok(Heightmap {
+            width,
+            height,
+            data: img.into_raw(),
+        });
+    }
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let (width, height) = Heightmap::raw16_dims(bytes.len(), w_chunks, h_chunks)
+        .ok_or("couldn't guess the dimensions of the raw file")?;
+    Heightmap::from_raw16(&bytes, width, height).ok_or_else(|| "invalid raw file".to_string())
+}
+
+fn write_heightmap(path: &str, hm: Heightmap) -> Result<(), String> {
+    if is_png(path) {
+        let img = image::ImageBuffer::<image::Luma<u16>, _>::from_raw(hm.width, hm.height, hm.data)
+            .ok_or("invalid heightmap dimensions")?;
+        return img.save(path).map_err(|e| e.to_string());
+    }
+    std::fs::write(path, hm.to_raw16()).map_err(|e| e.to_string())
+}
+
+/// Scale saved next to the heightmap when it was exported, if any
+fn read_heightmap_scale(path: &str) -> Option<HeightmapScale> {
+    let s = std::fs::read_to_string(HeightmapScale::sidecar_path(path)).ok()?;
+    HeightmapScale::from_sidecar(&s)
+}
+
+fn write_heightmap_scale(path: &str, scale: HeightmapScale) -> Result<(), String> {
+    std::fs::write(HeightmapScale::sidecar_path(path), scale.to_sidecar())
+        .map_err(|e| e.to_string())
+}
+
+fn is_png(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+}
+
 pub fn map(
     window: imgui::Window<'_, &'static str>,
     ui: &Ui<'_>,
@@ -43,14 +115,158 @@ pub fn map(
             );
         }
 
-        if matches!(
+        drop(state);
+
+        ui.separator();
+        uiworld.check_present(CityProperties::default);
+        let mut cstate = uiworld.write::<CityProperties>();
+
+        imgui::Drag::new("city seed").build(ui, &mut cstate.seed);
+        imgui::Drag::new("city radius")
+            .range(400.0, 5000.0)
+            .speed(10.0)
+            .display_format("%.0f m")
+            .build(ui, &mut cstate.size);
+        imgui::Drag::new("city density")
+            .range(0.0, 1.0)
+            .speed(0.01)
+            .display_format("%.2f")
+            .build(ui, &mut cstate.density);
+
+        if ui.small_button("generate city") {
+            uiworld.commands().map_generate_city(
+                uiworld.read::<Camera>().pos.xy(),
+                CityParams {
+                    seed: cstate.seed as u64,
+                    size: cstate.size,
+                    density: cstate.density,
+                },
+            );
+        }
+
+        drop(cstate);
+
+        let singleplayer = matches!(
             *uiworld.read::<NetworkState>(),
             NetworkState::Singleplayer { .. }
-        ) && ui.small_button("reset the save")
-        {
+        );
+
+        if singleplayer && ui.small_button("reset the save") {
             uiworld.commands().reset_save();
         }
 
+        ui.separator();
+        uiworld.check_present(HeightmapProperties::default);
+        let mut hstate = uiworld.write::<HeightmapProperties>();
+
+        ui.input_text("heightmap", &mut hstate.path).build();
+        imgui::Drag::new("width (chunks)")
+            .range(1, 100)
+            .build(ui, &mut hstate.w_chunks);
+        imgui::Drag::new("height (chunks)")
+            .range(1, 100)
+            .build(ui, &mut hstate.h_chunks);
+        imgui::Drag::new("base height")
+            .display_format("%.0f m")
+            .build(ui, &mut hstate.scale.base);
+        imgui::Drag::new("vertical scale")
+            .range(1.0, 5000.0)
+            .display_format("%.0f m")
+            .build(ui, &mut hstate.scale.vertical);
+
+        if singleplayer && ui.small_button("new world from heightmap") {
+            match read_heightmap(&hstate.path, hstate.w_chunks, hstate.h_chunks) {
+                Ok(hm) => {
+                    if let Some(scale) = read_heightmap_scale(&hstate.path) {
+                        hstate.scale = scale;
+                    }
+                    uiworld.commands().map_load_heightmap(
+                        hm,
+                        hstate.w_chunks,
+                        hstate.h_chunks,
+                        hstate.scale,
+                    );
+                    hstate.status = String::new();
+                }
+                Err(e) => hstate.status = format!("couldn't read heightmap: {}", e),
+            }
+        }
+
+        if ui.small_button("export heightmap") {
+            let (hm, scale) = goria.map().terrain.to_heightmap();
+            let written = write_heightmap(&hstate.path, hm)
+                .and_then(|_| write_heightmap_scale(&hstate.path, scale));
+            hstate.status = match written {
+                Ok(()) => format!(
+                    "exported with base {:.0}m and vertical scale {:.0}m, saved in {}",
+                    scale.base,
+                    scale.vertical,
+                    HeightmapScale::sidecar_path(&hstate.path)
+                ),
+                Err(e) => format!("couldn't export heightmap: {}", e),
+            };
+        }
+
+        if !hstate.status.is_empty() {
+            ui.text_wrapped(&hstate.status);
+        }
+
+        drop(hstate);
+
+        ui.separator();
+        uiworld.check_present(OsmProperties::default);
+        let mut ostate = uiworld.write::<OsmProperties>();
+        let ostate = &mut *ostate;
+
+        ui.input_text("osm file", &mut ostate.path).build();
+        for (label, v) in [
+            ("min lat", &mut ostate.bbox.min_lat),
+            ("min lon", &mut ostate.bbox.min_lon),
+            ("max lat", &mut ostate.bbox.max_lat),
+            ("max lon", &mut ostate.bbox.max_lon),
+        ] {
+            imgui::Drag::new(label)
+                .speed(0.0001)
+                .display_format("%.5f")
+                .build(ui, v);
+        }
+        ui.checkbox("import buildings", &mut ostate.buildings);
+
+        if ui.small_button("import osm") {
+            match parse_osm(&ostate.path, ostate.bbox) {
+                Ok(data) => {
+                    uiworld
+                        .commands()
+                        .map_load_osm(data, ostate.bbox, ostate.buildings);
+                    ostate.status = String::new();
+                }
+                Err(e) => ostate.status = format!("couldn't read osm file: {}", e),
+            }
+        }
+
+        if !ostate.status.is_empty() {
+            ui.text_wrapped(&ostate.status);
+        }
+
+        ui.separator();
+        uiworld.check_present(GeoJsonProperties::default);
+        let mut gstate = uiworld.write::<GeoJsonProperties>();
+
+        ui.input_text("geojson file", &mut gstate.path).build();
+        if ui.small_button("export geojson") {
+            let geojson = GeoJsonMap::new(&goria.map());
+            gstate.status = match std::fs::File::create(&gstate.path)
+                .and_then(|f| geojson.write(std::io::BufWriter::new(f)))
+            {
+                Ok(()) => format!("exported {} features", geojson.features.len()),
+                Err(e) => format!("couldn't export geojson: {}", e),
+            };
+        }
+
+        if !gstate.status.is_empty() {
+            ui.text_wrapped(&gstate.status);
+        }
+
         ui.text(format!(
             "{} pedestrians",
             goria.world().query::<&Pedestrian>().iter().count()
@@ -70,3 +286,50 @@ impl Default for TestFieldProperties {
         }
     }
 }
+
+impl Default for CityProperties {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            size: 1500.0,
+            density: 0.6,
+        }
+    }
+}
+
+impl Default for HeightmapProperties {
+    fn default() -> Self {
+        Self {
+            path: "heightmap.png".to_string(),
+            w_chunks: 50,
+            h_chunks: 50,
+            scale: HeightmapScale::default(),
+            status: String::new(),
+        }
+    }
+}
+
+impl Default for OsmProperties {
+    fn default() -> Self {
+        Self {
+            path: "map.osm".to_string(),
+            bbox: OsmBBox {
+                min_lat: 48.85,
+                min_lon: 2.33,
+                max_lat: 48.87,
+                max_lon: 2.36,
+            },
+            buildings: false,
+            status: String::new(),
+        }
+    }
+}
+
+impl Default for GeoJsonProperties {
+    fn default() -> Self {
+        Self {
+            path: "map.geojson".to_string(),
+            status: String::new(),
+        }
+    }
+}
@@ -11,6 +11,7 @@ mod map;
 #[cfg(feature = "multiplayer")]
 pub mod network;
 pub mod settings;
+pub mod transport;
 
 pub trait ImguiWindow: Send + Sync {
     fn render_window(
@@ -58,6 +59,7 @@ impl Default for ImguiWindows {
         };
         s.insert("Map", map::map, true);
         s.insert("Economy", economy::economy, false);
+        s.insert("Transport", transport::transport, false);
         s.insert("Config", config::config, false);
         s.insert("Debug", debug::debug, false);
         s.insert("Settings", settings::settings, false);
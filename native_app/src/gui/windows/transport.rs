@@ -0,0 +1,123 @@
+use crate::rendering::HeatmapMetric;
+use crate::uiworld::UiWorld;
+use egregoria::map_dynamic::{ModalSplit, TrafficStats, TransportMode, TripLog};
+use egregoria::vehicles::gridlock::GridlockDetector;
+use egregoria::Egregoria;
+use imgui::{Condition, Ui};
+
+/// Which measure of the traffic statistics is drawn over the map
+#[derive(Default)]
+pub struct TrafficHeatmap {
+    pub metric: Option<HeatmapMetric>,
+}
+
+struct TripExportProperties {
+    path: String,
+    status: String,
+}
+
+impl Default for TripExportProperties {
+    fn default() -> Self {
+        Self {
+            path: "trips.csv".to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+pub fn transport(
+    window: imgui::Window<'_, &'static str>,
+    ui: &Ui<'_>,
+    uiworld: &mut UiWorld,
+    goria: &Egregoria,
+) {
+    let split = goria.read::<ModalSplit>();
+    let stats = goria.read::<TrafficStats>();
+    let [w, h] = ui.io().display_size;
+
+    window
+        .position([w * 0.5, h * 0.5], Condition::Appearing)
+        .position_pivot([0.5, 0.5])
+        .size([500.0, 300.0], Condition::Appearing)
+        .build(ui, || {
+            ui.text(format!(
+                "Modal split of the last {} trips",
+                split.n_recent()
+            ));
+
+            ui.columns(4, "Modal split", false);
+            ui.text("Mode");
+            ui.next_column();
+            ui.text("Recent share");
+            ui.next_column();
+            ui.text("Total trips");
+            ui.next_column();
+            ui.text("Avg trip last hour");
+            ui.next_column();
+
+            for mode in TransportMode::ALL {
+                ui.text(format!("{:?}", mode));
+                ui.next_column();
+                imgui::ProgressBar::new(split.recent_share(mode))
+                    .size([-1.0, 0.0])
+                    .build(ui);
+                ui.next_column();
+                ui.text(format!("{}", split.total(mode)));
+                ui.next_column();
+                match stats.latest().trips(mode).and_then(|t| t.avg_duration()) {
+                    Some(d) => ui.text(format!("{:.0}s", d)),
+                    None => ui.text("-"),
+                }
+                ui.next_column();
+            }
+            ui.columns(1, "", false);
+
+            ui.separator();
+            let gridlocks = goria.read::<GridlockDetector>();
+            ui.text(format!(
+                "Gridlocks: {} ({} rerouted, {} skipped ahead, {} respawned)",
+                gridlocks.n_gridlocks,
+                gridlocks.n_reroutes,
+                gridlocks.n_skips,
+                gridlocks.n_respawns
+            ));
+
+            ui.separator();
+            ui.text(format!("Heatmap of hour {}", stats.latest().hour));
+            let mut heatmap = uiworld.write::<TrafficHeatmap>();
+            ui.radio_button("none", &mut heatmap.metric, None);
+            ui.same_line();
+            ui.radio_button("vehicles", &mut heatmap.metric, Some(HeatmapMetric::Count));
+            ui.same_line();
+            ui.radio_button("slowdown", &mut heatmap.metric, Some(HeatmapMetric::Speed));
+            ui.same_line();
+            ui.radio_button(
+                "occupancy",
+                &mut heatmap.metric,
+                Some(HeatmapMetric::Occupancy),
+            );
+            ui.same_line();
+            ui.radio_button("delay", &mut heatmap.metric, Some(HeatmapMetric::Delay));
+            drop(heatmap);
+
+            ui.separator();
+            let log = goria.read::<TripLog>();
+            ui.text(format!("{} trips logged", log.len()));
+            uiworld.check_present(TripExportProperties::default);
+            let mut estate = uiworld.write::<TripExportProperties>();
+
+            ui.input_text("trips file", &mut estate.path).build();
+            if ui.small_button("export trips csv") {
+                estate.status = match std::fs::File::create(&estate.path)
+                    .and_then(|f| log.write_csv(std::io::BufWriter::new(f)))
+                {
+                    Ok(()) => format!("exported {} trips", log.len()),
+                    Err(e) => format!("couldn't export trips: {}", e),
+                };
+            }
+
+            if !estate.status.is_empty() {
+                ui.text_wrapped(&estate.status);
+            }
+        });
+}
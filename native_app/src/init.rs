@@ -5,8 +5,10 @@ use crate::gui::lotbrush::LotBrushResource;
 use crate::gui::roadbuild::RoadBuildResource;
 use crate::gui::roadeditor::RoadEditorResource;
 use crate::gui::specialbuilding::SpecialBuildingResource;
+use crate::gui::terraforming::TerraformingResource;
 use crate::gui::windows::debug::{DebugObjs, DebugState};
 use crate::gui::windows::settings::Settings;
+use crate::gui::windows::transport::TrafficHeatmap;
 use crate::gui::{FollowEntity, InspectedEntity, Tool};
 use crate::input::{KeyboardInfo, MouseInfo};
 use crate::network::NetworkState;
@@ -25,6 +27,7 @@ pub fn init() {
     #[cfg(feature = "multiplayer")]
     register_resource::<crate::gui::windows::network::NetworkConnectionInfo>("netinfo");
     register_resource::<LotBrushResource>("lot_brush");
+    register_resource::<TerraformingResource>("terraforming");
 
     register_resource_noserialize::<MouseInfo>();
     register_resource_noserialize::<KeyboardInfo>();
@@ -36,6 +39,7 @@ pub fn init() {
     register_resource_noserialize::<RoadBuildResource>();
     register_resource_noserialize::<DebugState>();
     register_resource_noserialize::<DebugObjs>();
+    register_resource_noserialize::<TrafficHeatmap>();
     register_resource_noserialize::<FollowEntity>();
     register_resource_noserialize::<BulldozerState>();
     register_resource_noserialize::<InspectedEntity>();
@@ -1,7 +1,7 @@
 use egregoria::map_dynamic::Itinerary;
 use egregoria::pedestrians::{Location, Pedestrian};
 use egregoria::vehicles::trains::{Locomotive, RailWagon, RailWagonKind};
-use egregoria::vehicles::{Vehicle, VehicleKind};
+use egregoria::vehicles::{Vehicle, VehicleKind, VehicleState};
 use egregoria::Egregoria;
 use geom::{LinearColor, Transform, Vec3, V3};
 use hecs::With;
@@ -50,6 +50,14 @@ impl InstancedRender {
             match v.kind {
                 VehicleKind::Car => self.cars.instances.push(instance),
                 VehicleKind::Truck => self.trucks.instances.push(instance),
+                // No bike mesh yet, only the rider is drawn
+                VehicleKind::Bicycle if !matches!(v.state, VehicleState::Stored) => {
+                    self.pedestrians.instances.push(MeshInstance {
+                        pos: trans.position.up(1.0),
+                        dir: trans.dir.xy().z0(),
+                        tint: v.tint.into(),
+                    })
+                }
                 _ => {}
             }
         }
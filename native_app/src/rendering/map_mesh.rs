@@ -353,6 +353,7 @@ impl MapBuilders {
         let low_col: LinearColor = common::config().road_low_col.into();
         let mid_col: LinearColor = common::config().road_mid_col.into();
         let hig_col: LinearColor = common::config().road_hig_col.into();
+        let bike_col: LinearColor = common::config().road_bike_col.into();
         let line_col: LinearColor = common::config().road_line_col.into();
 
         let inters = map.intersections();
@@ -405,6 +406,7 @@ impl MapBuilders {
                     match l.kind {
                         LaneKind::Walking => hig_col,
                         LaneKind::Parking => low_col,
+                        LaneKind::Biking => bike_col,
                         _ => mid_col,
                     },
                     l.kind.width() - 0.25,
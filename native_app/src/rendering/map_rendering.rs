@@ -1,12 +1,26 @@
 use crate::rendering::map_mesh::MapMeshHandler;
 use common::FastMap;
 use egregoria::map::{ChunkID, Lane, Map, ProjectFilter, ProjectKind, TrafficBehavior, CHUNK_SIZE};
+use egregoria::map_dynamic::HourStats;
 use egregoria::Egregoria;
-use geom::{vec3, Camera, Color, LinearColor};
+use geom::{vec3, Camera, Color, LinearColor, Vec3};
 use wgpu_engine::meshload::load_mesh;
 use wgpu_engine::{
     FrameContext, GfxContext, InstancedMesh, InstancedMeshBuilder, MeshInstance, Tesselator,
 };
+
+/// Average waiting time at an intersection drawn fully red, in seconds
+const HEATMAP_MAX_DELAY: f32 = 60.0;
+
+/// Measure of the traffic statistics drawn by `RoadRenderer::traffic_heatmap`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeatmapMetric {
+    Count,
+    Speed,
+    Occupancy,
+    Delay,
+}
+
 pub struct RoadRenderer {
     pub meshb: MapMeshHandler,
 
@@ -104,6 +118,43 @@ impl RoadRenderer {
         }
     }
 
+    /// Colors the lanes, or the intersections for the delays, from green to red
+    /// with the measured traffic
+    pub fn traffic_heatmap(
+        map: &Map,
+        hour: &HourStats,
+        metric: HeatmapMetric,
+        tess: &mut Tesselator,
+    ) {
+        let heat = |x: f32| {
+            let x = x.max(0.0).min(1.0);
+            (x * LinearColor::RED + (1.0 - x) * LinearColor::GREEN).a(0.6)
+        };
+
+        if let HeatmapMetric::Delay = metric {
+            for (id, s) in hour.intersections() {
+                let inter = unwrap_cont!(map.intersections().get(id));
+                let delay = unwrap_cont!(s.avg_delay());
+                tess.set_color(heat(delay / HEATMAP_MAX_DELAY));
+                tess.draw_circle(inter.pos.up(0.3), 8.0);
+            }
+            return;
+        }
+
+        let max_count = hour.lanes().map(|(_, s)| s.count).max().unwrap_or(0).max(1);
+        for (id, s) in hour.lanes() {
+            let lane = unwrap_cont!(map.lanes().get(id));
+            let x = match metric {
+                HeatmapMetric::Count => s.count as f32 / max_count as f32,
+                HeatmapMetric::Speed => 1.0 - unwrap_cont!(s.avg_speed()) / lane.speed_limit,
+                HeatmapMetric::Occupancy | HeatmapMetric::Delay => hour.lane_occupancy(id),
+            };
+            tess.set_color(heat(x));
+            let points: Vec<Vec3> = lane.points.iter().map(|p| p.up(0.3)).collect();
+            tess.draw_polyline(&points, 2.0, false);
+        }
+    }
+
     pub fn build_trees(&mut self, map: &Map, ctx: &mut FrameContext<'_>) {
         if map.terrain.dirt_id.0 == self.terrain_dirt_id {
             return;